@@ -0,0 +1,54 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/* A cartridge's save file on disk, `<rom name>.sav` next to the ROM or in a chosen
+ * directory. Saves are written to a temporary file first and then renamed over the old
+ * one, so a crash halfway through a write leaves the previous save intact. */
+pub struct SaveFile {
+    path: PathBuf,
+    // What's on disk now, so an unchanged save isn't rewritten
+    written: Option<Vec<u8>>,
+}
+
+impl SaveFile {
+    pub fn for_rom(rom_path: &Path, save_dir: Option<&Path>) -> Self {
+        let path = match (save_dir, rom_path.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name).with_extension("sav"),
+            _ => rom_path.with_extension("sav"),
+        };
+        SaveFile { path, written: None }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The saved data, or None if there's no save yet
+    pub fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::read(&self.path) {
+            Ok(data) => {
+                self.written = Some(data.clone());
+                Ok(Some(data))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn flush(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.written.as_deref() == Some(data) {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let temp_path = self.path.with_extension("sav.tmp");
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)?;
+        self.written = Some(data.to_vec());
+        Ok(())
+    }
+}
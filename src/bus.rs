@@ -0,0 +1,212 @@
+use crate::cartridge::{Rom, RomError};
+use crate::mapper::{self, Mapper};
+use crate::mixer::Mixer;
+use crate::ppu::Ppu;
+
+/* Everything the CPU sees through its address and data pins.
+ * Reads can have side effects on real hardware (reading a PPU or APU status register
+ * clears flags), so debuggers and the tracer go through `peek` instead. */
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, data: u8);
+
+    // Same value `read` would return, without touching any device state
+    fn peek(&self, addr: u16) -> u8;
+
+    // Called after each instruction with the cycles it took, so devices can keep pace
+    fn tick(&mut self, _cycles: u8) {}
+
+    // State of the shared IRQ line that cartridges and the APU can pull
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Level of the NMI line; the CPU reacts to it going from high to asserted
+    fn nmi(&self) -> bool {
+        false
+    }
+}
+
+/* 64 KiB of plain RAM with nothing mapped into it, used by the sandbox and tests */
+pub struct FlatMemory {
+    memory: [u8; 0x10000],
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory { memory: [0; 0x10000] }
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+}
+
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// |               |       |               |
+// |_______________| $8000 |               |
+// | PRG-RAM       |       |               |
+// |_______________| $6000 | Cartridge     |
+// | Expansion ROM |       |               |
+// |_______________| $4020 |_______________|
+// | APU / IO      |       |               |
+// |_______________| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_______________| $2008 |               |
+// | PPU registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_______________| $0800 |               |
+// | RAM           |       | RAM           |
+// |_______________| $0000 |_______________|
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_IO_REGISTERS: u16 = 0x4000;
+const APU_IO_REGISTERS_END: u16 = 0x401F;
+const OAM_DMA: u16 = 0x4014;
+const CARTRIDGE: u16 = 0x4020;
+
+/* The CPU side of the NES: 2 KiB of internal RAM, the PPU and APU/IO registers and
+ * whatever the cartridge board puts in $4020-$FFFF. */
+pub struct NesBus {
+    cpu_vram: [u8; 2048],
+    pub ppu: Ppu,
+    // Until the APU exists its registers just hold the last value written
+    apu_io_registers: [u8; 0x20],
+    pub mapper: Box<dyn Mapper>,
+    // The header's battery bit: whether the board's PRG-RAM survives power-off
+    battery: bool,
+    // Only there once something wants to play the sound, see enable_audio()
+    mixer: Option<Mixer>,
+}
+
+impl NesBus {
+    pub fn new(rom: Rom) -> Result<Self, RomError> {
+        Ok(NesBus {
+            cpu_vram: [0; 2048],
+            ppu: Ppu::new(),
+            apu_io_registers: [0; 0x20],
+            battery: rom.battery,
+            mapper: mapper::for_rom(rom)?,
+            mixer: None,
+        })
+    }
+
+    // Starts turning the console's sound into `sample_rate` samples for take_audio()
+    pub fn enable_audio(&mut self, sample_rate: u32) {
+        self.mixer = Some(Mixer::new(sample_rate));
+    }
+
+    // Samples produced since the last call; always empty until enable_audio()
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.mixer.as_mut().map_or_else(Vec::new, Mixer::take_samples)
+    }
+
+    /* What belongs in the cartridge's save file: whatever the board keeps itself, or
+     * else its PRG-RAM when there's a battery behind it. None if nothing is saved. */
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.mapper.battery_data().or_else(|| {
+            let prg_ram = self.mapper.prg_ram();
+            (self.battery && !prg_ram.is_empty()).then(|| prg_ram.to_vec())
+        })
+    }
+
+    // Restores a save_data() snapshot; one of the wrong size is ignored
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        if self.mapper.battery_data().is_some() {
+            self.mapper.load_battery_data(data);
+        } else if self.battery && self.mapper.prg_ram().len() == data.len() {
+            self.mapper.prg_ram_mut().copy_from_slice(data);
+        }
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.read_register(addr, self.mapper.as_mut())
+            }
+            CARTRIDGE..=0xFFFF => self.mapper.cpu_read(addr),
+            _ => self.peek(addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize] = data;
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.write_register(addr, data, self.mapper.as_mut());
+                self.mapper.ppu_register_write(addr & 0x2007, data);
+            }
+            OAM_DMA => {
+                // Copies a whole CPU page into OAM, starting at OAMADDR. The CPU is
+                // halted for 513 cycles meanwhile on the real console; that stall isn't
+                // modelled yet.
+                self.apu_io_registers[(addr - APU_IO_REGISTERS) as usize] = data;
+                let page = (data as u16) << 8;
+                for offset in 0..=0xFF {
+                    let byte = self.read(page | offset);
+                    self.ppu.write_oam(byte);
+                }
+            }
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
+                self.apu_io_registers[(addr - APU_IO_REGISTERS) as usize] = data;
+            }
+            CARTRIDGE..=0xFFFF => self.mapper.cpu_write(addr, data),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => self.ppu.peek_register(addr),
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
+                self.apu_io_registers[(addr - APU_IO_REGISTERS) as usize]
+            }
+            CARTRIDGE..=0xFFFF => self.mapper.cpu_peek(addr),
+        }
+    }
+
+    // The NTSC PPU runs three dots per CPU cycle
+    fn tick(&mut self, cycles: u8) {
+        self.mapper.cpu_clock(cycles);
+        if let Some(mixer) = &mut self.mixer {
+            // The 2A03's own channels join the cartridge's here once the APU exists
+            mixer.clock(self.mapper.audio_output(), cycles);
+        }
+        self.ppu.tick(cycles as u16 * 3, self.mapper.as_mut());
+    }
+
+    fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    fn nmi(&self) -> bool {
+        self.ppu.nmi()
+    }
+}
@@ -0,0 +1,251 @@
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    /* All four nametables show the first or second page of the PPU's VRAM,
+     * switchable at runtime on boards like MMC1 and AxROM */
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    INes,
+    Nes2,
+}
+
+/* CPU/PPU timing the game was made for */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    /* Runs on either, usually by checking the frame length at boot */
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    /* Arcade board; the PPU variant matters for its palette */
+    VsSystem { ppu_type: u8, hardware_type: u8 },
+    PlayChoice10,
+    /* NES 2.0 extended console type from byte 13, e.g. a famiclone with decimal mode */
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    /* The file doesn't start with "NES<EOF>" */
+    NotINes,
+    /* The header promises more data than the file holds */
+    Truncated { expected: usize, actual: usize },
+    /* Every NES game has at least one 16 KiB PRG bank */
+    NoPrgRom,
+    /* Valid file for a board we can't emulate */
+    UnsupportedMapper(u16),
+}
+
+impl std::fmt::Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::NotINes => write!(f, "file is not in iNES format"),
+            RomError::Truncated { expected, actual } => {
+                write!(f, "file is truncated: expected {} bytes, found {}", expected, actual)
+            }
+            RomError::NoPrgRom => write!(f, "header declares no PRG-ROM"),
+            RomError::UnsupportedMapper(number) => write!(f, "mapper {} is not supported", number),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+/* A cartridge dump in iNES or NES 2.0 format.
+ *
+ *  Byte  Contents
+ *  0-3   "NES" followed by MS-DOS end-of-file ($1A)
+ *  4     PRG-ROM size in 16 KiB units
+ *  5     CHR-ROM size in 8 KiB units, 0 means the board has CHR-RAM
+ *  6     Mapper low nibble, four-screen, trainer, battery, vertical mirroring
+ *  7     Mapper high nibble, format version, Vs./PlayChoice flags
+ *  8-15  Unused in iNES 1.0, should be zero
+ *
+ * NES 2.0 is flagged by bits 2-3 of byte 7 being %10 and gives the rest a meaning:
+ *  8     Mapper bits 8-11, submapper
+ *  9     PRG-ROM and CHR-ROM size high nibbles
+ *  10    PRG-RAM and PRG-NVRAM size as 64 << shift
+ *  11    CHR-RAM and CHR-NVRAM size as 64 << shift
+ *  12    CPU/PPU timing
+ *  13    Vs. System PPU and hardware type, or extended console type
+ *  14    Number of miscellaneous ROMs
+ *  15    Default expansion device */
+pub struct Rom {
+    pub format: RomFormat,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u16,
+    /* Board variant within a mapper, 0 when unknown or for iNES files */
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    /* Byte 6 bits 3 and 0 as stored. A few boards give the pair their own meaning that
+     * screen_mirroring can't express, UNROM-512's one-screen mode for one. */
+    pub nametable_flags: u8,
+    /* Battery-backed PRG-RAM at $6000-$7FFF */
+    pub battery: bool,
+    /* 512 bytes the loader copies to $7000-$71FF before the game starts */
+    pub trainer: Option<Vec<u8>>,
+    /* RAM sizes in bytes, split into volatile and battery-backed ("NV") parts.
+     * iNES files don't record them, so the usual 8 KiB defaults are filled in. */
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+}
+
+// NES 2.0 RAM sizes are stored as a shift count, with 0 meaning none at all
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/* NES 2.0 ROM size from the header's LSB byte and MSB nibble. An MSB nibble of $F switches
+ * to exponent-multiplier notation for sizes that aren't a whole number of banks:
+ * the LSB byte is EEEEEEMM and the size is 2^E * (MM * 2 + 1) bytes. */
+fn rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize.checked_shl(exponent).unwrap_or(usize::MAX).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err(RomError::NotINes);
+        }
+
+        let control_1 = raw[6];
+        let mut control_2 = raw[7];
+        let format = if control_2 & 0b1100 == 0b1000 {
+            RomFormat::Nes2
+        } else {
+            RomFormat::INes
+        };
+        // Old dumping tools wrote their name ("DiskDude!") into bytes 7-15. If the tail of
+        // an iNES header isn't blank, byte 7 is garbage too and the mapper number only has
+        // its low nibble.
+        if format == RomFormat::INes && raw[12..16].iter().any(|&byte| byte != 0) {
+            control_2 = 0;
+        }
+        let mut mapper = ((control_2 & 0b1111_0000) | (control_1 >> 4)) as u16;
+
+        let four_screen = control_1 & 0b1000 != 0;
+        let vertical_mirroring = control_1 & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+        let nametable_flags = control_1 & 0b1001;
+        let battery = control_1 & 0b10 != 0;
+
+        let mut console_type = match control_2 & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem { ppu_type: 0, hardware_type: 0 },
+            2 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Extended(0),
+        };
+
+        let prg_rom_size;
+        let chr_rom_size;
+        let mut submapper = 0;
+        let prg_ram_size;
+        let prg_nvram_size;
+        let chr_ram_size;
+        let chr_nvram_size;
+        let timing;
+        match format {
+            RomFormat::Nes2 => {
+                mapper |= ((raw[8] & 0x0F) as u16) << 8;
+                submapper = raw[8] >> 4;
+                prg_rom_size = rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE);
+                chr_rom_size = rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE);
+                prg_ram_size = ram_size(raw[10] & 0x0F);
+                prg_nvram_size = ram_size(raw[10] >> 4);
+                chr_ram_size = ram_size(raw[11] & 0x0F);
+                chr_nvram_size = ram_size(raw[11] >> 4);
+                timing = match raw[12] & 0b11 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+                console_type = match console_type {
+                    ConsoleType::VsSystem { .. } => ConsoleType::VsSystem {
+                        ppu_type: raw[13] & 0x0F,
+                        hardware_type: raw[13] >> 4,
+                    },
+                    ConsoleType::Extended(_) => ConsoleType::Extended(raw[13] & 0x0F),
+                    other => other,
+                };
+            }
+            RomFormat::INes => {
+                prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+                chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+                // Every board is assumed to have 8 KiB of PRG-RAM, kept alive by the
+                // battery if there is one, and CHR-RAM exactly when there's no CHR-ROM
+                prg_ram_size = if battery { 0 } else { 0x2000 };
+                prg_nvram_size = if battery { 0x2000 } else { 0 };
+                chr_ram_size = if chr_rom_size == 0 { 0x2000 } else { 0 };
+                chr_nvram_size = 0;
+                timing = if raw[9] & 0b1 != 0 { Timing::Pal } else { Timing::Ntsc };
+            }
+        }
+        if prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        let has_trainer = control_1 & 0b100 != 0;
+        let trainer_size = if has_trainer { TRAINER_SIZE } else { 0 };
+        let prg_rom_start = HEADER_SIZE + trainer_size;
+        let chr_rom_start = prg_rom_start.saturating_add(prg_rom_size);
+        let expected = chr_rom_start.saturating_add(chr_rom_size);
+        if raw.len() < expected {
+            return Err(RomError::Truncated { expected, actual: raw.len() });
+        }
+
+        Ok(Rom {
+            format,
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..expected].to_vec(),
+            mapper,
+            submapper,
+            screen_mirroring,
+            nametable_flags,
+            battery,
+            trainer: has_trainer.then(|| raw[HEADER_SIZE..prg_rom_start].to_vec()),
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+            timing,
+            console_type,
+        })
+    }
+}
@@ -1,18 +1,84 @@
-use bitflags::Flags;
+use crate::bus::{Bus, FlatMemory};
+use crate::opcodes::{self, Mnemonic};
 
-pub struct CPU {
+pub struct CPU<B: Bus = FlatMemory> {
+   pub variant: CpuVariant,
    pub register_a: u8,
    pub status: CpuFlags,
    pub register_x: u8,
    pub register_y: u8,
    pub stack_pointer: u8,
    pub program_counter: u16,
-   memory: [u8; 0xFFFF]
+   /* Total CPU cycles executed since power on */
+   pub cycles: u64,
+   nmi_line: bool,
+   nmi_pending: bool,
+   irq_line: bool,
+   // Last level seen on the bus's own NMI output, for edge detection
+   bus_nmi_line: bool,
+   /* What to do when the program uses an undocumented opcode */
+   pub illegal_opcode_policy: IllegalOpcodePolicy,
+   /* Set once the CPU has stopped; step() does nothing until the next reset */
+   pub halted: Option<CpuError>,
+   warned_opcodes: [bool; 256],
+   /* Set by the 65C02 WAI instruction until an interrupt line is asserted */
+   waiting: bool,
+   /* Everything the CPU can address: RAM, memory-mapped devices, cartridge */
+   pub bus: B,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuVariant {
+   /* Ricoh 2A03/2A07 inside the NES: an NMOS 6502 with decimal mode cut out */
+   #[default]
+   Nes2A03,
+   /* Stock NMOS 6502 with BCD arithmetic */
+   Nmos6502,
+   /* WDC 65C02: extra instructions, bug fixes and different timing */
+   Wdc65C02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IllegalOpcodePolicy {
+   /* Run undocumented opcodes the way NMOS silicon does, like real games expect */
+   #[default]
+   Emulate,
+   /* Emulate them, but report each opcode on stderr the first time it is seen */
+   Warn,
+   /* Stop before executing one and record a CpuError::IllegalOpcode */
+   Halt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+   /* A KIL/JAM opcode locked up the processor */
+   Jammed { code: u8, addr: u16 },
+   /* An undocumented opcode was hit under IllegalOpcodePolicy::Halt */
+   IllegalOpcode { code: u8, addr: u16 },
+   /* The 65C02 executed STP */
+   Stopped { addr: u16 },
+}
+
+impl std::fmt::Display for CpuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CpuError::Jammed { code, addr } => {
+                write!(f, "CPU jammed by opcode {:#04x} at {:#06x}", code, addr)
+            }
+            CpuError::IllegalOpcode { code, addr } => {
+                write!(f, "unofficial opcode {:#04x} at {:#06x}", code, addr)
+            }
+            CpuError::Stopped { addr } => write!(f, "CPU stopped by STP at {:#06x}", addr),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
+   Accumulator,
    Immediate,
    ZeroPage,
    ZeroPage_X,
@@ -22,6 +88,12 @@ pub enum AddressingMode {
    Absolute_Y,
    Indirect_X,
    Indirect_Y,
+   Indirect,
+   Relative,
+   // 65C02 only
+   ZeroPage_Indirect,
+   Absolute_Indirect_X,
+   ZeroPage_Relative,
    NoneAddressing,
 }
 
@@ -39,107 +111,232 @@ bitflags!{
     }
 
   }
-const STACK:u16 = 0x1000;
+const STACK:u16 = 0x0100;
 const STACK_RESET: u8 = 0xfd;
 
+// OR-ed into A by the unstable XAA/LXA opcodes
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+fn page_crossed(a: u16, b: u16) -> bool {
+    a & 0xFF00 != b & 0xFF00
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new(CpuVariant::default())
+    }
+}
+
 impl CPU {
-   pub fn new() -> Self {
+   /* A CPU with 64 KiB of plain RAM, for the sandbox and tests */
+   pub fn new(variant: CpuVariant) -> Self {
+       CPU::with_bus(FlatMemory::new(), variant)
+   }
+}
+
+impl<B: Bus> CPU<B> {
+   pub fn with_bus(bus: B, variant: CpuVariant) -> Self {
        CPU {
+           variant,
            register_a: 0,
            status: CpuFlags::from_bits_truncate(0b100100),
            program_counter: 0,
            register_x: 0,
            register_y: 0,
            stack_pointer: STACK_RESET,
-           memory: [0; 0xFFFF]
+           cycles: 0,
+           nmi_line: false,
+           nmi_pending: false,
+           irq_line: false,
+           bus_nmi_line: false,
+           illegal_opcode_policy: IllegalOpcodePolicy::default(),
+           halted: None,
+           warned_opcodes: [false; 256],
+           waiting: false,
+           bus,
        }
    }
 
-   fn get_operand_address(& mut self, mode: &AddressingMode) -> u16
+   /* Address before the index register is added, for the modes that can cross a page */
+   fn indexed_base_address(&self, mode: &AddressingMode) -> Option<u16>
+   {
+        let operand = self.program_counter;
+        match mode{
+            AddressingMode::Absolute_X | AddressingMode::Absolute_Y => Some(self.peek_u16(operand)),
+            AddressingMode::Indirect_Y => {
+               let ptr = self.peek(operand);
+               let lo = self.peek(ptr as u16) as u16;
+               let hi = self.peek(ptr.wrapping_add(1) as u16) as u16;
+               Some(hi << 8 | lo)
+           }
+            _ => None,
+        }
+   }
+
+   /* Indexed reads pay an extra cycle when adding the index carries into the high byte */
+   fn operand_crosses_page(&self, mode: &AddressingMode) -> bool
+   {
+        match self.indexed_base_address(mode){
+            Some(base) => page_crossed(base, self.get_absolute_address(mode, self.program_counter)),
+            None => false,
+        }
+   }
+
+   fn get_operand_address(&mut self, mode: &AddressingMode) -> u16
    {
         match mode{
             AddressingMode::Immediate => self.program_counter,
+            _ => self.get_absolute_address(mode, self.program_counter),
+        }
+   }
 
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+   /* Resolves the effective address for the operand bytes starting at `addr`.
+    * Only reads memory, so the tracer can call it on an instruction that hasn't run yet. */
+   pub fn get_absolute_address(&self, mode: &AddressingMode, addr: u16) -> u16
+   {
+        match mode{
+            AddressingMode::ZeroPage => self.peek(addr) as u16,
 
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            AddressingMode::Absolute => self.peek_u16(addr),
 
             AddressingMode::ZeroPage_X => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
+                let pos = self.peek(addr);
+                pos.wrapping_add(self.register_x) as u16
             }
 
             AddressingMode::ZeroPage_Y => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y);
-                addr.into() 
+                let pos = self.peek(addr);
+                pos.wrapping_add(self.register_y) as u16
             }
 
             AddressingMode::Absolute_X => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_x.into());
-                addr
+                let base = self.peek_u16(addr);
+                base.wrapping_add(self.register_x as u16)
             }
 
             AddressingMode::Absolute_Y => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_y.into());
-                addr
+                let base = self.peek_u16(addr);
+                base.wrapping_add(self.register_y as u16)
             }
 
             AddressingMode::Indirect_X => {
-               let base = self.mem_read(self.program_counter);
+               let base = self.peek(addr);
 
-               let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-               let lo = self.mem_read(ptr as u16);
-               let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+               let ptr: u8 = base.wrapping_add(self.register_x);
+               let lo = self.peek(ptr as u16);
+               let hi = self.peek(ptr.wrapping_add(1) as u16);
                (hi as u16) << 8 | (lo as u16)
            }
             AddressingMode::Indirect_Y => {
-               let base = self.mem_read(self.program_counter);
+               let base = self.peek(addr);
 
-               let lo = self.mem_read(base as u16);
-               let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
+               let lo = self.peek(base as u16);
+               let hi = self.peek(base.wrapping_add(1) as u16);
                let deref_base = (hi as u16) << 8 | (lo as u16);
-               let deref = deref_base.wrapping_add(self.register_y as u16);
-               deref
+               deref_base.wrapping_add(self.register_y as u16)
+           }
+
+            /* JMP ($xxxx) on the NMOS 6502 never carries into the high byte of the pointer:
+             * JMP ($10FF) reads the low byte from $10FF and the high byte from $1000.
+             * The 65C02 fixed this. */
+            AddressingMode::Indirect => {
+               let ptr = self.peek_u16(addr);
+               let hi_addr = if self.variant == CpuVariant::Wdc65C02 {
+                   ptr.wrapping_add(1)
+               } else {
+                   (ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF)
+               };
+               let lo = self.peek(ptr) as u16;
+               let hi = self.peek(hi_addr) as u16;
+               hi << 8 | lo
            }
-         
-            AddressingMode::NoneAddressing => {
+
+            AddressingMode::ZeroPage_Indirect => {
+               let base = self.peek(addr);
+               let lo = self.peek(base as u16);
+               let hi = self.peek(base.wrapping_add(1) as u16);
+               (hi as u16) << 8 | (lo as u16)
+           }
+
+            AddressingMode::Absolute_Indirect_X => {
+               let ptr = self.peek_u16(addr).wrapping_add(self.register_x as u16);
+               self.peek_u16(ptr)
+           }
+
+            // BBR/BBS test a zero page byte; their branch offset follows it
+            AddressingMode::ZeroPage_Relative => self.peek(addr) as u16,
+
+            /* Branch offsets are relative to the instruction following the branch */
+            AddressingMode::Relative => {
+               let jump = self.peek(addr) as i8;
+               addr.wrapping_add(1).wrapping_add(jump as u16)
+           }
+
+            _ => {
                panic!("mode {:?} is not supported", mode);
-           }   
+           }
         }
-   } 
+   }
 
-   pub fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+   pub fn mem_read(&mut self, addr: u16) -> u8 {
+        self.bus.read(addr)
     }
 
 
    pub fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+        self.bus.write(addr, data);
     }
 
-    fn mem_read_u16(&mut self, pos: u16) -> u16{
+    pub fn mem_read_u16(&mut self, pos: u16) -> u16{
         let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    /* Reads memory without side effects, e.g. without clearing a device's status flag */
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
+    pub fn peek_u16(&self, pos: u16) -> u16 {
+        let lo = self.peek(pos) as u16;
+        let hi = self.peek(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    /* Final write of a read-modify-write instruction. The NMOS parts write the unmodified
+     * value back the cycle before the result, which memory-mapped registers can see;
+     * the 65C02 only writes once. */
+    fn write_back(&mut self, addr: u16, original: u8, value: u8) {
+        if self.variant != CpuVariant::Wdc65C02 {
+            self.mem_write(addr, original);
+        }
+        self.mem_write(addr, value);
     }
 
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
        let hi = (data >> 8) as u8;
        let lo = (data & 0xff) as u8;
        self.mem_write(pos, lo);
-       self.mem_write(pos + 1, hi);
+       self.mem_write(pos.wrapping_add(1), hi);
     }
 
     pub fn reset(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
+        self.register_y = 0;
         self.status = CpuFlags::from_bits_truncate(0b100100);
         self.stack_pointer = STACK_RESET;
-        self.program_counter = self.mem_read_u16(0xFFFC);
+        self.nmi_pending = false;
+        self.halted = None;
+        self.waiting = false;
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+        // The reset sequence itself takes 7 cycles before the first instruction is fetched
+        self.cycles = 7;
     }
 
 
@@ -148,12 +345,14 @@ impl CPU {
         self.reset();
         self.interpret();
     }
-    
+
     pub fn load(&mut self, program: Vec<u8>){
-        self.memory[0x0600 .. (0x0600 + program.len())].copy_from_slice(&program[..]);
-        self.mem_write_u16(0xFFFc ,0x0600);
+        for (i, byte) in program.iter().enumerate() {
+            self.mem_write(0x0600 + i as u16, *byte);
+        }
+        self.mem_write_u16(RESET_VECTOR, 0x0600);
     }
-   
+
     fn set_register_a(&mut self, value: u8) {
         self.register_a = value;
         self.update_status_flag(self.register_a);
@@ -169,7 +368,7 @@ impl CPU {
         }
 
         // Update Sign Flag (N)
-        if register & 0b1000_0000 != 0 
+        if register & 0b1000_0000 != 0
         {
             self.status.insert(CpuFlags::NEGATIVE);
         } else {
@@ -177,9 +376,9 @@ impl CPU {
         }
    }
 
-    
+
  fn stack_push(&mut self, data: u8){
-   self.mem_write((STACK as u16) + self.stack_pointer as u16, data);
+   self.mem_write(STACK + self.stack_pointer as u16, data);
    self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 
  }
@@ -191,7 +390,7 @@ impl CPU {
  }
     fn stack_pop(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
-        self.mem_read((STACK as u16) + self.stack_pointer as u16)
+        self.mem_read(STACK + self.stack_pointer as u16)
     }
  fn stack_pop_u16(&mut self) -> u16{
     let lo = self.stack_pop() as u16;
@@ -200,21 +399,21 @@ impl CPU {
  }
     // Load value to register A
    fn lda(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
         self.register_a = value;
         self.update_status_flag(self.register_a);
    }
 
    fn ldx(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
         self.register_x = value;
         self.update_status_flag(self.register_x);
    }
- 
+
    fn ldy(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
         self.register_y = value;
         self.update_status_flag(self.register_y);
@@ -236,63 +435,118 @@ impl CPU {
    fn txa(&mut self){
         self.set_register_a(self.register_x);
    }
-   
-   fn branch(&mut self, condition: bool){
-        if condition{
-            let jump: i8 = self.mem_read(self.program_counter) as i8;
-            let jump_addr = self.program_counter.wrapping_add(1).wrapping_add(jump as u16);
-            self.program_counter = jump_addr;
+
+   fn tay(&mut self){
+        self.register_y = self.register_a;
+        self.update_status_flag(self.register_y);
+   }
+
+   fn tya(&mut self){
+        self.set_register_a(self.register_y);
+   }
+
+   // TSX updates Z and N, TXS leaves the flags alone
+   fn tsx(&mut self){
+        self.register_x = self.stack_pointer;
+        self.update_status_flag(self.register_x);
+   }
+
+   fn txs(&mut self){
+        self.stack_pointer = self.register_x;
+   }
+
+   /* Returns the extra cycles spent: 1 for a taken branch, 2 if it also lands on another page */
+   fn branch(&mut self, condition: bool) -> u8{
+        if !condition{
+            return 0;
         }
+        let next_instruction = self.program_counter.wrapping_add(1);
+        self.program_counter = self.get_operand_address(&AddressingMode::Relative);
+        if page_crossed(next_instruction, self.program_counter) {2} else {1}
    }
-    
+
 
    // Arithmetic Shift Left
    fn asl_accumulator(&mut self){
-        let mut value = self.register_a;
-        let carry = value > 128;
-        if carry {self.status.insert(CpuFlags::CARRY);} else {self.status.remove(CpuFlags::CARRY);}
-        value *= 2;
-        self.set_register_a(value as u8);     
+        let value = self.register_a;
+        self.status.set(CpuFlags::CARRY, value & 0b1000_0000 != 0);
+        self.set_register_a(value << 1);
    }
 
    fn asl(&mut self, mode: &AddressingMode) -> u8{
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let mut value = self.mem_read(addr);
-        let carry = value > 128;
-        if carry {self.status.insert(CpuFlags::CARRY);} else {self.status.remove(CpuFlags::CARRY);}
-        value *= 2;
-        self.mem_write(addr, value);
+        let original = value;
+        self.status.set(CpuFlags::CARRY, value & 0b1000_0000 != 0);
+        value <<= 1;
+        self.write_back(addr, original, value);
         self.update_status_flag(value);
         value
     }
 
-   fn lsr_accumulaotor(&mut self){
-        let mut value = self.register_a;
-        let carry = value > 128;
-        if carry {self.status.insert(CpuFlags::CARRY);} else {self.status.remove(CpuFlags::CARRY);}
-        value /= 2;
-        self.set_register_a(value as u8);
+   // Logical Shift Right
+   fn lsr_accumulator(&mut self){
+        let value = self.register_a;
+        self.status.set(CpuFlags::CARRY, value & 1 == 1);
+        self.set_register_a(value >> 1);
+   }
+
+   fn lsr(&mut self, mode: &AddressingMode) -> u8{
+        let addr = self.get_operand_address(mode);
+        let mut value = self.mem_read(addr);
+        let original = value;
+        self.status.set(CpuFlags::CARRY, value & 1 == 1);
+        value >>= 1;
+        self.write_back(addr, original, value);
+        self.update_status_flag(value);
+        value
    }
 
    fn php(&mut self) {
-        let mut flags = self.status.clone();
+        let mut flags = self.status;
         flags.insert(CpuFlags::BREAK);
         flags.insert(CpuFlags::BREAK2);
         self.stack_push(flags.bits());
     }
-    
+
+   // B only exists on the stack copy, bit 5 always reads back as set
+   fn plp(&mut self) {
+        self.status = CpuFlags::from_bits_truncate(self.stack_pop());
+        self.status.remove(CpuFlags::BREAK);
+        self.status.insert(CpuFlags::BREAK2);
+   }
+
+   fn pha(&mut self) {
+        self.stack_push(self.register_a);
+   }
+
+   fn pla(&mut self) {
+        let data = self.stack_pop();
+        self.set_register_a(data);
+   }
+
     // INX (INcrement X)
    fn inx(&mut self){
        self.register_x = self.register_x.wrapping_add(1);
        self.update_status_flag(self.register_x);
    }
-    
+
+   fn iny(&mut self){
+       self.register_y = self.register_y.wrapping_add(1);
+       self.update_status_flag(self.register_y);
+   }
+
    // DEX (DEcrement X)
    fn dex(&mut self){
         self.register_x = self.register_x.wrapping_sub(1);
         self.update_status_flag(self.register_x);
    }
-   
+
+   fn dey(&mut self){
+        self.register_y = self.register_y.wrapping_sub(1);
+        self.update_status_flag(self.register_y);
+   }
+
    fn add_to_register_a(&mut self, data: u8){
         let sum = self.register_a as u16 + data as u16 + (if self.status.contains(CpuFlags::CARRY){1} else {0}) as u16;
         let carry = sum > 255;
@@ -301,24 +555,114 @@ impl CPU {
         if (data ^ result) & (result ^ self.register_a) & 0x80 != 0 {self.status.insert(CpuFlags::OVERFLOW);} else {self.status.remove(CpuFlags::OVERFLOW);}
         self.set_register_a(result);
 
-   } 
+   }
+
+   // The 2A03 ignores the D flag entirely
+   fn decimal_mode(&self) -> bool {
+        self.status.contains(CpuFlags::DECIMAL_MODE) && self.variant != CpuVariant::Nes2A03
+   }
+
+   fn add_with_carry(&mut self, data: u8){
+        if self.decimal_mode() {
+            self.add_decimal(data);
+        } else {
+            self.add_to_register_a(data);
+        }
+   }
+
+   // A - M - (1 - C) is the same as A + !M + C
+   fn subtract_with_carry(&mut self, data: u8){
+        if self.decimal_mode() {
+            self.subtract_decimal(data);
+        } else {
+            self.add_to_register_a(!data);
+        }
+   }
+
+   /* BCD addition as described in Bruce Clark's "Decimal Mode" tutorial. N and V come from
+    * the sum before the high nibble is adjusted; the NMOS part takes Z from the binary sum
+    * while the 65C02 sets N and Z from the final result. */
+   fn add_decimal(&mut self, data: u8){
+        let a = self.register_a as i16;
+        let b = data as i16;
+        let carry = self.status.contains(CpuFlags::CARRY) as i16;
+
+        let mut low = (a & 0x0F) + (b & 0x0F) + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let signed = ((a & 0xF0) as u8 as i8) as i16 + ((b & 0xF0) as u8 as i8) as i16 + low;
+        let mut sum = (a & 0xF0) + (b & 0xF0) + low;
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+
+        let binary = (a + b + carry) as u8;
+        let result = sum as u8;
+        self.register_a = result;
+        self.status.set(CpuFlags::CARRY, sum >= 0x100);
+        self.status.set(CpuFlags::OVERFLOW, !(-128..=127).contains(&signed));
+        if self.variant == CpuVariant::Wdc65C02 {
+            self.update_status_flag(result);
+        } else {
+            self.status.set(CpuFlags::NEGATIVE, signed & 0x80 != 0);
+            self.status.set(CpuFlags::ZERO, binary == 0);
+        }
+   }
+
+   /* BCD subtraction. Flags always follow the binary subtraction, except that the 65C02
+    * sets N and Z from the decimal result. */
+   fn subtract_decimal(&mut self, data: u8){
+        let a = self.register_a as i16;
+        let b = data as i16;
+        let borrow = 1 - self.status.contains(CpuFlags::CARRY) as i16;
+
+        let low = (a & 0x0F) - (b & 0x0F) - borrow;
+        let result = if self.variant == CpuVariant::Wdc65C02 {
+            let mut diff = a - b - borrow;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            if low < 0 {
+                diff -= 0x06;
+            }
+            diff as u8
+        } else {
+            let low = if low < 0 { ((low - 0x06) & 0x0F) - 0x10 } else { low };
+            let mut diff = (a & 0xF0) - (b & 0xF0) + low;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            diff as u8
+        };
+
+        self.add_to_register_a(!data);
+        self.register_a = result;
+        if self.variant == CpuVariant::Wdc65C02 {
+            self.update_status_flag(result);
+        }
+   }
+
    // Add with Carry
    fn adc(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.add_with_carry(value);
+   }
+
+   // Subtract with Carry
+   fn sbc(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
-        self.add_to_register_a(value);
+        self.subtract_with_carry(value);
    }
-   
+
    fn bitwise_and(&mut self, data: u8){
-        let value = self.register_a & data;
-        let zero :bool = value == 0;
-        if zero {self.status.insert(CpuFlags::ZERO);} else {self.status.remove(CpuFlags::ZERO);}
-        let result = value as u8;
-        self.set_register_a(result);
-   } 
+        self.set_register_a(self.register_a & data);
+   }
    // Bitwise AND with accumulator
    fn and(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
         self.bitwise_and(value);
    }
@@ -335,58 +679,93 @@ impl CPU {
         if new_carry{self.status.insert(CpuFlags::CARRY);} else {self.status.remove(CpuFlags::CARRY);}
         self.set_register_a(shifted_value);
    }
-   
+
    fn rol(&mut self, mode: &AddressingMode) -> u8{
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
         let carry = self.status.contains(CpuFlags::CARRY);
         let new_carry = (value & 0b10000000) != 0;
         let shifted_value = (value << 1) | carry as u8;
         if new_carry{self.status.insert(CpuFlags::CARRY);} else {self.status.remove(CpuFlags::CARRY);}
-        self.mem_write(addr, shifted_value);
-        self.update_status_flag(value);
+        self.write_back(addr, value, shifted_value);
+        self.update_status_flag(shifted_value);
+        shifted_value
+   }
+
+   fn ror_accumulator(&mut self){
+        let value = self.register_a;
+        let carry = self.status.contains(CpuFlags::CARRY);
+        let shifted_value = (value >> 1) | ((carry as u8) << 7);
+        self.status.set(CpuFlags::CARRY, value & 1 == 1);
+        self.set_register_a(shifted_value);
+   }
+
+   fn ror(&mut self, mode: &AddressingMode) -> u8{
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        let carry = self.status.contains(CpuFlags::CARRY);
+        let shifted_value = (value >> 1) | ((carry as u8) << 7);
+        self.status.set(CpuFlags::CARRY, value & 1 == 1);
+        self.write_back(addr, value, shifted_value);
+        self.update_status_flag(shifted_value);
         shifted_value
    }
 
    fn sta(&mut self, mode: &AddressingMode){
-       let addr = self.get_operand_address(&mode);
-       self.mem_write(addr, self.register_a); 
+       let addr = self.get_operand_address(mode);
+       self.mem_write(addr, self.register_a);
    }
-   
+
+   fn stx(&mut self, mode: &AddressingMode){
+       let addr = self.get_operand_address(mode);
+       self.mem_write(addr, self.register_x);
+   }
+
+   fn sty(&mut self, mode: &AddressingMode){
+       let addr = self.get_operand_address(mode);
+       self.mem_write(addr, self.register_y);
+   }
+
    fn bit(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
         let result = self.register_a & value;
         // Updating the zero flag based on the result
-        if result == 0{self.status.remove(CpuFlags::ZERO);} else{self.status.insert(CpuFlags::ZERO);}
-        
+        self.status.set(CpuFlags::ZERO, result == 0);
+
+        // The 65C02's BIT #imm has no memory operand to copy N and V from
+        if *mode == AddressingMode::Immediate {
+            return;
+        }
+
         // Updating the Overflow and Negative flags based on the data
         self.status.set(CpuFlags::OVERFLOW, value & 0b01000000 > 0);
         self.status.set(CpuFlags::NEGATIVE, value & 0b10000000 > 0);
-   } 
+   }
 
    fn compare(&mut self, mode: &AddressingMode, compare_with: u8){
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
-        if value <= compare_with{self.status.insert(CpuFlags::CARRY);}
-        else {self.status.remove(CpuFlags::CARRY);}
-        self.update_status_flag(compare_with.wrapping_sub(1));
+        self.status.set(CpuFlags::CARRY, value <= compare_with);
+        self.update_status_flag(compare_with.wrapping_sub(value));
    }
 
-   fn inc_mem(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(&mode);
-        let mut value = self.mem_read(addr);
-        value = value.wrapping_add(1);
-        self.mem_write(addr, value);
+   fn inc_mem(&mut self, mode: &AddressingMode) -> u8 {
+        let addr = self.get_operand_address(mode);
+        let original = self.mem_read(addr);
+        let value = original.wrapping_add(1);
+        self.write_back(addr, original, value);
         self.update_status_flag(value);
+        value
    }
 
-   fn dec_mem(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(&mode);
-        let mut value = self.mem_read(addr);
-        value = value.wrapping_sub(1);
-        self.mem_write(addr, value);
+   fn dec_mem(&mut self, mode: &AddressingMode) -> u8{
+        let addr = self.get_operand_address(mode);
+        let original = self.mem_read(addr);
+        let value = original.wrapping_sub(1);
+        self.write_back(addr, original, value);
         self.update_status_flag(value);
+        value
    }
 
    fn clc (&mut self) {
@@ -395,318 +774,537 @@ impl CPU {
 
    /* Logical Inclusive OR*/
    fn ora(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
         self.set_register_a(value | self.register_a);
    }
 
-   pub fn interpret(&mut self) {
-        self.run_with_callback(|_| {});
-    }
+   /* ---- Unofficial opcodes ---- */
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
-    where
-        F: FnMut(&mut CPU),
-    {
-   //pub fn interpret(&mut self) {
-    loop {
-        let opscode = self.mem_read(self.program_counter);
-        self.program_counter += 1;
-        let program_counter_state = self.program_counter;
-        println!("{}", opscode);
-        match opscode {
-        0xA9 => {
-            self.lda(&AddressingMode::Immediate);
-            self.program_counter += 1;
-        }
+   // LAX: LDA and LDX in one go
+   fn lax(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.set_register_a(value);
+        self.register_x = self.register_a;
+   }
 
-        0xA5 => {
-            self.lda(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
-        }
+   // SAX: store A AND X, flags untouched
+   fn sax(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_a & self.register_x);
+   }
 
-        0xAD => {
-            self.lda(&AddressingMode::Absolute);
-            self.program_counter += 1;
-        }
-        
-        0xB5 => {
-            self.lda(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
-        }
+   // DCP: DEC then CMP against the decremented value
+   fn dcp(&mut self, mode: &AddressingMode){
+        let data = self.dec_mem(mode);
+        self.status.set(CpuFlags::CARRY, data <= self.register_a);
+        self.update_status_flag(self.register_a.wrapping_sub(data));
+   }
 
-        0xA2 => {
-            self.ldx(&AddressingMode::Immediate);
-            self.program_counter += 1;
-        }
+   // ISB: INC then SBC with the incremented value
+   fn isb(&mut self, mode: &AddressingMode){
+        let data = self.inc_mem(mode);
+        self.subtract_with_carry(data);
+   }
 
-        0xA6 => {
-            self.ldx(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
-        }
+   // SLO: ASL then ORA
+   fn slo(&mut self, mode: &AddressingMode){
+        let data = self.asl(mode);
+        self.set_register_a(self.register_a | data);
+   }
 
+   // RLA: ROL then AND
+   fn rla(&mut self, mode: &AddressingMode){
+        let data = self.rol(mode);
+        self.set_register_a(self.register_a & data);
+   }
 
-        0xA0 => {
-            self.ldy(&AddressingMode::Immediate);
-            self.program_counter += 1;
-        }
+   // SRE: LSR then EOR
+   fn sre(&mut self, mode: &AddressingMode){
+        let data = self.lsr(mode);
+        self.set_register_a(self.register_a ^ data);
+   }
 
-        0x69 => {
-            self.adc(&AddressingMode::Immediate);
-            self.program_counter += 1;
-        }
+   // RRA: ROR then ADC, the carry out of the rotate feeds the addition
+   fn rra(&mut self, mode: &AddressingMode){
+        let data = self.ror(mode);
+        self.add_with_carry(data);
+   }
 
-        0x65 => {
-            self.adc(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
-        }
+   // ANC: AND #imm, then copy N into C
+   fn anc(&mut self, mode: &AddressingMode){
+        self.and(mode);
+        self.status.set(CpuFlags::CARRY, self.status.contains(CpuFlags::NEGATIVE));
+   }
 
-        0x6D => {
-            self.adc(&AddressingMode::Absolute);
-            self.program_counter += 1;
-        }
-        
-        0x29 => {
-            self.and(&AddressingMode::Immediate);
-            self.program_counter += 1;
-        }
+   // ALR: AND #imm, then LSR A
+   fn alr(&mut self, mode: &AddressingMode){
+        self.and(mode);
+        self.lsr_accumulator();
+   }
 
-        0x35 => {
-            self.and(&AddressingMode::ZeroPage_X);
-            self.program_counter += 1;
-        }
+   // ARR: AND #imm, then ROR A with C taken from bit 6 and V from bit 6 XOR bit 5
+   fn arr(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        let value = self.register_a & self.mem_read(addr);
+        let carry = self.status.contains(CpuFlags::CARRY);
+        let result = (value >> 1) | ((carry as u8) << 7);
+        self.set_register_a(result);
+        let bit6 = result & 0b0100_0000 != 0;
+        let bit5 = result & 0b0010_0000 != 0;
+        self.status.set(CpuFlags::CARRY, bit6);
+        self.status.set(CpuFlags::OVERFLOW, bit6 ^ bit5);
+   }
 
-        0x25 => {
-            self.and(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
-        }
+   // AXS: X = (A AND X) - #imm, setting C like CMP and ignoring the D flag
+   fn axs(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        let and = self.register_a & self.register_x;
+        self.status.set(CpuFlags::CARRY, data <= and);
+        self.register_x = and.wrapping_sub(data);
+        self.update_status_flag(self.register_x);
+   }
 
-        0x2D => {
-            self.and(&AddressingMode::Absolute);
-            self.program_counter += 2;
-        }
-        
-        0x0A => self.asl_accumulator(),
-    
-        0x06 => {
-            self.asl(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
-        }
+   // LAS: A, X and SP all become memory AND SP
+   fn las(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr) & self.stack_pointer;
+        self.stack_pointer = value;
+        self.register_x = value;
+        self.set_register_a(value);
+   }
 
-        0x16 => {
-            self.asl(&AddressingMode::ZeroPage_X);
-            self.program_counter += 1;
-        }        
+   /* XAA and LXA depend on analog effects in the chip. This uses the common
+    * "magic constant" model with $EE, which is what most test ROMs accept. */
+   fn xaa(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        self.set_register_a((self.register_a | UNSTABLE_MAGIC) & self.register_x & data);
+   }
 
+   fn lxa(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        self.set_register_a((self.register_a | UNSTABLE_MAGIC) & data);
+        self.register_x = self.register_a;
+   }
 
-        0x09 => {
-            self.ora(&AddressingMode::Immediate);
-            self.program_counter += 1;
+   /* SHX/SHY/AHX/TAS store `value AND (high byte of the base address + 1)`. When the
+    * index crosses a page the stored value also replaces the high byte of the address. */
+   fn store_unstable(&mut self, mode: &AddressingMode, value: u8){
+        let base = self.indexed_base_address(mode).unwrap_or_default();
+        let mut addr = self.get_operand_address(mode);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        if page_crossed(base, addr) {
+            addr = (addr & 0x00FF) | ((value as u16) << 8);
         }
+        self.mem_write(addr, value);
+   }
 
-        0x0D => {
-            self.ora(&AddressingMode::Absolute);
-            self.program_counter += 2;
-        }
+   // TAS: SP = A AND X, then store it like AHX
+   fn tas(&mut self, mode: &AddressingMode){
+        self.stack_pointer = self.register_a & self.register_x;
+        self.store_unstable(mode, self.stack_pointer);
+   }
 
-        0x15 => {
-            self.ora(&AddressingMode::ZeroPage_X);
-            self.program_counter += 1;
-        }
+   // JAM: the processor stops fetching until reset, PC stays on the opcode
+   fn jam(&mut self, code: u8){
+        let addr = self.program_counter.wrapping_sub(1);
+        self.program_counter = addr;
+        self.halted = Some(CpuError::Jammed { code, addr });
+   }
 
-        0x11 => {
-            self.ora(&AddressingMode::Indirect_Y);
-            self.program_counter += 1;
+   fn warn_unofficial(&mut self, code: u8, addr: u16){
+        if !self.warned_opcodes[code as usize] {
+            self.warned_opcodes[code as usize] = true;
+            eprintln!("warning: {}", CpuError::IllegalOpcode { code, addr });
         }
+   }
 
-        0x19 => {
-            self.ora(&AddressingMode::Absolute_Y);
-            self.program_counter += 2;
-        }
+   /* RTI - Return from Interrupt
+    * Pulls the status (B dropped, bit 5 forced) followed by the program counter. */
+   fn rti(&mut self){
+        self.plp();
+        self.program_counter = self.stack_pop_u16();
+   }
 
-        0x01 => {
-            self.ora(&AddressingMode::Indirect_X);
-            self.program_counter += 1;
+   /* NMI is edge triggered: only the transition to asserted queues an interrupt,
+    * holding the line low does not retrigger it. */
+   pub fn set_nmi(&mut self, asserted: bool){
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
         }
+        self.nmi_line = asserted;
+   }
 
-        0x24 => {
-            self.bit(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
-        }
+   /* IRQ is level triggered: it fires on every instruction boundary while the line is
+    * held and INTERRUPT_DISABLE is clear, until the device acknowledges it. */
+   pub fn set_irq(&mut self, asserted: bool){
+        self.irq_line = asserted;
+   }
 
-        0x10 => {
-            self.branch(!self.status.contains(CpuFlags::NEGATIVE));
-            self.program_counter += 1;
-        }
+   // The line is wired-OR: set_irq() and any device on the bus can pull it low
+   fn irq_asserted(&self) -> bool {
+        self.irq_line || self.bus.irq()
+   }
 
-        /*0xB0 => {
-            self.branch(!self.status.contains(CpuFlags::CARRY));
-        }*/
+   /* Hardware interrupts push the status with B clear so the handler can tell them apart from BRK */
+   fn interrupt(&mut self, vector: u16) -> u8{
+        self.stack_push_u16(self.program_counter);
+        let mut flags = self.status;
+        flags.remove(CpuFlags::BREAK);
+        flags.insert(CpuFlags::BREAK2);
+        self.stack_push(flags.bits());
+        self.enter_interrupt_handler();
+        self.program_counter = self.mem_read_u16(vector);
+        7
+   }
 
-        0xD0 => {
-            self.branch(!self.status.contains(CpuFlags::ZERO));
-            self.program_counter += 1;
+   // The 65C02 also clears D so handlers start in binary mode
+   fn enter_interrupt_handler(&mut self){
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        if self.variant == CpuVariant::Wdc65C02 {
+            self.status.remove(CpuFlags::DECIMAL_MODE);
         }
+   }
 
-        0xB0 => {
-            self.branch(self.status.contains(CpuFlags::CARRY));
-            self.program_counter += 1;
-        }
+   /* BRK - Force Interrupt
+    * Pushes the address of the byte after its padding byte (PC + 2) and the status with B set,
+    * then jumps through the IRQ/BRK vector. */
+   fn brk(&mut self){
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.php();
+        self.enter_interrupt_handler();
+        self.program_counter = self.mem_read_u16(IRQ_VECTOR);
+   }
 
-        0xF0 => {
-            self.branch(self.status.contains(CpuFlags::ZERO));
-            self.program_counter += 1;
-        }
+   /* ---- 65C02 additions ---- */
 
-        0x0E => {
-            self.asl(&AddressingMode::Absolute);
-            self.program_counter += 2;
-        }
+   fn stz(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        self.mem_write(addr, 0);
+   }
 
-        0x1E => {
-            self.asl(&AddressingMode::Absolute_X);
-            self.program_counter += 2;
-        }
+   // TRB/TSB set Z like BIT, then clear/set the bits of A in memory
+   fn trb(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.status.set(CpuFlags::ZERO, value & self.register_a == 0);
+        self.mem_write(addr, value & !self.register_a);
+   }
 
-        0x85 => {
-            self.sta(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
-        }
+   fn tsb(&mut self, mode: &AddressingMode){
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.status.set(CpuFlags::ZERO, value & self.register_a == 0);
+        self.mem_write(addr, value | self.register_a);
+   }
+
+   // RMB/SMB: the bit number is encoded in the high nibble of the opcode
+   fn rmb_smb(&mut self, code: u8, set: bool){
+        let addr = self.get_operand_address(&AddressingMode::ZeroPage);
+        let mask = 1 << ((code >> 4) & 0x07);
+        let value = self.mem_read(addr);
+        self.mem_write(addr, if set { value | mask } else { value & !mask });
+   }
 
-        0x95 => {
-            self.sta(&AddressingMode::ZeroPage_X);
-            self.program_counter += 1;
+   /* BBR/BBS: branch if a zero page bit is clear/set. Same extra cycles as other branches */
+   fn branch_on_bit(&mut self, code: u8, set: bool) -> u8{
+        let addr = self.get_operand_address(&AddressingMode::ZeroPage_Relative);
+        let mask = 1 << ((code >> 4) & 0x07);
+        if (self.mem_read(addr) & mask != 0) != set {
+            return 0;
         }
+        let offset = self.program_counter.wrapping_add(1);
+        self.program_counter = self.get_absolute_address(&AddressingMode::Relative, offset);
+        if page_crossed(offset.wrapping_add(1), self.program_counter) {2} else {1}
+   }
+
+   fn inc_accumulator(&mut self){
+        self.set_register_a(self.register_a.wrapping_add(1));
+   }
+
+   fn dec_accumulator(&mut self){
+        self.set_register_a(self.register_a.wrapping_sub(1));
+   }
+
+   fn phx(&mut self){
+        self.stack_push(self.register_x);
+   }
+
+   fn plx(&mut self){
+        self.register_x = self.stack_pop();
+        self.update_status_flag(self.register_x);
+   }
+
+   fn phy(&mut self){
+        self.stack_push(self.register_y);
+   }
+
+   fn ply(&mut self){
+        self.register_y = self.stack_pop();
+        self.update_status_flag(self.register_y);
+   }
+
+   pub fn interpret(&mut self) {
+        self.run_with_callback(|_| {});
+    }
 
-        0x81 => {
-            self.sta(&AddressingMode::Indirect_X);
-            self.program_counter += 1;
+    /* Sandbox runner: calls back after every instruction and stops when the program
+     * reaches a BRK, without executing it. Use step() to run real programs. */
+    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU<B>),
+    {
+    loop {
+        if self.halted.is_some() {
+            return;
         }
-        
-        0x91 => {
-            self.sta(&AddressingMode::Indirect_Y);
-            self.program_counter += 1;
+        if self.mem_read(self.program_counter) == 0x00 {
+            self.program_counter = self.program_counter.wrapping_add(1);
+            return;
         }
+        self.step();
+        callback(self);
+    }
+   }
 
-        0x8D => {
-            self.sta(&AddressingMode::Absolute);
-            self.program_counter += 2;
+    /* Executes a single instruction, or services a pending interrupt, and returns the
+     * number of cycles it took. A halted CPU does nothing and reports 0 cycles. */
+    pub fn step(&mut self) -> u8 {
+        if self.halted.is_some() {
+            return 0;
         }
-
-        0xE6 => {
-            self.inc_mem(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
+        if self.waiting {
+            if !self.nmi_pending && !self.irq_asserted() {
+                return self.tick(1);
+            }
+            self.waiting = false;
         }
-
-        0xFE => {
-            self.inc_mem(&AddressingMode::Absolute_X);
-            self.program_counter += 2;
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            let cycles = self.interrupt(NMI_VECTOR);
+            return self.tick(cycles);
         }
-
-        0xC6 => {
-            self.dec_mem(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
+        if self.irq_asserted() && !self.status.contains(CpuFlags::INTERRUPT_DISABLE) {
+            let cycles = self.interrupt(IRQ_VECTOR);
+            return self.tick(cycles);
         }
 
-        0x2A => {
-            self.rol_accumulator();
+        let code = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        let opcode = opcodes::opcode_table(self.variant)
+            .get(&code)
+            .unwrap_or_else(|| panic!("Unknown {:#04x} opcode encountered", code));
+        let mode = &opcode.mode;
+
+        if opcode.unofficial {
+            let addr = self.program_counter.wrapping_sub(1);
+            match self.illegal_opcode_policy {
+                IllegalOpcodePolicy::Emulate => {}
+                IllegalOpcodePolicy::Warn => self.warn_unofficial(code, addr),
+                IllegalOpcodePolicy::Halt => {
+                    self.program_counter = addr;
+                    self.halted = Some(CpuError::IllegalOpcode { code, addr });
+                    return 0;
+                }
+            }
         }
 
-        0x26 => {
-            self.rol(&AddressingMode::ZeroPage);
-            self.program_counter += 1;
+        let mut cycles = opcode.cycles;
+        // Taken branches cost at least one extra cycle, so this is non-zero exactly when
+        // a branch wrote PC
+        let mut branch_cycles = 0;
+        if opcode.page_cross_penalty && self.operand_crosses_page(mode) {
+            cycles += 1;
         }
-
-        0x4A => {
-            self.lsr_accumulaotor();
+        // The 65C02 spends an extra cycle fixing up flags in decimal mode
+        if self.variant == CpuVariant::Wdc65C02
+            && matches!(opcode.mnemonic, Mnemonic::ADC | Mnemonic::SBC)
+            && self.decimal_mode()
+        {
+            cycles += 1;
         }
 
-        0x4C => {
-            let address = self.mem_read_u16(self.program_counter);
-            self.program_counter = address;
-            /* The Flags are not affected */
-            self.program_counter += 2;
+        match opcode.mnemonic {
+        Mnemonic::LDA => self.lda(mode),
+        Mnemonic::LDX => self.ldx(mode),
+        Mnemonic::LDY => self.ldy(mode),
+
+        Mnemonic::STA => self.sta(mode),
+        Mnemonic::STX => self.stx(mode),
+        Mnemonic::STY => self.sty(mode),
+
+        Mnemonic::ADC => self.adc(mode),
+        Mnemonic::SBC => self.sbc(mode),
+        Mnemonic::AND => self.and(mode),
+        Mnemonic::EOR => self.eor(mode),
+        Mnemonic::ORA => self.ora(mode),
+        Mnemonic::BIT => self.bit(mode),
+
+        Mnemonic::CMP => self.compare(mode, self.register_a),
+        Mnemonic::CPX => self.compare(mode, self.register_x),
+        Mnemonic::CPY => self.compare(mode, self.register_y),
+
+        Mnemonic::ASL if *mode == AddressingMode::Accumulator => self.asl_accumulator(),
+        Mnemonic::ASL => {
+            self.asl(mode);
         }
 
-        /* JSR - Jump to Subroutine
-         * The JSR instruction pushes the address (minus one) of the return point 
-         * on to the stack and then sets the program counter to the target memory address. */
-        0x20 => {
-            self.stack_push_u16(self.program_counter + 1 );
-            let target_memory_address = self.mem_read_u16(self.program_counter);
-            self.program_counter = target_memory_address;
-            self.program_counter += 1;
+        Mnemonic::LSR if *mode == AddressingMode::Accumulator => self.lsr_accumulator(),
+        Mnemonic::LSR => {
+            self.lsr(mode);
         }
 
-        0xC9 => {
-            self.compare(&AddressingMode::Immediate, self.register_a);
-            self.program_counter += 1 as u16;
+        Mnemonic::ROL if *mode == AddressingMode::Accumulator => self.rol_accumulator(),
+        Mnemonic::ROL => {
+            self.rol(mode);
         }
 
-        0xC5 => {
-            self.compare(&AddressingMode::ZeroPage, self.register_a);
-            self.program_counter += 1;
+        Mnemonic::ROR if *mode == AddressingMode::Accumulator => self.ror_accumulator(),
+        Mnemonic::ROR => {
+            self.ror(mode);
         }
 
-        0xE4 => {
-            self.compare(&AddressingMode::ZeroPage, self.register_x);
-            self.program_counter += 1;
+        Mnemonic::INC if *mode == AddressingMode::Accumulator => self.inc_accumulator(),
+        Mnemonic::INC => {
+            self.inc_mem(mode);
         }
-
-        0x4D =>{
-            self.eor(&AddressingMode::Absolute);
-            self.program_counter += 2;
+        Mnemonic::DEC if *mode == AddressingMode::Accumulator => self.dec_accumulator(),
+        Mnemonic::DEC => {
+            self.dec_mem(mode);
         }
-        
-        /* RTS - Return from sub routine */
-        0x60 => {
-            self.program_counter = self.stack_pop_u16() + 1;
-            // self.program_counter +=1;
+        Mnemonic::INX => self.inx(),
+        Mnemonic::INY => self.iny(),
+        Mnemonic::DEX => self.dex(),
+        Mnemonic::DEY => self.dey(),
+
+        Mnemonic::BPL => branch_cycles = self.branch(!self.status.contains(CpuFlags::NEGATIVE)),
+        Mnemonic::BMI => branch_cycles = self.branch(self.status.contains(CpuFlags::NEGATIVE)),
+        Mnemonic::BVC => branch_cycles = self.branch(!self.status.contains(CpuFlags::OVERFLOW)),
+        Mnemonic::BVS => branch_cycles = self.branch(self.status.contains(CpuFlags::OVERFLOW)),
+        Mnemonic::BCC => branch_cycles = self.branch(!self.status.contains(CpuFlags::CARRY)),
+        Mnemonic::BCS => branch_cycles = self.branch(self.status.contains(CpuFlags::CARRY)),
+        Mnemonic::BNE => branch_cycles = self.branch(!self.status.contains(CpuFlags::ZERO)),
+        Mnemonic::BEQ => branch_cycles = self.branch(self.status.contains(CpuFlags::ZERO)),
+
+        /* JMP - The Flags are not affected */
+        Mnemonic::JMP => {
+            self.program_counter = self.get_operand_address(mode);
         }
 
-       
-        
-        0x08 => {
-            self.php();
+        /* JSR - Jump to Subroutine
+         * The JSR instruction pushes the address (minus one) of the return point
+         * on to the stack and then sets the program counter to the target memory address. */
+        Mnemonic::JSR => {
+            self.stack_push_u16(self.program_counter.wrapping_add(1));
+            let target_memory_address = self.get_operand_address(mode);
+            self.program_counter = target_memory_address;
         }
 
-        0x38 => {
-            self.sec();
-        }
-        0xAA => {
-            self.tax();
+        /* RTS - Return from sub routine */
+        Mnemonic::RTS => {
+            self.program_counter = self.stack_pop_u16().wrapping_add(1);
         }
 
-        0x8A => {
-            self.txa();
-        }
+        Mnemonic::RTI => self.rti(),
 
-        0xE8 => {
-            self.inx();
-        }
+        Mnemonic::PHP => self.php(),
+        Mnemonic::PLP => self.plp(),
+        Mnemonic::PHA => self.pha(),
+        Mnemonic::PLA => self.pla(),
 
-        0x18 => {
-            self.clc();
-        }
-      
+        Mnemonic::CLC => self.clc(),
+        Mnemonic::SEC => self.sec(),
+        Mnemonic::CLI => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
+        Mnemonic::SEI => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
+        Mnemonic::CLV => self.status.remove(CpuFlags::OVERFLOW),
+        Mnemonic::CLD => self.status.remove(CpuFlags::DECIMAL_MODE),
+        Mnemonic::SED => self.status.insert(CpuFlags::DECIMAL_MODE),
+
+        Mnemonic::TAX => self.tax(),
+        Mnemonic::TXA => self.txa(),
+        Mnemonic::TAY => self.tay(),
+        Mnemonic::TYA => self.tya(),
+        Mnemonic::TSX => self.tsx(),
+        Mnemonic::TXS => self.txs(),
 
         /* NOP - No Operation
-         *The NOP instruction causes no changes to the processor other than the normal 
+         *The NOP instruction causes no changes to the processor other than the normal
          incrementing of the program counter to the next instruction.*/
-        0xEA => {
-            self.program_counter += 1;
+        Mnemonic::NOP => {}
+
+        Mnemonic::BRK => self.brk(),
+
+        Mnemonic::LAX => self.lax(mode),
+        Mnemonic::SAX => self.sax(mode),
+        Mnemonic::DCP => self.dcp(mode),
+        Mnemonic::ISB => self.isb(mode),
+        Mnemonic::SLO => self.slo(mode),
+        Mnemonic::RLA => self.rla(mode),
+        Mnemonic::SRE => self.sre(mode),
+        Mnemonic::RRA => self.rra(mode),
+        Mnemonic::ANC => self.anc(mode),
+        Mnemonic::ALR => self.alr(mode),
+        Mnemonic::ARR => self.arr(mode),
+        Mnemonic::AXS => self.axs(mode),
+        Mnemonic::XAA => self.xaa(mode),
+        Mnemonic::LXA => self.lxa(mode),
+        Mnemonic::LAS => self.las(mode),
+        Mnemonic::TAS => self.tas(mode),
+        Mnemonic::AHX => self.store_unstable(mode, self.register_a & self.register_x),
+        Mnemonic::SHX => self.store_unstable(mode, self.register_x),
+        Mnemonic::SHY => self.store_unstable(mode, self.register_y),
+        Mnemonic::JAM => self.jam(code),
+
+        Mnemonic::BRA => branch_cycles = self.branch(true),
+        Mnemonic::BBR => branch_cycles = self.branch_on_bit(code, false),
+        Mnemonic::BBS => branch_cycles = self.branch_on_bit(code, true),
+        Mnemonic::RMB => self.rmb_smb(code, false),
+        Mnemonic::SMB => self.rmb_smb(code, true),
+        Mnemonic::STZ => self.stz(mode),
+        Mnemonic::TRB => self.trb(mode),
+        Mnemonic::TSB => self.tsb(mode),
+        Mnemonic::PHX => self.phx(),
+        Mnemonic::PLX => self.plx(),
+        Mnemonic::PHY => self.phy(),
+        Mnemonic::PLY => self.ply(),
+        Mnemonic::WAI => self.waiting = true,
+        Mnemonic::STP => {
+            self.program_counter = self.program_counter.wrapping_sub(1);
+            self.halted = Some(CpuError::Stopped { addr: self.program_counter });
+        }
         }
 
-        0xCA => {
-            self.dex();
+        // Control flow leaves PC where it sent it, which can be anywhere, the next
+        // byte included; everything else steps over its operands
+        cycles += branch_cycles;
+        let pc_written = branch_cycles > 0
+            || matches!(
+                opcode.mnemonic,
+                Mnemonic::JMP | Mnemonic::JSR | Mnemonic::RTS | Mnemonic::RTI | Mnemonic::BRK
+                    | Mnemonic::JAM | Mnemonic::STP
+            );
+        if !pc_written {
+            self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
         }
 
-        0x00 => return, 
+        self.tick(cycles)
+    }
 
-            _ => panic!("Unknown {} opcode encountered", opscode),
+    /* Adds the cycles just spent to the running count and lets the bus catch up */
+    fn tick(&mut self, cycles: u8) -> u8 {
+        self.cycles += cycles as u64;
+        self.bus.tick(cycles);
+        // Devices like the PPU drive NMI alongside set_nmi(); each edge counts once
+        let bus_nmi = self.bus.nmi();
+        if bus_nmi && !self.bus_nmi_line {
+            self.nmi_pending = true;
         }
-
-        callback(self);
+        self.bus_nmi_line = bus_nmi;
+        cycles
     }
-   }
  }
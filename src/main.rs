@@ -1,14 +1,27 @@
+pub mod battery;
+pub mod bus;
+pub mod cartridge;
 pub mod cpu;
+pub mod mapper;
+pub mod mixer;
+pub mod opcodes;
 pub mod operands;
-use cpu::CPU;
+pub mod ppu;
+pub mod savestate;
+pub mod trace;
+use battery::SaveFile;
+use bus::NesBus;
+use cartridge::Rom;
+use cpu::{CpuVariant, CPU};
+use ppu::{FRAME_HEIGHT, FRAME_WIDTH, SYSTEM_PALETTE};
 use rand::Rng;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::EventPump;
+use sdl2::{EventPump, Sdl};
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
-use std::time::Duration;
-use sdl2::rect::Rect;
+use std::path::{Path, PathBuf};
 
 #[macro_use]
 extern crate lazy_static;
@@ -19,6 +32,20 @@ extern crate bitflags;
 #[cfg(test)]
 mod test;
 
+// The snake demo was written for a slow virtual 6502 rather than a 1.79 MHz NES,
+// so it only gets a small slice of cycles per 60 Hz frame to stay playable.
+const DEMO_CYCLES_PER_FRAME: u64 = 1_000;
+
+// Battery saves are flushed to disk about once per emulated second
+const SAVE_FLUSH_CYCLES: u64 = 1_789_773;
+
+const AUDIO_SAMPLE_RATE: i32 = 44_100;
+
+// About 100 ms of queued mono f32 samples. The emulator is paced by the display rather
+// than the sound card, so the two drift apart; past this much backlog new samples are
+// dropped instead of letting the latency grow.
+const MAX_QUEUED_AUDIO_BYTES: u32 = AUDIO_SAMPLE_RATE as u32 / 10 * 4;
+
 fn color(byte: u8) -> Color {
     match byte {
         0 => sdl2::pixels::Color::BLACK,
@@ -37,7 +64,7 @@ fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     let mut frame_idx = 0;
     let mut update = false;
     for i in 0x0200..0x600 {
-        let color_idx = cpu.mem_read(i as u16);
+        let color_idx = cpu.peek(i as u16);
         let (b1, b2, b3) = color(color_idx).rgb();
         if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
             frame[frame_idx] = b1;
@@ -76,8 +103,174 @@ fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
 }
 
 
+/* Writes the cartridge's save data, if it has any, to its save file */
+fn flush_save(save_file: &mut SaveFile, bus: &NesBus) {
+    if let Some(data) = bus.save_data() {
+        if let Err(err) = save_file.flush(&data) {
+            eprintln!("{}: {}", save_file.path().display(), err);
+        }
+    }
+}
+
+// Whether the window was closed or Escape pressed since the last call
+fn quit_requested(event_pump: &mut EventPump) -> bool {
+    event_pump.poll_iter().any(|event| {
+        matches!(
+            event,
+            Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. }
+        )
+    })
+}
+
+/* Runs the console until the CPU halts or `on_frame`, called with the bus once each
+ * picture is finished, returns false. With `trace` every instruction is logged in the
+ * nestest.log format. Battery saves are flushed every so often and once more at the end. */
+fn emulate(
+    cpu: &mut CPU<NesBus>,
+    trace: bool,
+    save_file: &mut SaveFile,
+    mut on_frame: impl FnMut(&mut NesBus) -> bool,
+) {
+    let mut next_flush = SAVE_FLUSH_CYCLES;
+    let mut frame = cpu.bus.ppu.frame;
+    while cpu.halted.is_none() {
+        if trace {
+            println!("{}", trace::trace(cpu));
+        }
+        cpu.step();
+        if cpu.cycles >= next_flush {
+            flush_save(save_file, &cpu.bus);
+            next_flush = cpu.cycles + SAVE_FLUSH_CYCLES;
+        }
+        if cpu.bus.ppu.frame != frame {
+            frame = cpu.bus.ppu.frame;
+            if !on_frame(&mut cpu.bus) {
+                break;
+            }
+        }
+    }
+    if let Some(err) = &cpu.halted {
+        eprintln!("{}", err);
+    }
+    flush_save(save_file, &cpu.bus);
+}
+
+/* Mono output at the device's rate, fed by the bus's mixer. Without a usable audio
+ * device the game just runs silently. */
+fn open_audio(sdl_context: &Sdl, bus: &mut NesBus) -> Option<AudioQueue<f32>> {
+    let desired = AudioSpecDesired {
+        freq: Some(AUDIO_SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+    match sdl_context.audio().and_then(|audio| audio.open_queue::<f32, _>(None, &desired)) {
+        Ok(queue) => {
+            bus.enable_audio(queue.spec().freq as u32);
+            queue.resume();
+            Some(queue)
+        }
+        Err(err) => {
+            eprintln!("audio: {}", err);
+            None
+        }
+    }
+}
+
+/* Boots a cartridge from disk and shows the PPU's picture in a window, or runs without
+ * one when `headless` (handy together with `--trace`).
+ * Battery saves are read from `<rom>.sav` (in `save_dir` if one is given) at boot. */
+fn run_rom(path: &str, trace: bool, headless: bool, save_dir: Option<&Path>) {
+    let raw = std::fs::read(path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        std::process::exit(1);
+    });
+    let mut bus = Rom::new(&raw).and_then(NesBus::new).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        std::process::exit(1);
+    });
+
+    let mut save_file = SaveFile::for_rom(Path::new(path), save_dir);
+    if bus.save_data().is_some() {
+        match save_file.load() {
+            Ok(Some(data)) => bus.load_save_data(&data),
+            Ok(None) => {}
+            Err(err) => eprintln!("{}: {}", save_file.path().display(), err),
+        }
+    }
+
+    let mut cpu = CPU::with_bus(bus, CpuVariant::Nes2A03);
+    cpu.reset();
+    if headless {
+        emulate(&mut cpu, trace, &mut save_file, |_| true);
+        return;
+    }
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(path, (FRAME_WIDTH * 3) as u32, (FRAME_HEIGHT * 3) as u32)
+        .position_centered()
+        .build().unwrap();
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(3.0, 3.0).unwrap();
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, FRAME_WIDTH as u32, FRAME_HEIGHT as u32)
+        .unwrap();
+
+    let audio = open_audio(&sdl_context, &mut cpu.bus);
+
+    let mut rgb = vec![0_u8; FRAME_WIDTH * FRAME_HEIGHT * 3];
+    emulate(&mut cpu, trace, &mut save_file, |bus| {
+        let samples = bus.take_audio();
+        if let Some(queue) = &audio {
+            if queue.size() < MAX_QUEUED_AUDIO_BYTES {
+                if let Err(err) = queue.queue_audio(&samples) {
+                    eprintln!("audio: {}", err);
+                }
+            }
+        }
+
+        for (pixel, &colour) in rgb.chunks_exact_mut(3).zip(&bus.ppu.frame_buffer) {
+            let (r, g, b) = SYSTEM_PALETTE[(colour & 0x3F) as usize];
+            pixel.copy_from_slice(&[r, g, b]);
+        }
+        texture.update(None, &rgb, FRAME_WIDTH * 3).unwrap();
+
+        // present_vsync blocks until the next refresh, which paces the emulated clock
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+        !quit_requested(&mut event_pump)
+    });
+}
+
+fn usage() -> ! {
+    eprintln!("usage: nes_emulator [--trace] [--headless] [--save-dir <dir>] [rom]");
+    std::process::exit(1);
+}
 
 pub fn main(){
+    let mut trace = false;
+    let mut headless = false;
+    let mut save_dir = None;
+    let mut rom_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = true,
+            "--headless" => headless = true,
+            "--save-dir" => save_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ if arg.starts_with("--") => usage(),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => usage(),
+        }
+    }
+    if let Some(path) = rom_path {
+        run_rom(&path, trace, headless, save_dir.as_deref());
+        return;
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
@@ -121,24 +314,29 @@ pub fn main(){
 
 
     //load the game
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(CpuVariant::Nmos6502);
     cpu.load(game_code);
     cpu.reset();
 
-    let mut screen_state = [0 as u8; 32 * 3 * 32];
+    let mut screen_state = [0_u8; 32 * 3 * 32];
     let mut rng = rand::thread_rng();
     // run the game cycle
+    let mut frame_start = cpu.cycles;
     cpu.run_with_callback(move |cpu| {
-        handle_user_input(cpu, &mut event_pump);
         cpu.mem_write(0xfe, rng.gen_range(1..16));
 
+        if cpu.cycles - frame_start < DEMO_CYCLES_PER_FRAME {
+            return;
+        }
+        frame_start = cpu.cycles;
+
+        handle_user_input(cpu, &mut event_pump);
         if read_screen_state(cpu, &mut screen_state) {
             texture.update(None, &screen_state, 32 * 3).unwrap();
-            canvas.copy(&texture, None, None).unwrap();
-
-            canvas.present();
         }
 
-        ::std::thread::sleep(std::time::Duration::new(0, 70_000));
+        // present_vsync blocks until the next refresh, which paces the emulated clock
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
     });
 }
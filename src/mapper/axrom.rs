@@ -0,0 +1,86 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/* Mapper 7: AxROM (AMROM, ANROM, AOROM).
+ * Writes to $8000-$FFFF: ---M-PPP, a 32 KiB PRG bank and which page of the PPU's VRAM
+ * all four nametables show. CHR is 8 KiB of RAM.
+ * Only ANROM has bus conflicts and most games don't expect them, so they're emulated
+ * only when the header asks for them (submapper 2). */
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    bus_conflicts: bool,
+    register: u8,
+}
+
+impl Axrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Axrom {
+            bus_conflicts: rom.submapper == 2,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            register: 0,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank = (self.register & 0x0F) as usize;
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, mut data: u8) {
+        if addr >= 0x8000 {
+            if self.bus_conflicts {
+                data &= self.cpu_peek(addr);
+            }
+            self.register = data;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.register & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u8()?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::eeprom::{EepromChip, I2cEeprom};
+use crate::mapper::{bank_offset, chr_memory, last_bank, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// Mapper 153 switches between two 256 KiB halves of its 512 KiB PRG
+const OUTER_BANK_SIZE: usize = 0x40000;
+
+/* Mappers 16, 153 and 159: Bandai FCG-1/2 and LZ93D50 boards.
+ *
+ * Registers repeat every 16 bytes, at $6000-$7FFF on the FCG chips and $8000-$FFFF on
+ * the LZ93D50; iNES mapper 16 files don't say which, so both ranges work for them.
+ *  $x0-$x7  1 KiB CHR banks (mapper 153: bit 0 picks the 256 KiB PRG half)
+ *  $x8      16 KiB PRG bank at $8000 ($C000 holds the last bank)
+ *  $x9      mirroring  0: vertical, 1: horizontal, 2/3: single screen lower/upper
+ *  $xA      IRQ enable (bit 0); acknowledges the IRQ
+ *  $xB/$xC  IRQ counter low/high byte
+ *  $xD      RDC- ----  EEPROM read enable, SDA, SCL (mapper 153: bit 5 enables PRG-RAM)
+ *
+ * The IRQ counter counts down every CPU cycle while enabled and fires on reaching 0.
+ * On the FCG, $xB/$xC write the counter itself; on the LZ93D50 they write a latch that
+ * $xA copies into the counter.
+ *
+ * Saves go to a serial EEPROM: a 24C02 on mapper 16, an X24C01 on mapper 159, read back
+ * in bit 4 of $6000-$7FFF. Mapper 153 has plain battery-backed PRG-RAM instead. */
+pub struct Bandai {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    battery: bool,
+    // Which register windows the board decodes
+    fcg_registers: bool,
+    lz93d50_registers: bool,
+    // Mapper 153's 256 KiB PRG half, set by bit 0 of whichever CHR register was
+    // written last
+    outer_prg_bank: Option<u8>,
+    chr_banks: [u8; 8],
+    prg_bank: u8,
+    mirroring: u8,
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+    irq_pending: bool,
+    control: u8,
+    eeprom: Option<I2cEeprom>,
+}
+
+impl Bandai {
+    pub fn new(mut rom: Rom) -> Self {
+        let (fcg_registers, lz93d50_registers) = match (rom.mapper, rom.submapper) {
+            (16, 4) => (true, false),
+            (16, 5) | (153, _) | (159, _) => (false, true),
+            _ => (true, true),
+        };
+        let eeprom = match rom.mapper {
+            16 if rom.submapper != 4 => Some(I2cEeprom::new(EepromChip::C02)),
+            159 => Some(I2cEeprom::new(EepromChip::C01)),
+            _ => None,
+        };
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Bandai {
+            prg_ram: if rom.mapper == 153 { prg_ram(&rom) } else { Vec::new() },
+            outer_prg_bank: if rom.mapper == 153 { Some(0) } else { None },
+            battery: rom.battery,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            fcg_registers,
+            lz93d50_registers,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            mirroring: 0,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+            control: 0,
+            eeprom,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let len = self.prg_rom.len();
+        let (outer, inner_len) = match self.outer_prg_bank {
+            Some(bank) => (bank as usize * OUTER_BANK_SIZE, len.min(OUTER_BANK_SIZE)),
+            None => (0, len),
+        };
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize,
+            _ => last_bank(inner_len, PRG_BANK_SIZE),
+        };
+        (outer + bank_offset(inner_len, bank, PRG_BANK_SIZE, addr)) % len
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        // Mapper 153 has a single unbanked 8 KiB of CHR-RAM
+        if self.outer_prg_bank.is_some() {
+            return addr as usize % self.chr.len();
+        }
+        let bank = self.chr_banks[(addr >> 10) as usize & 7];
+        bank_offset(self.chr.len(), bank as usize, CHR_BANK_SIZE, addr)
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & 0x20 != 0
+    }
+
+    fn write_register(&mut self, register: u16, data: u8, latched: bool) {
+        match register {
+            0x0..=0x7 => {
+                self.chr_banks[register as usize] = data;
+                if let Some(bank) = &mut self.outer_prg_bank {
+                    *bank = data & 1;
+                }
+            }
+            0x8 => self.prg_bank = data & 0x0F,
+            0x9 => self.mirroring = data & 0b11,
+            0xA => {
+                self.irq_enabled = data & 1 != 0;
+                self.irq_pending = false;
+                if latched {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB | 0xC => {
+                let shift = (register - 0xB) * 8;
+                let target = if latched { &mut self.irq_latch } else { &mut self.irq_counter };
+                *target = (*target & !(0xFF << shift)) | (data as u16) << shift;
+            }
+            0xD => {
+                self.control = data;
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write_lines(data & 0x20 != 0, data & 0x40 != 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Bandai {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x6000..=0x7FFF => match &self.eeprom {
+                Some(eeprom) if self.control & 0x80 != 0 => (eeprom.output() as u8) << 4,
+                _ => 0,
+            },
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x6000..=0x7FFF if self.fcg_registers => self.write_register(addr & 0xF, data, false),
+            0x8000..=0xFFFF if self.lz93d50_registers => {
+                self.write_register(addr & 0xF, data, true)
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_clock(&mut self, cycles: u8) {
+        if !self.irq_enabled {
+            return;
+        }
+        for _ in 0..cycles {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    // The EEPROM keeps its contents without a battery; PRG-RAM needs one
+    fn battery_data(&self) -> Option<Vec<u8>> {
+        match &self.eeprom {
+            Some(eeprom) => Some(eeprom.data.clone()),
+            None if self.battery && !self.prg_ram.is_empty() => Some(self.prg_ram.clone()),
+            None => None,
+        }
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        let target = match &mut self.eeprom {
+            Some(eeprom) => &mut eeprom.data,
+            None => &mut self.prg_ram,
+        };
+        if target.len() == data.len() {
+            target.copy_from_slice(data);
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for bank in self.chr_banks {
+            state.write_u8(bank);
+        }
+        state.write_u8(self.prg_bank);
+        state.write_u8(self.mirroring);
+        state.write_bool(self.irq_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_u16(self.irq_latch);
+        state.write_bool(self.irq_pending);
+        state.write_u8(self.control);
+        if let Some(bank) = self.outer_prg_bank {
+            state.write_u8(bank);
+        }
+        if let Some(eeprom) = &self.eeprom {
+            eeprom.save_state(state);
+        }
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        self.prg_bank = state.read_u8()?;
+        self.mirroring = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_latch = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+        self.control = state.read_u8()?;
+        if let Some(bank) = &mut self.outer_prg_bank {
+            *bank = state.read_u8()? & 1;
+        }
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.load_state(state)?;
+        }
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/* Mapper 34 covers two unrelated boards.
+ *
+ * BNROM (submapper 2): any write to $8000-$FFFF picks a 32 KiB PRG bank, with bus
+ * conflicts. CHR is 8 KiB of RAM.
+ *
+ * NINA-001 (submapper 1): 8 KiB of PRG-RAM at $6000, with registers on top of its last
+ * three bytes that are written through to the RAM as well:
+ *  $7FFD  32 KiB PRG bank
+ *  $7FFE  4 KiB CHR bank at PPU $0000
+ *  $7FFF  4 KiB CHR bank at PPU $1000
+ *
+ * iNES files don't say which one they are, but only NINA-001 has more than 8 KiB of CHR. */
+pub struct Bnrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    nina_001: bool,
+    prg_bank: u8,
+    chr_banks: [u8; 2],
+}
+
+impl Bnrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let nina_001 = match rom.submapper {
+            1 => true,
+            2 => false,
+            _ => rom.chr_rom.len() > 0x2000,
+        };
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Bnrom {
+            prg_ram: if nina_001 { prg_ram(&rom) } else { Vec::new() },
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            mirroring: rom.screen_mirroring,
+            nina_001,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 12) as usize & 1];
+        bank_offset(self.chr.len(), bank as usize, CHR_BANK_SIZE, addr)
+    }
+}
+
+impl Mapper for Bnrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => {
+                let bank = self.prg_bank as usize;
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.nina_001 => {
+                match addr {
+                    0x7FFD => self.prg_bank = data,
+                    0x7FFE => self.chr_banks[0] = data,
+                    0x7FFF => self.chr_banks[1] = data,
+                    _ => {}
+                }
+                if !self.prg_ram.is_empty() {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[(addr - 0x6000) as usize % len] = data;
+                }
+            }
+            0x8000..=0xFFFF if !self.nina_001 => {
+                self.prg_bank = data & self.cpu_peek(addr);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_bank);
+        state.write_u8(self.chr_banks[0]);
+        state.write_u8(self.chr_banks[1]);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read_u8()?;
+        self.chr_banks[0] = state.read_u8()?;
+        self.chr_banks[1] = state.read_u8()?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
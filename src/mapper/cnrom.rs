@@ -0,0 +1,83 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/* Mapper 3: CNROM.
+ * PRG-ROM is fixed like NROM; any write to $8000-$FFFF picks the 8 KiB CHR-ROM bank.
+ * Writes conflict with the ROM's output unless the header says otherwise (submapper 1). */
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    chr_bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Cnrom {
+            bus_conflicts: rom.submapper != 1,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            mirroring: rom.screen_mirroring,
+            chr_bank: 0,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        bank_offset(self.chr.len(), self.chr_bank as usize, CHR_BANK_SIZE, addr)
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, mut data: u8) {
+        if addr >= 0x8000 {
+            if self.bus_conflicts {
+                data &= self.cpu_peek(addr);
+            }
+            self.chr_bank = data;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.chr_bank);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.chr_bank = state.read_u8()?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+/* The two serial EEPROMs found on Bandai boards */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EepromChip {
+    /* X24C01: 128 bytes, no device address, everything shifted LSB first */
+    C01,
+    /* 24C02: 256 bytes, standard I2C with a device address byte, MSB first */
+    C02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    Device,
+    Address,
+    Write,
+    Read,
+}
+
+impl Mode {
+    fn to_u8(self) -> u8 {
+        match self {
+            Mode::Idle => 0,
+            Mode::Device => 1,
+            Mode::Address => 2,
+            Mode::Write => 3,
+            Mode::Read => 4,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Mode::Device,
+            2 => Mode::Address,
+            3 => Mode::Write,
+            4 => Mode::Read,
+            _ => Mode::Idle,
+        }
+    }
+}
+
+/* A bit-level I2C EEPROM, driven by the game toggling SCL and SDA through a mapper
+ * register.
+ *
+ * SDA falling while SCL is high is a start condition and SDA rising while SCL is high a
+ * stop. Otherwise the chip samples SDA on SCL's rising edge and changes its own output
+ * on the falling edge. Every byte is followed by an acknowledge bit: the receiver pulls
+ * SDA low. When reading, the game's acknowledge asks for the next byte and its absence
+ * ends the transfer. Writes wrap within a page: 8 bytes on the 24C02, 4 on the X24C01. */
+pub struct I2cEeprom {
+    chip: EepromChip,
+    pub data: Vec<u8>,
+    mode: Mode,
+    next_mode: Mode,
+    scl: bool,
+    sda: bool,
+    // What the chip drives onto SDA; high (released) unless it's acknowledging or
+    // sending a 0
+    output: bool,
+    shift: u8,
+    bit: u8,
+    address: u8,
+}
+
+impl I2cEeprom {
+    pub fn new(chip: EepromChip) -> Self {
+        let size = match chip {
+            EepromChip::C01 => 128,
+            EepromChip::C02 => 256,
+        };
+        I2cEeprom {
+            chip,
+            data: vec![0; size],
+            mode: Mode::Idle,
+            next_mode: Mode::Idle,
+            scl: false,
+            sda: false,
+            output: true,
+            shift: 0,
+            bit: 0,
+            address: 0,
+        }
+    }
+
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    pub fn write_lines(&mut self, scl: bool, sda: bool) {
+        let (old_scl, old_sda) = (self.scl, self.sda);
+        self.scl = scl;
+        self.sda = sda;
+        if old_scl && scl {
+            if old_sda && !sda {
+                self.start();
+            } else if !old_sda && sda {
+                self.mode = Mode::Idle;
+                self.output = true;
+            }
+        } else if !old_scl && scl {
+            self.rising_edge();
+        } else if old_scl && !scl {
+            self.falling_edge();
+        }
+    }
+
+    fn start(&mut self) {
+        self.mode = match self.chip {
+            EepromChip::C01 => Mode::Address,
+            EepromChip::C02 => Mode::Device,
+        };
+        self.bit = 0;
+        self.shift = 0;
+        self.output = true;
+    }
+
+    fn lsb_first(&self) -> bool {
+        self.chip == EepromChip::C01
+    }
+
+    fn rising_edge(&mut self) {
+        match self.mode {
+            Mode::Device | Mode::Address | Mode::Write if self.bit < 8 => {
+                self.shift = if self.lsb_first() {
+                    self.shift | (self.sda as u8) << self.bit
+                } else {
+                    self.shift << 1 | self.sda as u8
+                };
+                self.bit += 1;
+            }
+            Mode::Read if self.bit == 9 => {
+                if self.sda {
+                    // No acknowledge: the game has read all it wanted
+                    self.mode = Mode::Idle;
+                } else {
+                    self.address = self.address.wrapping_add(1) & self.address_mask();
+                    self.shift = self.data[self.address as usize];
+                    self.bit = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn falling_edge(&mut self) {
+        match self.mode {
+            Mode::Device | Mode::Address | Mode::Write if self.bit == 8 => {
+                self.receive_byte();
+                self.bit = 9;
+            }
+            Mode::Device | Mode::Address | Mode::Write if self.bit == 9 => {
+                self.output = true;
+                self.bit = 0;
+                self.shift = 0;
+                self.mode = self.next_mode;
+                if self.mode == Mode::Read {
+                    self.shift = self.data[self.address as usize];
+                    self.send_bit();
+                }
+            }
+            Mode::Read if self.bit < 8 => self.send_bit(),
+            Mode::Read if self.bit == 8 => {
+                // Let go of SDA for the game's acknowledge
+                self.output = true;
+                self.bit = 9;
+            }
+            _ => {}
+        }
+    }
+
+    fn send_bit(&mut self) {
+        let position = if self.lsb_first() { self.bit } else { 7 - self.bit };
+        self.output = (self.shift >> position) & 1 != 0;
+        self.bit += 1;
+    }
+
+    fn address_mask(&self) -> u8 {
+        (self.data.len() - 1) as u8
+    }
+
+    // Acts on a complete byte and acknowledges it, unless it's for another device
+    fn receive_byte(&mut self) {
+        let byte = self.shift;
+        self.next_mode = match (self.mode, self.chip) {
+            (Mode::Device, _) if byte & 0xF0 != 0xA0 => {
+                self.mode = Mode::Idle;
+                return;
+            }
+            (Mode::Device, _) if byte & 1 != 0 => Mode::Read,
+            (Mode::Device, _) => Mode::Address,
+            (Mode::Address, EepromChip::C01) => {
+                self.address = byte & 0x7F;
+                if byte & 0x80 != 0 { Mode::Read } else { Mode::Write }
+            }
+            (Mode::Address, EepromChip::C02) => {
+                self.address = byte;
+                Mode::Write
+            }
+            _ => {
+                self.data[self.address as usize] = byte;
+                let page = match self.chip {
+                    EepromChip::C01 => 0b011,
+                    EepromChip::C02 => 0b111,
+                };
+                self.address = (self.address & !page) | (self.address.wrapping_add(1) & page);
+                Mode::Write
+            }
+        };
+        self.output = false;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.mode.to_u8());
+        state.write_u8(self.next_mode.to_u8());
+        state.write_bool(self.scl);
+        state.write_bool(self.sda);
+        state.write_bool(self.output);
+        state.write_u8(self.shift);
+        state.write_u8(self.bit);
+        state.write_u8(self.address);
+        state.write_bytes(&self.data);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mode = Mode::from_u8(state.read_u8()?);
+        self.next_mode = Mode::from_u8(state.read_u8()?);
+        self.scl = state.read_bool()?;
+        self.sda = state.read_bool()?;
+        self.output = state.read_bool()?;
+        self.shift = state.read_u8()?;
+        self.bit = state.read_u8()?;
+        self.address = state.read_u8()? & self.address_mask();
+        state.read_bytes_into(&mut self.data)
+    }
+}
@@ -0,0 +1,424 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, last_bank, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// Full volume on one channel comes out about as loud as an APU pulse at full volume
+const CHANNEL_SCALE: f32 = 0.15;
+// Tones and noise are clocked every 16 CPU cycles, the 32-step envelope every 8
+const TONE_DIVIDER: u8 = 16;
+const ENVELOPE_DIVIDER: u8 = 8;
+
+/* The Sunsoft 5B's sound core, a YM2149 in all but name: three square channels that
+ * can each mix in the shared noise generator, with either a fixed volume or the shared
+ * envelope. $C000 selects one of these registers, $E000 writes it:
+ *
+ *  $00-$05  tone period for A, B and C, low 8 bits then high 4 bits
+ *  $06      noise period (5 bits)
+ *  $07      --CB Acba  noise disable for C/B/A, tone disable for c/b/a
+ *  $08-$0A  ---E VVVV  volume for A, B and C, or the envelope if E is set
+ *  $0B-$0C  envelope period, low then high byte
+ *  $0D      ---- CAAH  envelope shape: continue, attack, alternate, hold
+ *
+ * Volume steps are 3 dB apart, 1.5 dB for the envelope's 32 levels. */
+struct Sunsoft5b {
+    register: u8,
+    divider: u8,
+    tone_periods: [u16; 3],
+    tone_timers: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_period: u8,
+    noise_timer: u8,
+    noise_shift: u32,
+    mixer: u8,
+    volumes: [u8; 3],
+    envelope_period: u16,
+    envelope_timer: u16,
+    envelope_step: u8,
+    envelope_shape: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+}
+
+impl Sunsoft5b {
+    fn new() -> Self {
+        Sunsoft5b {
+            register: 0,
+            divider: 0,
+            tone_periods: [0; 3],
+            tone_timers: [0; 3],
+            tone_outputs: [false; 3],
+            noise_period: 0,
+            noise_timer: 0,
+            noise_shift: 1,
+            mixer: 0,
+            volumes: [0; 3],
+            envelope_period: 0,
+            envelope_timer: 0,
+            envelope_step: 0,
+            envelope_shape: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        match self.register {
+            register @ 0x00..=0x05 => {
+                let channel = (register / 2) as usize;
+                let period = self.tone_periods[channel];
+                self.tone_periods[channel] = if register & 1 == 0 {
+                    (period & 0x0F00) | data as u16
+                } else {
+                    (period & 0x00FF) | ((data as u16 & 0x0F) << 8)
+                };
+            }
+            0x06 => self.noise_period = data & 0x1F,
+            0x07 => self.mixer = data,
+            register @ 0x08..=0x0A => self.volumes[(register - 0x08) as usize] = data & 0x1F,
+            0x0B => self.envelope_period = (self.envelope_period & 0xFF00) | data as u16,
+            0x0C => self.envelope_period = (self.envelope_period & 0x00FF) | (data as u16) << 8,
+            0x0D => {
+                self.envelope_shape = data & 0x0F;
+                self.envelope_attack = data & 0b0100 != 0;
+                self.envelope_step = 0;
+                self.envelope_timer = 0;
+                self.envelope_holding = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.divider = (self.divider + 1) % TONE_DIVIDER;
+        if self.divider.is_multiple_of(ENVELOPE_DIVIDER) {
+            self.clock_envelope();
+        }
+        if self.divider != 0 {
+            return;
+        }
+
+        for channel in 0..3 {
+            self.tone_timers[channel] += 1;
+            if self.tone_timers[channel] >= self.tone_periods[channel].max(1) {
+                self.tone_timers[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_timer += 1;
+        if self.noise_timer >= self.noise_period.max(1) {
+            self.noise_timer = 0;
+            // 17-bit LFSR with taps on bits 0 and 3
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        self.envelope_timer += 1;
+        if self.envelope_timer < self.envelope_period.max(1) {
+            return;
+        }
+        self.envelope_timer = 0;
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+
+        let cont = self.envelope_shape & 0b1000 != 0;
+        let alternate = self.envelope_shape & 0b0010 != 0;
+        let hold = self.envelope_shape & 0b0001 != 0;
+        if !cont {
+            // Shapes 0-7 end on silence whichever way they went
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else if hold {
+            // Stays on the last level, or its opposite when alternating
+            self.envelope_attack ^= alternate;
+            self.envelope_holding = true;
+        } else {
+            self.envelope_attack ^= alternate;
+            self.envelope_step = 0;
+        }
+    }
+
+    // 5-bit level, where a fixed volume v sits on the envelope's level 2v + 1
+    fn level(&self, channel: usize) -> u8 {
+        let volume = self.volumes[channel];
+        if volume & 0x10 != 0 {
+            if self.envelope_attack { self.envelope_step } else { 31 - self.envelope_step }
+        } else if volume == 0 {
+            0
+        } else {
+            volume * 2 + 1
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let noise = self.noise_shift & 1 != 0;
+        (0..3)
+            .map(|channel| {
+                let tone_on = self.tone_outputs[channel] || self.mixer & (1 << channel) != 0;
+                let noise_on = noise || self.mixer & (8 << channel) != 0;
+                match self.level(channel) {
+                    level if level > 0 && tone_on && noise_on => {
+                        10f32.powf((level as f32 - 31.0) * 1.5 / 20.0)
+                    }
+                    _ => 0.0,
+                }
+            })
+            .sum::<f32>()
+            * CHANNEL_SCALE
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        state.write_u8(self.divider);
+        for channel in 0..3 {
+            state.write_u16(self.tone_periods[channel]);
+            state.write_u16(self.tone_timers[channel]);
+            state.write_bool(self.tone_outputs[channel]);
+            state.write_u8(self.volumes[channel]);
+        }
+        state.write_u8(self.noise_period);
+        state.write_u8(self.noise_timer);
+        state.write_u32(self.noise_shift);
+        state.write_u8(self.mixer);
+        state.write_u16(self.envelope_period);
+        state.write_u16(self.envelope_timer);
+        state.write_u8(self.envelope_step);
+        state.write_u8(self.envelope_shape);
+        state.write_bool(self.envelope_attack);
+        state.write_bool(self.envelope_holding);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u8()?;
+        self.divider = state.read_u8()?;
+        for channel in 0..3 {
+            self.tone_periods[channel] = state.read_u16()?;
+            self.tone_timers[channel] = state.read_u16()?;
+            self.tone_outputs[channel] = state.read_bool()?;
+            self.volumes[channel] = state.read_u8()?;
+        }
+        self.noise_period = state.read_u8()?;
+        self.noise_timer = state.read_u8()?;
+        self.noise_shift = state.read_u32()?;
+        self.mixer = state.read_u8()?;
+        self.envelope_period = state.read_u16()?;
+        self.envelope_timer = state.read_u16()?;
+        self.envelope_step = state.read_u8()?;
+        self.envelope_shape = state.read_u8()?;
+        self.envelope_attack = state.read_bool()?;
+        self.envelope_holding = state.read_bool()?;
+        Ok(())
+    }
+}
+
+/* Mapper 69: Sunsoft FME-7, and the 5A and 5B that add sound to it.
+ *
+ *  $8000  command    which register the next parameter goes to
+ *  $A000  parameter
+ *  $C000  5B audio register select
+ *  $E000  5B audio register write
+ *
+ * Commands:
+ *  $0-$7  1 KiB CHR bank for PPU $0000-$1FFF
+ *  $8     EMBBBBBB  $6000: RAM enable, RAM (1) or ROM (0), bank
+ *  $9-$B  8 KiB PRG bank at $8000, $A000, $C000 ($E000 holds the last bank)
+ *  $C     mirroring  0: vertical, 1: horizontal, 2/3: single screen lower/upper
+ *  $D     C------T  IRQ counter enable, IRQ enable; writing acknowledges the IRQ
+ *  $E/$F  IRQ counter low/high byte
+ *
+ * The 16-bit IRQ counter counts down every CPU cycle and fires when it wraps past 0. */
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    command: u8,
+    chr_banks: [u8; 8],
+    prg_banks: [u8; 4],
+    mirroring: u8,
+    irq_control: u8,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Fme7 {
+            prg_ram: prg_ram(&rom),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            mirroring: 0,
+            irq_control: 0,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn ram_selected(&self) -> bool {
+        self.prg_banks[0] & 0x40 != 0
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_selected() && self.prg_banks[0] & 0x80 != 0 && !self.prg_ram.is_empty()
+    }
+
+    fn prg_ram_offset(&self, addr: u16) -> usize {
+        let bank = (self.prg_banks[0] & 0x3F) as usize;
+        bank_offset(self.prg_ram.len(), bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x6000..=0xDFFF => (self.prg_banks[((addr - 0x6000) >> 13) as usize] & 0x3F) as usize,
+            _ => last_bank(self.prg_rom.len(), PRG_BANK_SIZE),
+        };
+        bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 10) as usize & 7];
+        bank_offset(self.chr.len(), bank as usize, CHR_BANK_SIZE, addr)
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            command @ 0x0..=0x7 => self.chr_banks[command as usize] = data,
+            command @ 0x8..=0xB => self.prg_banks[(command - 0x8) as usize] = data,
+            0xC => self.mirroring = data & 0b11,
+            0xD => {
+                self.irq_control = data;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.ram_enabled() => self.prg_ram[self.prg_ram_offset(addr)],
+            0x6000..=0x7FFF if self.ram_selected() => 0,
+            0x6000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.ram_enabled() => {
+                let offset = self.prg_ram_offset(addr);
+                self.prg_ram[offset] = data;
+            }
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.register = data & 0x0F,
+            0xE000..=0xFFFF => self.audio.write(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if self.irq_control & 0x80 != 0 {
+                self.irq_counter = self.irq_counter.wrapping_sub(1);
+                if self.irq_counter == 0xFFFF && self.irq_control & 0x01 != 0 {
+                    self.irq_pending = true;
+                }
+            }
+            self.audio.clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.command);
+        for bank in self.chr_banks {
+            state.write_u8(bank);
+        }
+        for bank in self.prg_banks {
+            state.write_u8(bank);
+        }
+        state.write_u8(self.mirroring);
+        state.write_u8(self.irq_control);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_pending);
+        self.audio.save_state(state);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.command = state.read_u8()?;
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        for bank in self.prg_banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        self.mirroring = state.read_u8()?;
+        self.irq_control = state.read_u8()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+        self.audio.load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/* Mappers 66 and 11: GxROM (GNROM, MHROM) and Color Dreams.
+ * Writes to $8000-$FFFF select a 32 KiB PRG bank and an 8 KiB CHR bank, packed
+ * differently on the two boards:
+ *
+ *  GxROM         --PP--CC
+ *  Color Dreams  CCCC--PP
+ *
+ * Both have bus conflicts. */
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    color_dreams: bool,
+    mirroring: Mirroring,
+    register: u8,
+}
+
+impl Gxrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Gxrom {
+            color_dreams: rom.mapper == 11,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            mirroring: rom.screen_mirroring,
+            register: 0,
+        }
+    }
+
+    // (PRG bank, CHR bank) from the register
+    fn banks(&self) -> (usize, usize) {
+        if self.color_dreams {
+            ((self.register & 0b11) as usize, (self.register >> 4) as usize)
+        } else {
+            (((self.register >> 4) & 0b11) as usize, (self.register & 0b11) as usize)
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        bank_offset(self.chr.len(), self.banks().1, CHR_BANK_SIZE, addr)
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank = self.banks().0;
+                self.prg_rom[bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.register = data & self.cpu_peek(addr);
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u8()?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
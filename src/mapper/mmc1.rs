@@ -0,0 +1,228 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+// SUROM/SXROM reach past 256 KiB of PRG-ROM with a bit of the CHR bank register
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+/* Mapper 1: Nintendo MMC1 (SxROM boards).
+ *
+ * The registers are loaded one bit at a time: five writes to $8000-$FFFF shift bit 0 of
+ * the data into a shift register, and the fifth write copies it into the register picked
+ * by bits 13-14 of that write's address. Writing a value with bit 7 set clears the shift
+ * register and locks the last PRG bank at $C000.
+ *
+ *  $8000 control   ---CPPMM  CHR mode (8/4 KiB), PRG mode, mirroring
+ *  $A000 CHR bank 0          4 KiB bank at PPU $0000, or 8 KiB bank in 8 KiB mode
+ *  $C000 CHR bank 1          4 KiB bank at PPU $1000, ignored in 8 KiB mode
+ *  $E000 PRG bank  ---RPPPP  PRG-RAM disable, 16 KiB PRG bank
+ *
+ * On the boards with 8 KiB of CHR-RAM the upper CHR bank bits aren't needed for CHR and
+ * drive other things instead: bit 4 selects the 256 KiB PRG half on SUROM/SXROM (or
+ * disables PRG-RAM on SNROM), bits 2-3 select the 8 KiB PRG-RAM bank on SOROM/SXROM.
+ * In 4 KiB CHR mode the real board uses whichever register the PPU last fetched
+ * through; CHR bank 0 is used here, which is what games set up anyway. */
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    shift_register: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+    // The MMC1 ignores a write on the cycle right after another one, which only
+    // happens when a read-modify-write instruction writes twice
+    written_this_instruction: bool,
+}
+
+impl Mmc1 {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Mmc1 {
+            prg_ram: prg_ram(&rom),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            shift_register: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            written_this_instruction: false,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    // Bit 4 of the CHR bank register picks the 256 KiB half on 512 KiB boards
+    fn prg_outer_bank(&self) -> usize {
+        if self.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            ((self.chr_bank_0 >> 4) & 1) as usize * (PRG_OUTER_BANK_SIZE / PRG_BANK_SIZE)
+        } else {
+            0
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let outer = self.prg_outer_bank();
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let bank = match ((self.control >> 2) & 0b11, addr) {
+            // 32 KiB mode switches both halves together, ignoring the low bit
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            // First bank fixed at $8000, $C000 switchable
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            // $8000 switchable, last bank fixed at $C000
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => 0x0F,
+        };
+        bank_offset(self.prg_rom.len(), outer + bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        if self.prg_ram.is_empty() || self.prg_bank & 0x10 != 0 {
+            return false;
+        }
+        // SNROM wires CHR A16 to a second PRG-RAM chip enable
+        let snrom = self.chr_is_ram
+            && self.prg_ram.len() == PRG_RAM_BANK_SIZE
+            && self.prg_rom.len() <= PRG_OUTER_BANK_SIZE;
+        !(snrom && self.chr_bank_0 & 0x10 != 0)
+    }
+
+    fn prg_ram_offset(&self, addr: u16) -> usize {
+        let bank = match self.prg_ram.len() / PRG_RAM_BANK_SIZE {
+            // SOROM: bit 3 picks one of two 8 KiB chips
+            2 => (self.chr_bank_0 >> 3) & 1,
+            // SXROM: bits 2-3 pick one of four 8 KiB banks
+            _ => (self.chr_bank_0 >> 2) & 0b11,
+        };
+        bank_offset(self.prg_ram.len(), bank as usize, PRG_RAM_BANK_SIZE, addr)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = if self.control & 0x10 == 0 {
+            // 8 KiB mode: the register's low bit is ignored
+            (self.chr_bank_0 & !1) as usize + (addr >= 0x1000) as usize
+        } else if addr < 0x1000 {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        };
+        bank_offset(self.chr.len(), bank, CHR_BANK_SIZE, addr)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[self.prg_ram_offset(addr)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let offset = self.prg_ram_offset(addr);
+                self.prg_ram[offset] = data;
+            }
+            0x8000..=0xFFFF => {
+                if self.written_this_instruction {
+                    return;
+                }
+                self.written_this_instruction = true;
+
+                if data & 0x80 != 0 {
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+                self.shift_register |= (data & 1) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    self.write_register(addr, self.shift_register);
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_clock(&mut self, _cycles: u8) {
+        self.written_this_instruction = false;
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.shift_register);
+        state.write_u8(self.shift_count);
+        state.write_u8(self.control);
+        state.write_u8(self.chr_bank_0);
+        state.write_u8(self.chr_bank_1);
+        state.write_u8(self.prg_bank);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift_register = state.read_u8()?;
+        self.shift_count = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.chr_bank_0 = state.read_u8()?;
+        self.chr_bank_1 = state.read_u8()?;
+        self.prg_bank = state.read_u8()?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
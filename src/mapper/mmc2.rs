@@ -0,0 +1,168 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, last_bank, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/* Mappers 9 and 10: Nintendo MMC2 (PxROM) and MMC4 (FxROM).
+ *
+ *  $A000  PRG bank: 8 KiB at $8000 on the MMC2, 16 KiB on the MMC4
+ *  $B000  4 KiB CHR bank at $0000 while latch 0 holds $FD
+ *  $C000  4 KiB CHR bank at $0000 while latch 0 holds $FE
+ *  $D000  4 KiB CHR bank at $1000 while latch 1 holds $FD
+ *  $E000  4 KiB CHR bank at $1000 while latch 1 holds $FE
+ *  $F000  mirroring  0: vertical, 1: horizontal
+ *
+ * Each pattern table half has a latch that the PPU sets by fetching tile $FD or $FE
+ * from it; the new bank applies from the next fetch on. The fetches that count are
+ * $xFD8-$xFDF and $xFE8-$xFEF, except that the MMC2's left half only reacts to exactly
+ * $0FD8 and $0FE8. The rest of PRG is fixed to the last banks, and the MMC4 has
+ * PRG-RAM at $6000. */
+pub struct Mmc2 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mmc4: bool,
+    prg_bank: u8,
+    // [half][latch]: latch 0 is $FD, latch 1 is $FE
+    chr_banks: [[u8; 2]; 2],
+    latches: [usize; 2],
+    horizontal_mirroring: bool,
+}
+
+impl Mmc2 {
+    pub fn new(mut rom: Rom) -> Self {
+        let mmc4 = rom.mapper == 10;
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Mmc2 {
+            prg_ram: if mmc4 { prg_ram(&rom) } else { Vec::new() },
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            mmc4,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1, 1],
+            horizontal_mirroring: rom.screen_mirroring == Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let len = self.prg_rom.len();
+        let bank_size = if self.mmc4 { 0x4000 } else { 0x2000 };
+        let switchable_end = 0x8000 + bank_size as u16;
+        let bank = if addr < switchable_end {
+            self.prg_bank as usize
+        } else {
+            // The fixed banks run up to the end of ROM
+            let banks_from_end = (0x10000 - addr as usize).div_ceil(bank_size);
+            (last_bank(len, bank_size) + 1).saturating_sub(banks_from_end)
+        };
+        bank_offset(len, bank, bank_size, addr)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let half = (addr >> 12) as usize & 1;
+        let bank = self.chr_banks[half][self.latches[half]];
+        bank_offset(self.chr.len(), bank as usize, CHR_BANK_SIZE, addr)
+    }
+
+    fn update_latch(&mut self, addr: u16) {
+        let half = (addr >> 12) as usize & 1;
+        let exact = !self.mmc4 && half == 0;
+        match addr & 0x0FF8 {
+            0x0FD8 if !exact || addr & 0x0FFF == 0x0FD8 => self.latches[half] = 0,
+            0x0FE8 if !exact || addr & 0x0FFF == 0x0FE8 => self.latches[half] = 1,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = data & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = data & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = data & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = data & 0x1F,
+            0xF000..=0xFFFF => self.horizontal_mirroring = data & 1 != 0,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let data = self.chr[self.chr_offset(addr)];
+        self.update_latch(addr);
+        data
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontal_mirroring {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_bank);
+        for half in self.chr_banks {
+            state.write_u8(half[0]);
+            state.write_u8(half[1]);
+        }
+        state.write_u8(self.latches[0] as u8);
+        state.write_u8(self.latches[1] as u8);
+        state.write_bool(self.horizontal_mirroring);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read_u8()?;
+        for half in self.chr_banks.iter_mut() {
+            half[0] = state.read_u8()?;
+            half[1] = state.read_u8()?;
+        }
+        self.latches[0] = (state.read_u8()? & 1) as usize;
+        self.latches[1] = (state.read_u8()? & 1) as usize;
+        self.horizontal_mirroring = state.read_bool()?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
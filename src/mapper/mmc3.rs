@@ -0,0 +1,254 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, last_bank, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// A12 has to stay low for about three CPU cycles before a rise clocks the counter,
+// which filters out the short drops between the eight sprite pattern fetches
+const A12_FILTER_DOTS: u64 = 10;
+
+/* The two IRQ counter behaviours found on MMC3 boards */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mmc3Revision {
+    /* Early MMC3A: no IRQ when a latch of 0 keeps reloading the counter with 0,
+     * only when it counts down to 0 or is reloaded through $C001 */
+    A,
+    /* MMC3B/C: an IRQ every time the counter is 0 after being clocked */
+    B,
+}
+
+/* Mapper 4: Nintendo MMC3 (TxROM boards).
+ *
+ *  $8000 even  bank select   CP---RRR  CHR A12 inversion, PRG mode, register to update
+ *  $8001 odd   bank data     value for R0-R7
+ *  $A000 even  mirroring     0: vertical, 1: horizontal
+ *  $A001 odd   PRG-RAM protect  RW------  chip enable, deny writes
+ *  $C000 even  IRQ latch     counter reload value
+ *  $C001 odd   IRQ reload    reload the counter on the next clock
+ *  $E000 even  IRQ disable   also acknowledges a pending IRQ
+ *  $E001 odd   IRQ enable
+ *
+ * R0/R1 pick 2 KiB CHR banks and R2-R5 1 KiB ones; R6/R7 pick 8 KiB PRG banks, the
+ * other two PRG windows hold the second-to-last and last bank.
+ * The IRQ counter is clocked by rising edges of PPU A12, once per scanline when the
+ * background uses $0000 and sprites $1000. */
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    four_screen: bool,
+    revision: Mmc3Revision,
+    bank_select: u8,
+    registers: [u8; 8],
+    horizontal_mirroring: bool,
+    prg_ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12_high: bool,
+    a12_low_since: u64,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Self {
+        // NES 2.0 submapper 4 marks the boards with the early IRQ behaviour
+        let revision = if rom.submapper == 4 { Mmc3Revision::A } else { Mmc3Revision::B };
+        Self::with_revision(rom, revision)
+    }
+
+    pub fn with_revision(mut rom: Rom, revision: Mmc3Revision) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Mmc3 {
+            prg_ram: prg_ram(&rom),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            revision,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal_mirroring: rom.screen_mirroring == Mirroring::Horizontal,
+            // Not every game enables the RAM before using it, so start with it on
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_high: false,
+            a12_low_since: 0,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let last = last_bank(self.prg_rom.len(), PRG_BANK_SIZE);
+        let swap = self.bank_select & 0x40 != 0;
+        let bank = match (addr, swap) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.registers[6] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => last.saturating_sub(1),
+            (0xA000..=0xBFFF, _) => self.registers[7] as usize,
+            _ => last,
+        };
+        bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        // A12 inversion swaps the 2 KiB and 1 KiB halves
+        let addr = if self.bank_select & 0x80 != 0 { addr ^ 0x1000 } else { addr };
+        let bank = match addr & 0x1FFF {
+            0x0000..=0x07FF => (self.registers[0] & !1) as usize + ((addr >> 10) & 1) as usize,
+            0x0800..=0x0FFF => (self.registers[1] & !1) as usize + ((addr >> 10) & 1) as usize,
+            _ => self.registers[2 + ((addr >> 10) & 0b11) as usize] as usize,
+        };
+        bank_offset(self.chr.len(), bank, CHR_BANK_SIZE, addr)
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_ram_protect & 0x80 != 0
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let reloaded = self.irq_reload;
+        let was_zero = self.irq_counter == 0;
+        if was_zero || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let fire = match self.revision {
+            Mmc3Revision::A => self.irq_counter == 0 && (!was_zero || reloaded),
+            Mmc3Revision::B => self.irq_counter == 0,
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_readable() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_readable() && self.prg_ram_protect & 0x40 == 0 => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.registers[(self.bank_select & 0b111) as usize] = data,
+            0xA000..=0xBFFF if even => self.horizontal_mirroring = data & 1 != 0,
+            0xA000..=0xBFFF => self.prg_ram_protect = data,
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else if self.horizontal_mirroring {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn ppu_address(&mut self, addr: u16, dot: u64) {
+        let a12_high = addr & 0x1000 != 0;
+        if a12_high && !self.a12_high && dot.wrapping_sub(self.a12_low_since) >= A12_FILTER_DOTS {
+            self.clock_irq_counter();
+        }
+        if !a12_high && self.a12_high {
+            self.a12_low_since = dot;
+        }
+        self.a12_high = a12_high;
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.bank_select);
+        for register in self.registers {
+            state.write_u8(register);
+        }
+        state.write_bool(self.horizontal_mirroring);
+        state.write_u8(self.prg_ram_protect);
+        state.write_u8(self.irq_latch);
+        state.write_u8(self.irq_counter);
+        state.write_bool(self.irq_reload);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.a12_high);
+        state.write_u64(self.a12_low_since);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select = state.read_u8()?;
+        for register in self.registers.iter_mut() {
+            *register = state.read_u8()?;
+        }
+        self.horizontal_mirroring = state.read_bool()?;
+        self.prg_ram_protect = state.read_u8()?;
+        self.irq_latch = state.read_u8()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_reload = state.read_bool()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.a12_high = state.read_bool()?;
+        self.a12_low_since = state.read_u64()?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
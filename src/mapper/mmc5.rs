@@ -0,0 +1,697 @@
+use crate::cartridge::{Mirroring, Rom, RomFormat};
+use crate::mapper::{bank_offset, chr_memory, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const EXRAM_SIZE: usize = 0x400;
+// iNES headers can't say how much PRG-RAM an MMC5 board has, so give them the most
+const DEFAULT_PRG_RAM_SIZE: usize = 0x10000;
+// The pulse channels' length counters and envelopes run off a fixed 240 Hz clock
+const QUARTER_FRAME_CYCLES: u32 = 7457;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96,
+    22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/* One of the MMC5's two square channels: an APU pulse channel without the sweep unit */
+#[derive(Default)]
+struct Pulse {
+    enabled: bool,
+    duty: u8,
+    halt_or_loop: bool,
+    constant_volume: bool,
+    volume: u8,
+    timer_period: u16,
+    timer: u16,
+    step: u8,
+    length: u8,
+    envelope_start: bool,
+    envelope_divider: u8,
+    envelope_decay: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.halt_or_loop = data & 0x20 != 0;
+                self.constant_volume = data & 0x10 != 0;
+                self.volume = data & 0x0F;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    // Advanced once every other CPU cycle, like the APU's pulse timers
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.envelope_decay = 15;
+            self.envelope_divider = self.volume;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.volume;
+            if self.envelope_decay > 0 {
+                self.envelope_decay -= 1;
+            } else if self.halt_or_loop {
+                self.envelope_decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+        if !self.halt_or_loop && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            return 0;
+        }
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.envelope_decay
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.duty);
+        state.write_bool(self.halt_or_loop);
+        state.write_bool(self.constant_volume);
+        state.write_u8(self.volume);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+        state.write_u8(self.length);
+        state.write_bool(self.envelope_start);
+        state.write_u8(self.envelope_divider);
+        state.write_u8(self.envelope_decay);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.duty = state.read_u8()?;
+        self.halt_or_loop = state.read_bool()?;
+        self.constant_volume = state.read_bool()?;
+        self.volume = state.read_u8()?;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()?;
+        self.length = state.read_u8()?;
+        self.envelope_start = state.read_bool()?;
+        self.envelope_divider = state.read_u8()?;
+        self.envelope_decay = state.read_u8()?;
+        Ok(())
+    }
+}
+
+/* Which of the two CHR register sets a pattern fetch goes through */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChrSet {
+    // $5120-$5127: sprites, or everything with 8x8 sprites
+    A,
+    // $5128-$512B: background with 8x16 sprites
+    B,
+}
+
+/* Mapper 5: Nintendo MMC5 (ExROM boards).
+ *
+ *  $5000-$5015  two pulse channels and a raw PCM channel
+ *  $5100  PRG mode       ------PP  32, 16+16, 16+8+8 or 8+8+8+8 KiB
+ *  $5101  CHR mode       ------CC  8, 4, 2 or 1 KiB
+ *  $5102/$5103  PRG-RAM protect, writable only with %10 and %01 written here
+ *  $5104  ExRAM mode     ------XX  nametable, extended attributes, CPU RAM, CPU ROM
+ *  $5105  nametables     DDCCBBAA  CIRAM page 0/1, ExRAM or fill mode per nametable
+ *  $5106/$5107  fill-mode tile and attribute
+ *  $5113-$5117  PRG banks: RAM at $6000, then $8000-$FFFF with bit 7 set for ROM
+ *  $5120-$512B  CHR banks, $5130 supplies their upper bits
+ *  $5200-$5202  vertical split: enable/side/tile count, scroll, 4 KiB CHR bank
+ *  $5203/$5204  scanline IRQ compare value, IRQ enable / status
+ *  $5205/$5206  8x8 -> 16 bit unsigned multiplier
+ *  $5C00-$5FFF  1 KiB of ExRAM
+ *
+ * The MMC5 has no view of the PPU's internal state, so it works out what the PPU is
+ * doing from its bus: scanline starts, which nametable fetch is which tile, and whether
+ * a pattern fetch is for sprites or the background. Here the PPU tells it through the
+ * scanline(), sprite_fetches() and ppu_register_write() hooks instead. */
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    exram: [u8; EXRAM_SIZE],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    prg_banks: [u8; 5],
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    last_chr_set: ChrSet,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    // What the PPU is up to, from the hooks
+    tall_sprites: bool,
+    fetching_sprites: bool,
+    tile_fetches: u8,
+    // Latched by a background nametable fetch for the attribute and pattern fetches
+    // of the same tile
+    split_tile: Option<(u8, u8)>,
+    extended_attribute: u8,
+
+    pulses: [Pulse; 2],
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq_pending: bool,
+    pcm: u8,
+    // CPU cycles into the current quarter frame
+    audio_cycles: u32,
+    // The pulse timers run at half the CPU clock, on every other cycle
+    odd_cycle: bool,
+}
+
+impl Mmc5 {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        let prg_ram_size = match rom.format {
+            RomFormat::Nes2 => rom.prg_ram_size + rom.prg_nvram_size,
+            RomFormat::INes => DEFAULT_PRG_RAM_SIZE,
+        };
+        Mmc5 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; prg_ram_size],
+            chr,
+            chr_is_ram,
+            exram: [0; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 3,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            last_chr_set: ChrSet::A,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            tall_sprites: false,
+            fetching_sprites: false,
+            tile_fetches: 0,
+            split_tile: None,
+            extended_attribute: 0,
+            pulses: [Pulse::default(), Pulse::default()],
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq_pending: false,
+            pcm: 0,
+            audio_cycles: 0,
+            odd_cycle: false,
+        }
+    }
+
+    /* PRG bank register and window size for `addr` in $6000-$FFFF.
+     * Returns whether it maps RAM and the offset into that chip. */
+    fn prg_target(&self, addr: u16) -> (bool, usize) {
+        let (register, size) = match (self.prg_mode, addr) {
+            (_, 0x6000..=0x7FFF) => (0, 1),
+            (0, _) => (4, 4),
+            (1, 0x8000..=0xBFFF) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0x8000..=0xBFFF) => (2, 2),
+            (2, 0xC000..=0xDFFF) => (3, 1),
+            (2, _) => (4, 1),
+            (_, _) => (1 + ((addr - 0x8000) >> 13) as usize, 1),
+        };
+        let value = self.prg_banks[register];
+        // $6000 is always RAM and $E000 always ROM; the rest pick with bit 7
+        let ram = register == 0 || (register < 4 && value & 0x80 == 0);
+        let within = ((addr >> 13) as usize) & (size - 1);
+        let bank = (value & 0x7F) as usize & !(size - 1) | within;
+        if ram {
+            let bank = bank & 0x07;
+            (true, bank_offset(self.prg_ram.len(), bank, PRG_BANK_SIZE, addr))
+        } else {
+            (false, bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr))
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01] && !self.prg_ram.is_empty()
+    }
+
+    fn chr_set(&self) -> ChrSet {
+        if self.tall_sprites && self.in_frame {
+            if self.fetching_sprites {
+                ChrSet::A
+            } else {
+                ChrSet::B
+            }
+        } else {
+            self.last_chr_set
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let addr = addr & 0x1FFF;
+        let background = self.in_frame && !self.fetching_sprites;
+        if background {
+            if let Some((_, fine_y)) = self.split_tile {
+                // The split replaces the fine Y scroll and reads from its own bank
+                let addr = (addr & 0x0FF8) | fine_y as u16;
+                return bank_offset(self.chr.len(), self.split_bank as usize, 0x1000, addr);
+            }
+            if self.exram_mode == 1 {
+                let bank = (self.extended_attribute & 0x3F) as usize | (self.chr_upper as usize) << 6;
+                return bank_offset(self.chr.len(), bank, 0x1000, addr);
+            }
+        }
+
+        let slot = (addr >> 10) as usize;
+        let (register, size) = match (self.chr_set(), self.chr_mode) {
+            (ChrSet::A, 0) => (7, 8),
+            (ChrSet::A, 1) => (3 + (slot & 4), 4),
+            (ChrSet::A, 2) => (1 + (slot & 6), 2),
+            (ChrSet::A, _) => (slot, 1),
+            // Set B only covers 4 KiB, which shows up in both pattern tables
+            (ChrSet::B, 0) => (11, 8),
+            (ChrSet::B, 1) => (11, 4),
+            (ChrSet::B, 2) => (9 + (slot & 2), 2),
+            (ChrSet::B, _) => (8 + (slot & 3), 1),
+        };
+        bank_offset(self.chr.len(), self.chr_banks[register] as usize, size * 0x400, addr)
+    }
+
+    // Background nametable fetch k of a scanline: dots 1-256 fetch tiles 2-33 of this
+    // line, dots 321-336 tiles 0-1 of the next one, 337-340 are dummies
+    fn latch_split_tile(&mut self) {
+        let fetch = self.tile_fetches;
+        self.tile_fetches = self.tile_fetches.saturating_add(1);
+        self.split_tile = None;
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+            return;
+        }
+        let (tile, line) = match fetch {
+            0..=31 => (fetch + 2, self.scanline),
+            32..=33 => (fetch - 32, self.scanline.wrapping_add(1)),
+            _ => return,
+        };
+        let count = self.split_control & 0x1F;
+        let inside = if self.split_control & 0x40 != 0 { tile >= count } else { tile < count };
+        if inside {
+            let y = ((self.split_scroll as u16 + line as u16) % 240) as u8;
+            self.split_tile = Some((tile & 0x1F, y));
+        }
+    }
+
+    fn split_nametable(&self, column: u8, y: u8, attribute: bool) -> u8 {
+        let row = (y / 8) as usize;
+        let column = column as usize;
+        if !attribute {
+            return self.exram[row * 32 + column];
+        }
+        let byte = self.exram[0x3C0 + (row / 4) * 8 + column / 4];
+        let shift = ((row & 2) << 1) | (column & 2);
+        replicate_palette((byte >> shift) & 0b11)
+    }
+
+    fn pcm_write(&mut self, data: u8) {
+        // A zero can't be played; it raises the PCM IRQ instead
+        if data == 0 {
+            self.pcm_irq_pending = true;
+        } else {
+            self.pcm = data;
+        }
+    }
+}
+
+// An attribute byte that gives every quadrant the same palette
+fn replicate_palette(palette: u8) -> u8 {
+    palette * 0b0101_0101
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x5010 => ((self.pcm_irq_pending && self.pcm_irq_enabled) as u8) << 7 | self.pcm_read_mode as u8,
+            0x5015 => (self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1,
+            0x5204 => (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6,
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+            0x6000..=0xFFFF => {
+                let (ram, offset) = self.prg_target(addr);
+                match ram {
+                    true if self.prg_ram.is_empty() => 0,
+                    true => self.prg_ram[offset],
+                    false => self.prg_rom[offset],
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let value = self.cpu_peek(addr);
+        match addr {
+            0x5010 => self.pcm_irq_pending = false,
+            0x5204 => self.irq_pending = false,
+            0x8000..=0xBFFF if self.pcm_read_mode => self.pcm_write(value),
+            _ => {}
+        }
+        value
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5007 => self.pulses[(addr >> 2) as usize & 1].write(addr & 0b11, data),
+            0x5010 => {
+                self.pcm_read_mode = data & 1 != 0;
+                self.pcm_irq_enabled = data & 0x80 != 0;
+            }
+            0x5011 if !self.pcm_read_mode => self.pcm_write(data),
+            0x5015 => {
+                self.pulses[0].set_enabled(data & 1 != 0);
+                self.pulses[1].set_enabled(data & 2 != 0);
+            }
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102 => self.prg_ram_protect[0] = data & 0b11,
+            0x5103 => self.prg_ram_protect[1] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0b11,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512B => {
+                let register = (addr - 0x5120) as usize;
+                self.chr_banks[register] = data as u16 | (self.chr_upper as u16) << 8;
+                self.last_chr_set = if register < 8 { ChrSet::A } else { ChrSet::B };
+            }
+            0x5130 => self.chr_upper = data & 0b11,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FFF => {
+                let offset = (addr - 0x5C00) as usize;
+                match self.exram_mode {
+                    // As nametable memory it only takes writes while the PPU renders
+                    0 | 1 => self.exram[offset] = if self.in_frame { data } else { 0 },
+                    2 => self.exram[offset] = data,
+                    _ => {}
+                }
+            }
+            0x6000..=0xFFFF if self.prg_ram_writable() => {
+                if let (true, offset) = self.prg_target(addr) {
+                    self.prg_ram[offset] = data;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    // Only an approximation: the PPU asks nametable_read() for the real mapping
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_mapping {
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            0x50 => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        }
+    }
+
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        let offset = (addr & 0x3FF) as usize;
+        let attribute = offset >= 0x3C0;
+        if self.in_frame && !self.fetching_sprites {
+            if !attribute {
+                self.latch_split_tile();
+                self.extended_attribute = self.exram[offset];
+            }
+            if let Some((column, y)) = self.split_tile {
+                return self.split_nametable(column, y, attribute);
+            }
+            if attribute && self.exram_mode == 1 {
+                return replicate_palette(self.extended_attribute >> 6);
+            }
+        }
+
+        let table = (addr >> 10) & 0b11;
+        match (self.nametable_mapping >> (table * 2)) & 0b11 {
+            0 => vram[offset],
+            1 => vram[0x400 + offset],
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0,
+            _ if attribute => replicate_palette(self.fill_attribute),
+            _ => self.fill_tile,
+        }
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        let offset = (addr & 0x3FF) as usize;
+        let table = (addr >> 10) & 0b11;
+        match (self.nametable_mapping >> (table * 2)) & 0b11 {
+            0 => vram[offset] = data,
+            1 => vram[0x400 + offset] = data,
+            2 if self.exram_mode <= 1 => self.exram[offset] = data,
+            _ => {}
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || (self.pcm_irq_pending && self.pcm_irq_enabled)
+    }
+
+    fn cpu_clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.audio_cycles += 1;
+            self.odd_cycle = !self.odd_cycle;
+            if !self.odd_cycle {
+                self.pulses[0].clock_timer();
+                self.pulses[1].clock_timer();
+            }
+            if self.audio_cycles >= QUARTER_FRAME_CYCLES {
+                self.audio_cycles = 0;
+                self.pulses[0].clock_quarter_frame();
+                self.pulses[1].clock_quarter_frame();
+            }
+        }
+    }
+
+    // The MMC5 sees a scanline start as the PPU's dummy nametable fetches at the end
+    // of the previous line, so `line` is the one that just ended
+    fn scanline(&mut self, line: u16, rendering: bool) {
+        self.tile_fetches = 0;
+        self.fetching_sprites = false;
+        self.split_tile = None;
+        let next = if line == 261 { 0 } else { line + 1 };
+        if !rendering || next >= 240 {
+            self.in_frame = false;
+            return;
+        }
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+        } else {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x2000 => self.tall_sprites = data & 0x20 != 0,
+            // Turning rendering off drops out of the frame right away
+            0x2001 if data & 0x18 == 0 => self.in_frame = false,
+            _ => {}
+        }
+    }
+
+    fn sprite_fetches(&mut self, active: bool) {
+        self.fetching_sprites = active;
+        if active {
+            self.split_tile = None;
+        }
+    }
+
+    // Same non-linear mix as the APU's pulse pair, plus the 8-bit PCM channel
+    fn audio_output(&self) -> f32 {
+        let pulses = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_out = if pulses == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulses + 100.0) };
+        pulse_out + self.pcm as f32 / 255.0 * 0.25
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.exram);
+        state.write_u8(self.prg_mode);
+        state.write_u8(self.chr_mode);
+        state.write_u8(self.prg_ram_protect[0]);
+        state.write_u8(self.prg_ram_protect[1]);
+        state.write_u8(self.exram_mode);
+        state.write_u8(self.nametable_mapping);
+        state.write_u8(self.fill_tile);
+        state.write_u8(self.fill_attribute);
+        for bank in self.prg_banks {
+            state.write_u8(bank);
+        }
+        for bank in self.chr_banks {
+            state.write_u16(bank);
+        }
+        state.write_u8(self.chr_upper);
+        state.write_bool(self.last_chr_set == ChrSet::B);
+        state.write_u8(self.split_control);
+        state.write_u8(self.split_scroll);
+        state.write_u8(self.split_bank);
+        state.write_u8(self.irq_compare);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.in_frame);
+        state.write_u8(self.scanline);
+        state.write_u8(self.multiplicand);
+        state.write_u8(self.multiplier);
+        state.write_bool(self.tall_sprites);
+        for pulse in &self.pulses {
+            pulse.save_state(state);
+        }
+        state.write_bool(self.pcm_read_mode);
+        state.write_bool(self.pcm_irq_enabled);
+        state.write_bool(self.pcm_irq_pending);
+        state.write_u8(self.pcm);
+        state.write_u32(self.audio_cycles);
+        state.write_bool(self.odd_cycle);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.exram)?;
+        self.prg_mode = state.read_u8()?;
+        self.chr_mode = state.read_u8()?;
+        self.prg_ram_protect[0] = state.read_u8()?;
+        self.prg_ram_protect[1] = state.read_u8()?;
+        self.exram_mode = state.read_u8()?;
+        self.nametable_mapping = state.read_u8()?;
+        self.fill_tile = state.read_u8()?;
+        self.fill_attribute = state.read_u8()?;
+        for bank in self.prg_banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.read_u16()?;
+        }
+        self.chr_upper = state.read_u8()?;
+        self.last_chr_set = if state.read_bool()? { ChrSet::B } else { ChrSet::A };
+        self.split_control = state.read_u8()?;
+        self.split_scroll = state.read_u8()?;
+        self.split_bank = state.read_u8()?;
+        self.irq_compare = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.in_frame = state.read_bool()?;
+        self.scanline = state.read_u8()?;
+        self.multiplicand = state.read_u8()?;
+        self.multiplier = state.read_u8()?;
+        self.tall_sprites = state.read_bool()?;
+        for pulse in self.pulses.iter_mut() {
+            pulse.load_state(state)?;
+        }
+        self.pcm_read_mode = state.read_bool()?;
+        self.pcm_irq_enabled = state.read_bool()?;
+        self.pcm_irq_pending = state.read_bool()?;
+        self.pcm = state.read_u8()?;
+        self.audio_cycles = state.read_u32()?;
+        self.odd_cycle = state.read_bool()?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use crate::cartridge::{Mirroring, Rom, RomError};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+pub mod axrom;
+pub mod bandai;
+pub mod bnrom;
+pub mod cnrom;
+mod eeprom;
+pub mod fme7;
+pub mod gxrom;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namco163;
+pub mod nrom;
+pub mod unrom512;
+pub mod uxrom;
+pub mod vrc4;
+pub mod vrc6;
+mod vrc_irq;
+
+pub use axrom::Axrom;
+pub use bandai::Bandai;
+pub use bnrom::Bnrom;
+pub use cnrom::Cnrom;
+pub use fme7::Fme7;
+pub use gxrom::Gxrom;
+pub use mmc1::Mmc1;
+pub use mmc2::Mmc2;
+pub use mmc3::{Mmc3, Mmc3Revision};
+pub use mmc5::Mmc5;
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use unrom512::Unrom512;
+pub use uxrom::Uxrom;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+
+/* The cartridge board: everything behind CPU $4020-$FFFF and PPU $0000-$1FFF.
+ * Boards differ in how they bank ROM into those windows, whether they have RAM, how
+ * they wire the nametables and whether they can raise IRQs. */
+pub trait Mapper {
+    // CPU read in $4020-$FFFF without side effects
+    fn cpu_peek(&self, addr: u16) -> u8;
+
+    // Boards with read-sensitive registers override this; everyone else just peeks
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    // PPU pattern table access in $0000-$1FFF. Rendering fetches come through here
+    // too, so boards like the MMC2 can switch banks on the tiles being drawn.
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    /* What the PPU actually calls for $0000-$1FFF. Like the nametable hooks below it
+     * gets the PPU's nametable memory, for boards that can map it into the pattern
+     * tables (the Namco 163); everyone else just goes to their CHR. */
+    fn pattern_read(&mut self, addr: u16, _vram: &[u8]) -> u8 {
+        self.ppu_read(addr)
+    }
+
+    fn pattern_write(&mut self, addr: u16, data: u8, _vram: &mut [u8]) {
+        self.ppu_write(addr, data)
+    }
+
+    // How the PPU's two nametables are laid out across $2000-$2FFF right now
+    fn mirroring(&self) -> Mirroring;
+
+    /* Nametable access in $2000-$2FFF. `vram` is the PPU's own nametable memory, laid
+     * out by mirroring() unless the board maps something else in: MMC5 puts its
+     * extra RAM and fill mode there, for instance. */
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        vram[nametable_offset(self.mirroring(), addr)]
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        vram[nametable_offset(self.mirroring(), addr)] = data;
+    }
+
+    // Whether the board is holding the CPU's IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Called after each CPU instruction with the cycles it took
+    fn cpu_clock(&mut self, _cycles: u8) {}
+
+    // Called by the PPU at the end of each scanline; `rendering` is false while
+    // the background and sprites are both disabled
+    fn scanline(&mut self, _line: u16, _rendering: bool) {}
+
+    // Every address the PPU puts on its bus, nametable fetches and $2006/$2007
+    // accesses included, with the PPU dot it happened on. Boards that watch the
+    // address lines, like the MMC3's A12 scanline counter, hook in here.
+    fn ppu_address(&mut self, _addr: u16, _dot: u64) {}
+
+    // CPU writes to the PPU registers ($2000-$2007), for boards that snoop them
+    fn ppu_register_write(&mut self, _addr: u16, _data: u8) {}
+
+    // The PPU switches to sprite pattern fetches at dot 257 and back to background
+    // fetches at dot 321, for boards with separate sprite and background CHR banks
+    fn sprite_fetches(&mut self, _active: bool) {}
+
+    // Expansion audio from the cartridge, which the bus mixes into the console's sound.
+    // Roughly 0.0-1.0 like the APU's output.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // The work RAM at $6000-$7FFF, empty on boards without any
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    /* Memory the board itself keeps across power cycles, for boards that save
+     * somewhere other than battery-backed PRG-RAM: EEPROM, flash, sound RAM.
+     * Battery-backed PRG-RAM alone is handled by NesBus through prg_ram(). */
+    fn battery_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    // Restores what battery_data() returned last time; a size mismatch is ignored
+    fn load_battery_data(&mut self, _data: &[u8]) {}
+
+    // Registers and RAM in a fixed order, restored by load_state()
+    fn save_state(&self, state: &mut StateWriter);
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+/* Builds the board the header asks for */
+pub fn for_rom(rom: Rom) -> Result<Box<dyn Mapper>, RomError> {
+    match rom.mapper {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        1 => Ok(Box::new(Mmc1::new(rom))),
+        2 => Ok(Box::new(Uxrom::new(rom))),
+        3 => Ok(Box::new(Cnrom::new(rom))),
+        4 => Ok(Box::new(Mmc3::new(rom))),
+        5 => Ok(Box::new(Mmc5::new(rom))),
+        7 => Ok(Box::new(Axrom::new(rom))),
+        9 | 10 => Ok(Box::new(Mmc2::new(rom))),
+        11 => Ok(Box::new(Gxrom::new(rom))),
+        16 | 153 | 159 => Ok(Box::new(Bandai::new(rom))),
+        19 => Ok(Box::new(Namco163::new(rom))),
+        21 | 22 | 23 | 25 => Ok(Box::new(Vrc4::new(rom))),
+        24 | 26 => Ok(Box::new(Vrc6::new(rom))),
+        30 => Ok(Box::new(Unrom512::new(rom))),
+        34 => Ok(Box::new(Bnrom::new(rom))),
+        66 => Ok(Box::new(Gxrom::new(rom))),
+        69 => Ok(Box::new(Fme7::new(rom))),
+        number => Err(RomError::UnsupportedMapper(number)),
+    }
+}
+
+/* Offset into the PPU's nametable memory for `addr` in $2000-$2FFF (or its $3000
+ * mirror). Four-screen boards bring another 2 KiB, which lives right after the PPU's own. */
+pub fn nametable_offset(mirroring: Mirroring, addr: u16) -> usize {
+    let table = ((addr >> 10) & 0b11) as usize;
+    let page = match mirroring {
+        Mirroring::Vertical => table & 1,
+        Mirroring::Horizontal => table >> 1,
+        Mirroring::SingleScreenLower => 0,
+        Mirroring::SingleScreenUpper => 1,
+        Mirroring::FourScreen => table,
+    };
+    page * 0x400 + (addr & 0x3FF) as usize
+}
+
+/* Offset of `addr` inside bank `bank` of a `len` byte chip split into `bank_size` banks.
+ * Bank numbers past the end of the chip wrap around, as the unconnected high address
+ * lines would on the real board, and a chip smaller than a bank repeats within it. */
+pub fn bank_offset(len: usize, bank: usize, bank_size: usize, addr: u16) -> usize {
+    let banks = (len / bank_size).max(1);
+    ((bank % banks) * bank_size + (addr as usize % bank_size)) % len.max(1)
+}
+
+/* Number of the last `bank_size` bank of a `len` byte chip, for the windows boards fix
+ * to the end of ROM. NES 2.0 headers can describe chips smaller than one bank, which
+ * then count as a single bank 0. */
+pub fn last_bank(len: usize, bank_size: usize) -> usize {
+    (len / bank_size).max(1) - 1
+}
+
+/* PRG-RAM as sized by the header, with the trainer (if any) already at $7000 */
+pub fn prg_ram(rom: &Rom) -> Vec<u8> {
+    let mut size = rom.prg_ram_size + rom.prg_nvram_size;
+    if rom.trainer.is_some() {
+        size = size.max(0x2000);
+    }
+    let mut ram = vec![0; size];
+    if let Some(trainer) = &rom.trainer {
+        ram[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+    }
+    ram
+}
+
+/* Pattern table memory: the CHR-ROM, or CHR-RAM when the cartridge has no CHR chip.
+ * Returns the memory and whether it's writable. */
+pub fn chr_memory(rom: &mut Rom) -> (Vec<u8>, bool) {
+    if rom.chr_rom.is_empty() {
+        let size = match rom.chr_ram_size + rom.chr_nvram_size {
+            0 => 0x2000,
+            size => size,
+        };
+        (vec![0; size], true)
+    } else {
+        (std::mem::take(&mut rom.chr_rom), false)
+    }
+}
@@ -0,0 +1,385 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, last_bank, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const SOUND_RAM_SIZE: usize = 0x80;
+// Bank numbers from $E0 up select a CIRAM page instead of CHR-ROM
+const CIRAM_BANKS: u8 = 0xE0;
+// Each channel takes 15 CPU cycles to update, one channel at a time
+const CHANNEL_CYCLES: u8 = 15;
+// A full-scale sample at full volume comes out about as loud as an APU pulse
+const OUTPUT_SCALE: f32 = 0.15 / 225.0;
+const IRQ_COUNTER_MAX: u16 = 0x7FFF;
+
+/* Mapper 19: Namco 163.
+ *
+ *  $4800  sound RAM data port, auto-incrementing the address if asked to
+ *  $5000  IRQ counter low 8 bits (readable; writing acknowledges the IRQ)
+ *  $5800  E-- high 7 bits   IRQ enable and counter high bits (same)
+ *  $8000-$BFFF  eight 1 KiB CHR banks for $0000-$1FFF
+ *  $C000-$DFFF  four 1 KiB nametable banks for $2000-$2FFF
+ *  $E000  -SPPPPPP  sound disable, 8 KiB PRG bank at $8000
+ *  $E800  HLPPPPPP  CIRAM disable in $1000/$0000, 8 KiB PRG bank at $A000
+ *  $F000  --PPPPPP  8 KiB PRG bank at $C000 ($E000 holds the last bank)
+ *  $F800  IAAAAAAA  sound RAM address with auto-increment, also PRG-RAM write protect
+ *
+ * CHR and nametable bank numbers of $E0 and up pick CIRAM page 0 or 1, unless $E800
+ * turns that off for the pattern table half in question. PRG-RAM writes need $F800 to
+ * read %0100DCBA, each of DCBA protecting one 2 KiB quarter.
+ *
+ * The 15-bit IRQ counter counts up every CPU cycle while enabled and raises the IRQ
+ * when it reaches $7FFF, where it stops.
+ *
+ * The sound RAM doubles as wavetable memory for up to eight channels, whose registers
+ * sit at the top of it, eight bytes each from $78 down to $40:
+ *  +0 +2 +4  frequency, 18 bits (the low two of +4)
+ *  +1 +3 +5  phase, 24 bits
+ *  +4        wave length in 4-bit samples: 256 - (value & $FC)
+ *  +6        wave start, in 4-bit samples
+ *  +7        volume; $7F bits 4-6 also hold the number of enabled channels - 1
+ * The chip updates one channel every 15 CPU cycles and plays them one after another,
+ * so more channels means each is heard less. */
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    battery: bool,
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    prg_banks: [u8; 3],
+    ram_protect: u8,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+    sound_ram: [u8; SOUND_RAM_SIZE],
+    sound_address: u8,
+    auto_increment: bool,
+    sound_divider: u8,
+    current_channel: usize,
+    channel_outputs: [u8; 8],
+}
+
+impl Namco163 {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        let last = last_bank(rom.prg_rom.len(), PRG_BANK_SIZE) as u8;
+        Namco163 {
+            prg_ram: prg_ram(&rom),
+            battery: rom.battery,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            chr_banks: [0; 8],
+            nametable_banks: [CIRAM_BANKS, CIRAM_BANKS + 1, CIRAM_BANKS, CIRAM_BANKS + 1],
+            prg_banks: [0, 1, last.saturating_sub(1)],
+            ram_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            sound_ram: [0; SOUND_RAM_SIZE],
+            sound_address: 0,
+            auto_increment: false,
+            sound_divider: 0,
+            current_channel: 7,
+            channel_outputs: [0; 8],
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x8000..=0xDFFF => (self.prg_banks[((addr - 0x8000) >> 13) as usize] & 0x3F) as usize,
+            _ => last_bank(self.prg_rom.len(), PRG_BANK_SIZE),
+        };
+        bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        let quarter = (addr - 0x6000) >> 11;
+        !self.prg_ram.is_empty()
+            && self.ram_protect & 0xF0 == 0x40
+            && self.ram_protect & (1 << quarter) == 0
+    }
+
+    // The CIRAM page a pattern table bank maps to, if it maps to one
+    fn ciram_page(&self, addr: u16) -> Option<usize> {
+        let bank = self.chr_banks[(addr >> 10) as usize & 7];
+        let disable_bit = if addr & 0x1000 == 0 { 0x40 } else { 0x80 };
+        if bank >= CIRAM_BANKS && self.prg_banks[1] & disable_bit == 0 {
+            Some((bank & 1) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn chr_offset(&self, bank: u8, addr: u16) -> usize {
+        bank_offset(self.chr.len(), bank as usize, CHR_BANK_SIZE, addr)
+    }
+
+    fn sound_ram_access(&mut self) -> usize {
+        let address = self.sound_address as usize;
+        if self.auto_increment {
+            self.sound_address = (self.sound_address + 1) & 0x7F;
+        }
+        address
+    }
+
+    fn enabled_channels(&self) -> usize {
+        ((self.sound_ram[0x7F] >> 4) & 0b111) as usize + 1
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let ram = &mut self.sound_ram;
+        let frequency = ram[base] as u32
+            | (ram[base + 2] as u32) << 8
+            | ((ram[base + 4] & 0b11) as u32) << 16;
+        let mut phase = ram[base + 1] as u32
+            | (ram[base + 3] as u32) << 8
+            | (ram[base + 5] as u32) << 16;
+        let length = 256 - (ram[base + 4] & 0xFC) as u32;
+        phase = (phase + frequency) % (length << 16);
+        ram[base + 1] = phase as u8;
+        ram[base + 3] = (phase >> 8) as u8;
+        ram[base + 5] = (phase >> 16) as u8;
+
+        let position = ((phase >> 16) + ram[base + 6] as u32) as usize & 0xFF;
+        let byte = ram[position / 2];
+        let sample = if position.is_multiple_of(2) { byte & 0x0F } else { byte >> 4 };
+        self.channel_outputs[channel] = sample * (ram[base + 7] & 0x0F);
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.sound_ram[self.sound_address as usize],
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => {
+                let address = self.sound_ram_access();
+                self.sound_ram[address]
+            }
+            _ => self.cpu_peek(addr),
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => {
+                let address = self.sound_ram_access();
+                self.sound_ram[address] = data;
+            }
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data & 0x7F) as u16) << 8;
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable(addr) => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = data,
+            0xC000..=0xDFFF => self.nametable_banks[((addr - 0xC000) >> 11) as usize] = data,
+            0xE000..=0xF7FF => self.prg_banks[((addr - 0xE000) >> 11) as usize] = data,
+            0xF800..=0xFFFF => {
+                self.ram_protect = data;
+                self.sound_address = data & 0x7F;
+                self.auto_increment = data & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr >> 10) as usize & 7];
+        self.chr[self.chr_offset(bank, addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let bank = self.chr_banks[(addr >> 10) as usize & 7];
+            let offset = self.chr_offset(bank, addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn pattern_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        match self.ciram_page(addr) {
+            Some(page) => vram[page * 0x400 + (addr & 0x3FF) as usize],
+            None => self.ppu_read(addr),
+        }
+    }
+
+    fn pattern_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        match self.ciram_page(addr) {
+            Some(page) => vram[page * 0x400 + (addr & 0x3FF) as usize] = data,
+            None => self.ppu_write(addr, data),
+        }
+    }
+
+    // Only meaningful while the nametable banks point at CIRAM the usual way
+    fn mirroring(&self) -> Mirroring {
+        if self.nametable_banks[1] & 1 == 1 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        let bank = self.nametable_banks[(addr >> 10) as usize & 3];
+        if bank >= CIRAM_BANKS {
+            vram[(bank & 1) as usize * 0x400 + (addr & 0x3FF) as usize]
+        } else {
+            self.chr[self.chr_offset(bank, addr)]
+        }
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        let bank = self.nametable_banks[(addr >> 10) as usize & 3];
+        if bank >= CIRAM_BANKS {
+            vram[(bank & 1) as usize * 0x400 + (addr & 0x3FF) as usize] = data;
+        } else if self.chr_is_ram {
+            let offset = self.chr_offset(bank, addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_clock(&mut self, cycles: u8) {
+        if self.irq_enabled && self.irq_counter < IRQ_COUNTER_MAX {
+            self.irq_counter = (self.irq_counter + cycles as u16).min(IRQ_COUNTER_MAX);
+            if self.irq_counter == IRQ_COUNTER_MAX {
+                self.irq_pending = true;
+            }
+        }
+
+        if self.prg_banks[0] & 0x40 != 0 {
+            return;
+        }
+        for _ in 0..cycles {
+            self.sound_divider += 1;
+            if self.sound_divider < CHANNEL_CYCLES {
+                continue;
+            }
+            self.sound_divider = 0;
+            self.update_channel(self.current_channel);
+            // Channels run from 7 down to the lowest enabled one
+            let lowest = 8 - self.enabled_channels();
+            self.current_channel = match self.current_channel {
+                channel if channel <= lowest => 7,
+                channel => channel - 1,
+            };
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.prg_banks[0] & 0x40 != 0 {
+            return 0.0;
+        }
+        let count = self.enabled_channels();
+        let sum: u32 = self.channel_outputs[8 - count..].iter().map(|&out| out as u32).sum();
+        sum as f32 / count as f32 * OUTPUT_SCALE
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    // Battery boards keep the sound RAM as well as the PRG-RAM, and some games store
+    // their saves in it
+    fn battery_data(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+        let mut data = self.prg_ram.clone();
+        data.extend_from_slice(&self.sound_ram);
+        Some(data)
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        if data.len() != self.prg_ram.len() + SOUND_RAM_SIZE {
+            return;
+        }
+        let (prg_ram, sound_ram) = data.split_at(self.prg_ram.len());
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.sound_ram.copy_from_slice(sound_ram);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for bank in self.chr_banks {
+            state.write_u8(bank);
+        }
+        for bank in self.nametable_banks {
+            state.write_u8(bank);
+        }
+        for bank in self.prg_banks {
+            state.write_u8(bank);
+        }
+        state.write_u8(self.ram_protect);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_bytes(&self.sound_ram);
+        state.write_u8(self.sound_address);
+        state.write_bool(self.auto_increment);
+        state.write_u8(self.sound_divider);
+        state.write_u8(self.current_channel as u8);
+        state.write_bytes(&self.channel_outputs);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        for bank in self.nametable_banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        for bank in self.prg_banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        self.ram_protect = state.read_u8()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        state.read_bytes_into(&mut self.sound_ram)?;
+        self.sound_address = state.read_u8()?;
+        self.auto_increment = state.read_bool()?;
+        self.sound_divider = state.read_u8()?;
+        self.current_channel = (state.read_u8()? & 7) as usize;
+        state.read_bytes_into(&mut self.channel_outputs)?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
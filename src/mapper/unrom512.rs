@@ -0,0 +1,272 @@
+use crate::cartridge::{Mirroring, Rom, RomFormat};
+use crate::mapper::{bank_offset, chr_memory, last_bank, nametable_offset, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const SECTOR_SIZE: usize = 0x1000;
+// iNES files can't give the CHR-RAM size, and the board always has 32 KiB
+const INES_CHR_RAM_SIZE: usize = 0x8000;
+// What the SST39SF040 answers in software ID mode
+const MANUFACTURER_ID: u8 = 0xBF;
+const DEVICE_ID: u8 = 0xB7;
+
+/* Where the flash chip is in its command sequence. Every command starts with $AA to
+ * $5555 and $55 to $2AAA; erases repeat that after the $80. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+    Ready,
+    Unlocked1,
+    Unlocked2,
+    Program,
+    EraseReady,
+    EraseUnlocked1,
+    EraseUnlocked2,
+}
+
+impl FlashState {
+    fn to_u8(self) -> u8 {
+        match self {
+            FlashState::Ready => 0,
+            FlashState::Unlocked1 => 1,
+            FlashState::Unlocked2 => 2,
+            FlashState::Program => 3,
+            FlashState::EraseReady => 4,
+            FlashState::EraseUnlocked1 => 5,
+            FlashState::EraseUnlocked2 => 6,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => FlashState::Unlocked1,
+            2 => FlashState::Unlocked2,
+            3 => FlashState::Program,
+            4 => FlashState::EraseReady,
+            5 => FlashState::EraseUnlocked1,
+            6 => FlashState::EraseUnlocked2,
+            _ => FlashState::Ready,
+        }
+    }
+}
+
+/* Mapper 30: UNROM-512, the homebrew board with up to 512 KiB of PRG and 32 KiB of
+ * CHR-RAM.
+ *
+ *  $8000-$FFFF  MCCPPPPP  one-screen page, 8 KiB CHR-RAM bank, 16 KiB PRG bank at $8000
+ *
+ * $C000 holds the last bank. Header byte 6 bits 3 and 0 pick the nametables: %00
+ * horizontal, %01 vertical, %10 one screen chosen by M, %11 four screens in the last
+ * 8 KiB of CHR-RAM.
+ *
+ * With the battery bit set the PRG is an SST39SF040 flash chip the game can rewrite
+ * to save. The register then only answers at $C000-$FFFF, and writes to $8000-$BFFF
+ * go to the flash at (PRG bank << 14) | (address & $3FFF):
+ *  $AA>$5555, $55>$2AAA, $A0>$5555, data>address  program a byte (bits only clear)
+ *  $AA>$5555, $55>$2AAA, $80>$5555, $AA>$5555, $55>$2AAA, $30>sector  erase 4 KiB
+ *  ... same with $10>$5555 instead  erase the whole chip
+ *  $AA>$5555, $55>$2AAA, $90>$5555  software ID mode until $F0 is written
+ * The sectors that differ from the ROM image are what gets saved. */
+pub struct Unrom512 {
+    prg_rom: Vec<u8>,
+    original_prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    nametable_flags: u8,
+    flashable: bool,
+    register: u8,
+    flash_state: FlashState,
+    software_id: bool,
+}
+
+impl Unrom512 {
+    pub fn new(mut rom: Rom) -> Self {
+        let (mut chr, chr_is_ram) = chr_memory(&mut rom);
+        if chr_is_ram && rom.format == RomFormat::INes {
+            chr = vec![0; INES_CHR_RAM_SIZE];
+        }
+        Unrom512 {
+            original_prg: if rom.battery { rom.prg_rom.clone() } else { Vec::new() },
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            nametable_flags: rom.nametable_flags,
+            flashable: rom.battery,
+            register: 0,
+            flash_state: FlashState::Ready,
+            software_id: false,
+        }
+    }
+
+    fn prg_bank(&self) -> usize {
+        (self.register & 0x1F) as usize
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = ((self.register >> 5) & 0b11) as usize;
+        bank_offset(self.chr.len(), bank, CHR_BANK_SIZE, addr)
+    }
+
+    fn four_screen(&self) -> bool {
+        self.nametable_flags == 0b1001
+    }
+
+    /* Four-screen nametables sit in the last 8 KiB of CHR-RAM, wrapping when there is less */
+    fn four_screen_offset(&self, addr: u16) -> usize {
+        let len = self.chr.len();
+        (len.saturating_sub(CHR_BANK_SIZE) + (addr & 0x0FFF) as usize) % len
+    }
+
+    fn write_flash(&mut self, addr: u16, data: u8) {
+        let bus_addr = self.prg_bank() << 14 | (addr & 0x3FFF) as usize;
+        let flash_addr = bus_addr % self.prg_rom.len();
+        // Commands only decode the low 15 address bits, even on chips too small to hold them
+        let command = |expected: usize| bus_addr & 0x7FFF == expected;
+        self.flash_state = match (self.flash_state, data) {
+            (_, 0xF0) => {
+                self.software_id = false;
+                FlashState::Ready
+            }
+            (FlashState::Ready, 0xAA) if command(0x5555) => FlashState::Unlocked1,
+            (FlashState::Unlocked1, 0x55) if command(0x2AAA) => FlashState::Unlocked2,
+            (FlashState::Unlocked2, 0xA0) if command(0x5555) => FlashState::Program,
+            (FlashState::Unlocked2, 0x80) if command(0x5555) => FlashState::EraseReady,
+            (FlashState::Unlocked2, 0x90) if command(0x5555) => {
+                self.software_id = true;
+                FlashState::Ready
+            }
+            (FlashState::Program, _) => {
+                self.prg_rom[flash_addr] &= data;
+                FlashState::Ready
+            }
+            (FlashState::EraseReady, 0xAA) if command(0x5555) => FlashState::EraseUnlocked1,
+            (FlashState::EraseUnlocked1, 0x55) if command(0x2AAA) => FlashState::EraseUnlocked2,
+            (FlashState::EraseUnlocked2, 0x30) => {
+                let sector = flash_addr & !(SECTOR_SIZE - 1);
+                let end = (sector + SECTOR_SIZE).min(self.prg_rom.len());
+                self.prg_rom[sector..end].fill(0xFF);
+                FlashState::Ready
+            }
+            (FlashState::EraseUnlocked2, 0x10) if command(0x5555) => {
+                self.prg_rom.fill(0xFF);
+                FlashState::Ready
+            }
+            _ => FlashState::Ready,
+        };
+    }
+}
+
+impl Mapper for Unrom512 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank(),
+            0xC000..=0xFFFF => last_bank(self.prg_rom.len(), PRG_BANK_SIZE),
+            _ => return 0,
+        };
+        if self.software_id {
+            return if addr & 1 == 0 { MANUFACTURER_ID } else { DEVICE_ID };
+        }
+        self.prg_rom[bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)]
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xBFFF if self.flashable => self.write_flash(addr, data),
+            0x8000..=0xFFFF => self.register = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_flags {
+            0b0000 => Mirroring::Horizontal,
+            0b0001 => Mirroring::Vertical,
+            0b1000 if self.register & 0x80 != 0 => Mirroring::SingleScreenUpper,
+            0b1000 => Mirroring::SingleScreenLower,
+            _ => Mirroring::FourScreen,
+        }
+    }
+
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        if self.four_screen() {
+            return self.chr[self.four_screen_offset(addr)];
+        }
+        vram[nametable_offset(self.mirroring(), addr)]
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        if self.four_screen() {
+            let offset = self.four_screen_offset(addr);
+            self.chr[offset] = data;
+            return;
+        }
+        vram[nametable_offset(self.mirroring(), addr)] = data;
+    }
+
+    /* Each rewritten sector as its number followed by its contents, so the save only
+     * holds what the game changed */
+    fn battery_data(&self) -> Option<Vec<u8>> {
+        if !self.flashable {
+            return None;
+        }
+        let mut data = StateWriter::new();
+        let sectors = self.prg_rom.chunks(SECTOR_SIZE).zip(self.original_prg.chunks(SECTOR_SIZE));
+        for (index, (sector, original)) in sectors.enumerate() {
+            if sector != original {
+                data.write_u16(index as u16);
+                data.write_bytes(sector);
+            }
+        }
+        Some(data.into_bytes())
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        let mut reader = StateReader::new(data);
+        while let Ok(index) = reader.read_u16() {
+            let start = index as usize * SECTOR_SIZE;
+            if start >= self.prg_rom.len() {
+                return;
+            }
+            let end = (start + SECTOR_SIZE).min(self.prg_rom.len());
+            if reader.read_bytes_into(&mut self.prg_rom[start..end]).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        state.write_u8(self.flash_state.to_u8());
+        state.write_bool(self.software_id);
+        if self.flashable {
+            state.write_bytes(&self.prg_rom);
+        }
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u8()?;
+        self.flash_state = FlashState::from_u8(state.read_u8()?);
+        self.software_id = state.read_bool()?;
+        if self.flashable {
+            state.read_bytes_into(&mut self.prg_rom)?;
+        }
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
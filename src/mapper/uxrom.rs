@@ -0,0 +1,83 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, chr_memory, last_bank, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/* Mapper 2: UxROM (UNROM, UOROM).
+ * Any write to $8000-$FFFF picks the 16 KiB PRG bank at $8000; the last bank is fixed at
+ * $C000. CHR is 8 KiB of unbanked RAM and mirroring is soldered on the board.
+ * The ROM drives the data bus during the write, so the written value is ANDed with the
+ * byte at that address unless the header says the board avoids that (submapper 1). */
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    prg_bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Uxrom {
+            bus_conflicts: rom.submapper != 1,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            mirroring: rom.screen_mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize,
+            0xC000..=0xFFFF => last_bank(self.prg_rom.len(), PRG_BANK_SIZE),
+            _ => return 0,
+        };
+        self.prg_rom[bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)]
+    }
+
+    fn cpu_write(&mut self, addr: u16, mut data: u8) {
+        if addr >= 0x8000 {
+            if self.bus_conflicts {
+                data &= self.cpu_peek(addr);
+            }
+            self.prg_bank = data;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_bank);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read_u8()?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{bank_offset, chr_memory, last_bank, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/* Mappers 21, 22, 23 and 25: Konami VRC2 and VRC4.
+ *
+ * Each register occupies four addresses, told apart by two chip pins that different
+ * boards wire to different CPU address lines, so the same chip turns up as VRC2a-c and
+ * VRC4a-f. The NES 2.0 submapper says which wiring a board has; without it both
+ * candidates for the mapper number are listened to at once. Mapper 23 also covers VRC2b,
+ * which without a submapper is picked only when the header gives the board no PRG-RAM,
+ * so iNES files (always assumed to have RAM) come up as VRC4.
+ *
+ *  $8000  PRG bank at $8000 (or $C000 in swap mode)
+ *  $9000  mirroring          VRC2: 1 bit, VRC4: vertical/horizontal/single screen
+ *  $9002  VRC4 PRG swap mode (bit 1); on VRC2 still the mirroring bit
+ *  $A000  PRG bank at $A000
+ *  $B000-$E003  eight 1 KiB CHR banks, written a nibble at a time
+ *  $F000-$F003  VRC4 IRQ latch low/high nibble, control, acknowledge
+ *
+ * The other two 8 KiB PRG windows hold the second-to-last and last bank. VRC2 has no
+ * IRQ and no swap mode, and on VRC2a the CHR banks ignore their lowest bit. */
+pub struct Vrc4 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    vrc2: bool,
+    chr_shift: u8,
+    // CPU address lines wired to the chip's A0 and A1 pins
+    a0_lines: u16,
+    a1_lines: u16,
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: u8,
+    // VRC2 boards without RAM keep a single bit at $6000
+    latch: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(mut rom: Rom) -> Self {
+        // VRC2b boards have no RAM, just the $6000 latch
+        let no_ram = rom.prg_ram_size + rom.prg_nvram_size == 0 && rom.trainer.is_none();
+        let (vrc2, chr_shift, a0_lines, a1_lines) = match (rom.mapper, rom.submapper) {
+            (21, 1) => (false, 0, 0x02, 0x04),
+            (21, 2) => (false, 0, 0x40, 0x80),
+            (21, _) => (false, 0, 0x42, 0x84),
+            (22, _) => (true, 1, 0x02, 0x01),
+            (23, 1) => (false, 0, 0x01, 0x02),
+            (23, 2) => (false, 0, 0x04, 0x08),
+            (23, 3) => (true, 0, 0x01, 0x02),
+            (23, _) => (no_ram, 0, 0x05, 0x0A),
+            (25, 1) => (false, 0, 0x02, 0x01),
+            (25, 2) => (false, 0, 0x08, 0x04),
+            (25, 3) => (true, 0, 0x02, 0x01),
+            (_, _) => (false, 0, 0x0A, 0x05),
+        };
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Vrc4 {
+            prg_ram: prg_ram(&rom),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            vrc2,
+            chr_shift,
+            a0_lines,
+            a1_lines,
+            prg_banks: [0, 1],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            latch: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    // Register address with the board's wiring undone: $x000-$x003
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.a0_lines != 0) as u16;
+        let a1 = (addr & self.a1_lines != 0) as u16;
+        (addr & 0xF000) | a1 << 1 | a0
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let last = last_bank(self.prg_rom.len(), PRG_BANK_SIZE);
+        let bank = match (addr, self.prg_swap) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => last.saturating_sub(1),
+            (0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+            _ => last,
+        };
+        bank_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 10) as usize & 7] >> self.chr_shift;
+        bank_offset(self.chr.len(), bank as usize, CHR_BANK_SIZE, addr)
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x6000..=0x6FFF if self.vrc2 => self.latch,
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if !self.prg_ram.is_empty() && addr >= 0x6000 {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            } else if self.vrc2 && (0x6000..=0x6FFF).contains(&addr) {
+                self.latch = data & 1;
+            }
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => self.mirroring = data & 1,
+            0x9000..=0x9001 => self.mirroring = data & 0b11,
+            0x9002..=0x9003 if !self.vrc2 => self.prg_swap = data & 0b10 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = data & 0x1F,
+            register @ 0xB000..=0xE003 => {
+                // Each register pair is one bank: even address low nibble, odd high bits
+                let bank = (((register >> 12) - 0xB) * 2 + ((register >> 1) & 1)) as usize;
+                let value = self.chr_banks[bank];
+                self.chr_banks[bank] = if register & 1 == 0 {
+                    (value & 0x1F0) | (data & 0x0F) as u16
+                } else {
+                    (value & 0x00F) | ((data & 0x1F) as u16) << 4
+                };
+            }
+            0xF000 if !self.vrc2 => self.irq.write_latch_low(data),
+            0xF001 if !self.vrc2 => self.irq.write_latch_high(data),
+            0xF002 if !self.vrc2 => self.irq.write_control(data),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn cpu_clock(&mut self, cycles: u8) {
+        self.irq.clock(cycles);
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_banks[0]);
+        state.write_u8(self.prg_banks[1]);
+        state.write_bool(self.prg_swap);
+        for bank in self.chr_banks {
+            state.write_u16(bank);
+        }
+        state.write_u8(self.mirroring);
+        state.write_u8(self.latch);
+        self.irq.save_state(state);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_banks[0] = state.read_u8()?;
+        self.prg_banks[1] = state.read_u8()?;
+        self.prg_swap = state.read_bool()?;
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.read_u16()?;
+        }
+        self.mirroring = state.read_u8()?;
+        self.latch = state.read_u8()?;
+        self.irq.load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{bank_offset, chr_memory, last_bank, prg_ram, Mapper};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE_16K: usize = 0x4000;
+const PRG_BANK_SIZE_8K: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// The DAC is linear; one output level is scaled so a full-volume pulse comes out about
+// as loud as one of the APU's
+const OUTPUT_STEP: f32 = 0.00996;
+
+/* One of the two VRC6 pulse channels.
+ *
+ *  +0  MDDD VVVV  ignore duty (constant output), duty 1/16-8/16, volume
+ *  +1  period low 8 bits
+ *  +2  E--- PPPP  enable, period high 4 bits */
+#[derive(Default)]
+struct Pulse {
+    constant: bool,
+    duty: u8,
+    volume: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.constant = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.constant);
+        state.write_u8(self.duty);
+        state.write_u8(self.volume);
+        state.write_u16(self.period);
+        state.write_bool(self.enabled);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.constant = state.read_bool()?;
+        self.duty = state.read_u8()?;
+        self.volume = state.read_u8()?;
+        self.period = state.read_u16()?;
+        self.enabled = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()?;
+        Ok(())
+    }
+}
+
+/* The VRC6 sawtooth: an accumulator that adds the rate on every other timer clock and
+ * is cleared after the seventh clock pair, so the top five bits ramp up six times.
+ *
+ *  $B000  --RR RRRR  accumulator rate
+ *  $B001  period low 8 bits
+ *  $B002  E--- PPPP  enable, period high 4 bits */
+#[derive(Default)]
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rate);
+        state.write_u16(self.period);
+        state.write_bool(self.enabled);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+        state.write_u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rate = state.read_u8()?;
+        self.period = state.read_u16()?;
+        self.enabled = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()?;
+        self.accumulator = state.read_u8()?;
+        Ok(())
+    }
+}
+
+/* Mappers 24 and 26: Konami VRC6 (VRC6a and VRC6b, which swap the A0 and A1 lines).
+ *
+ *  $8000-$8003  16 KiB PRG bank at $8000
+ *  $9000-$9002  pulse 1          $9003  audio control  ---- -ABH  x256, x16, halt
+ *  $A000-$A002  pulse 2
+ *  $B000-$B002  sawtooth         $B003  banking mode   R--- MM--  PRG-RAM enable, mirroring
+ *  $C000-$C003  8 KiB PRG bank at $C000
+ *  $D000-$E003  eight 1 KiB CHR banks
+ *  $F000  IRQ latch   $F001  IRQ control   $F002  IRQ acknowledge
+ *
+ * $E000-$FFFF holds the last 8 KiB bank. Only the banking mode every released game
+ * uses ($B003 = %R010MM00: 1 KiB CHR banks, CIRAM nametables) is implemented. */
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    swap_address_lines: bool,
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_banks: [u8; 8],
+    banking_mode: u8,
+    audio_control: u8,
+    pulses: [Pulse; 2],
+    sawtooth: Sawtooth,
+    irq: VrcIrq,
+}
+
+impl Vrc6 {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(&mut rom);
+        Vrc6 {
+            prg_ram: prg_ram(&rom),
+            swap_address_lines: rom.mapper == 26,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            banking_mode: 0,
+            audio_control: 0,
+            pulses: [Pulse::default(), Pulse::default()],
+            sawtooth: Sawtooth::default(),
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        if self.swap_address_lines {
+            (addr & 0xF000) | (addr & 1) << 1 | (addr >> 1) & 1
+        } else {
+            addr & 0xF003
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let len = self.prg_rom.len();
+        match addr {
+            0x8000..=0xBFFF => bank_offset(len, self.prg_16k as usize, PRG_BANK_SIZE_16K, addr),
+            0xC000..=0xDFFF => bank_offset(len, self.prg_8k as usize, PRG_BANK_SIZE_8K, addr),
+            _ => bank_offset(len, last_bank(len, PRG_BANK_SIZE_8K), PRG_BANK_SIZE_8K, addr),
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 10) as usize & 7];
+        bank_offset(self.chr.len(), bank as usize, CHR_BANK_SIZE, addr)
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.banking_mode & 0x80 != 0
+    }
+
+    // $9003 bit 2 speeds the timers up 256 times, bit 1 16 times, by dropping period bits
+    fn frequency_shift(&self) -> u8 {
+        if self.audio_control & 0b100 != 0 {
+            8
+        } else if self.audio_control & 0b010 != 0 {
+            4
+        } else {
+            0
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if addr >= 0x6000 && self.prg_ram_enabled() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_16k = data & 0x0F,
+            0x9003 => self.audio_control = data & 0b111,
+            register @ 0x9000..=0x9002 => self.pulses[0].write(register & 0b11, data),
+            register @ 0xA000..=0xA002 => self.pulses[1].write(register & 0b11, data),
+            register @ 0xB000..=0xB002 => self.sawtooth.write(register & 0b11, data),
+            0xB003 => self.banking_mode = data,
+            0xC000..=0xC003 => self.prg_8k = data & 0x1F,
+            register @ 0xD000..=0xE003 => {
+                let bank = ((register >> 12) - 0xD) * 4 + (register & 0b11);
+                self.chr_banks[bank as usize] = data;
+            }
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking_mode >> 2) & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn cpu_clock(&mut self, cycles: u8) {
+        self.irq.clock(cycles);
+        if self.audio_control & 1 != 0 {
+            return;
+        }
+        let shift = self.frequency_shift();
+        for _ in 0..cycles {
+            self.pulses[0].clock(shift);
+            self.pulses[1].clock(shift);
+            self.sawtooth.clock(shift);
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let level = self.pulses[0].output() + self.pulses[1].output() + self.sawtooth.output();
+        level as f32 * OUTPUT_STEP
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_16k);
+        state.write_u8(self.prg_8k);
+        for bank in self.chr_banks {
+            state.write_u8(bank);
+        }
+        state.write_u8(self.banking_mode);
+        state.write_u8(self.audio_control);
+        for pulse in &self.pulses {
+            pulse.save_state(state);
+        }
+        self.sawtooth.save_state(state);
+        self.irq.save_state(state);
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_16k = state.read_u8()?;
+        self.prg_8k = state.read_u8()?;
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.read_u8()?;
+        }
+        self.banking_mode = state.read_u8()?;
+        self.audio_control = state.read_u8()?;
+        for pulse in self.pulses.iter_mut() {
+            pulse.load_state(state)?;
+        }
+        self.sawtooth.load_state(state)?;
+        self.irq.load_state(state)?;
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::f32::consts::PI;
+
+// NTSC CPU clock, which is also the rate the sound hardware changes level at
+pub const CPU_CLOCK_HZ: f64 = 1_789_773.0;
+
+// The console's output stage blocks DC and rolls off below about 37 Hz
+const HIGH_PASS_HZ: f32 = 37.0;
+
+/* Turns the sound level the console puts out on each CPU cycle into samples at the
+ * host's rate. Every sample is the average level over its share of cycles, which keeps
+ * tones above the host's range from folding back down too badly, and then goes through
+ * a first-order high-pass so silence sits at 0.0 rather than wherever the channels
+ * happen to rest. */
+pub struct Mixer {
+    cycles_per_sample: f64,
+    // How far into the current sample we are, and the levels summed over it so far
+    phase: f64,
+    sum: f32,
+    count: u32,
+    high_pass: f32,
+    last_level: f32,
+    last_sample: f32,
+    samples: Vec<f32>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Self {
+        let rc = 1.0 / (2.0 * PI * HIGH_PASS_HZ);
+        let dt = 1.0 / sample_rate as f32;
+        Mixer {
+            cycles_per_sample: CPU_CLOCK_HZ / sample_rate as f64,
+            phase: 0.0,
+            sum: 0.0,
+            count: 0,
+            high_pass: rc / (rc + dt),
+            last_level: 0.0,
+            last_sample: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    // `cycles` CPU cycles spent at output level `level`
+    pub fn clock(&mut self, level: f32, cycles: u8) {
+        for _ in 0..cycles {
+            self.sum += level;
+            self.count += 1;
+            self.phase += 1.0;
+            if self.phase >= self.cycles_per_sample {
+                self.phase -= self.cycles_per_sample;
+                let level = self.sum / self.count as f32;
+                self.last_sample = self.high_pass * (self.last_sample + level - self.last_level);
+                self.last_level = level;
+                self.samples.push(self.last_sample);
+                self.sum = 0.0;
+                self.count = 0;
+            }
+        }
+    }
+
+    // The samples finished since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
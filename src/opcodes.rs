@@ -0,0 +1,511 @@
+use crate::cpu::{AddressingMode, CpuVariant};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Mnemonic {
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC,
+    CLD, CLI, CLV, CMP, CPX, CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JMP,
+    JSR, LDA, LDX, LDY, LSR, NOP, ORA, PHA, PHP, PLA, PLP, ROL, ROR, RTI,
+    RTS, SBC, SEC, SED, SEI, STA, STX, STY, TAX, TAY, TSX, TXA, TXS, TYA,
+
+    // Unofficial
+    AHX, ALR, ANC, ARR, AXS, DCP, ISB, JAM, LAS, LAX, LXA, RLA, RRA, SAX,
+    SHX, SHY, SLO, SRE, TAS, XAA,
+
+    // 65C02
+    BBR, BBS, BRA, PHX, PHY, PLX, PLY, RMB, SMB, STP, STZ, TRB, TSB, WAI,
+}
+
+pub struct OpCode {
+    pub code: u8,
+    pub mnemonic: Mnemonic,
+    pub len: u8,
+    pub cycles: u8,
+    pub mode: AddressingMode,
+    /* Indexed reads take one extra cycle when the effective address lands on another page */
+    pub page_cross_penalty: bool,
+    /* Undocumented (NMOS) or reserved (65C02) opcode, subject to the CPU's IllegalOpcodePolicy */
+    pub unofficial: bool,
+}
+
+impl OpCode {
+    fn new(code: u8, mnemonic: Mnemonic, len: u8, cycles: u8, mode: AddressingMode, page_cross_penalty: bool) -> Self {
+        OpCode {
+            code,
+            mnemonic,
+            len,
+            cycles,
+            mode,
+            page_cross_penalty,
+            unofficial: false,
+        }
+    }
+
+    fn unofficial(code: u8, mnemonic: Mnemonic, len: u8, cycles: u8, mode: AddressingMode, page_cross_penalty: bool) -> Self {
+        OpCode {
+            unofficial: true,
+            ..OpCode::new(code, mnemonic, len, cycles, mode, page_cross_penalty)
+        }
+    }
+}
+
+lazy_static! {
+    /* code, mnemonic, length in bytes, base cycles, addressing mode, +1 cycle on page cross */
+    pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
+        OpCode::new(0x00, Mnemonic::BRK, 1, 7, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xea, Mnemonic::NOP, 1, 2, AddressingMode::NoneAddressing, false),
+
+        OpCode::new(0x69, Mnemonic::ADC, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0x65, Mnemonic::ADC, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x75, Mnemonic::ADC, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x6d, Mnemonic::ADC, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0x7d, Mnemonic::ADC, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::new(0x79, Mnemonic::ADC, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::new(0x61, Mnemonic::ADC, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::new(0x71, Mnemonic::ADC, 2, 5, AddressingMode::Indirect_Y, true),
+
+        OpCode::new(0xe9, Mnemonic::SBC, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0xe5, Mnemonic::SBC, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0xf5, Mnemonic::SBC, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0xed, Mnemonic::SBC, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0xfd, Mnemonic::SBC, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::new(0xf9, Mnemonic::SBC, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::new(0xe1, Mnemonic::SBC, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::new(0xf1, Mnemonic::SBC, 2, 5, AddressingMode::Indirect_Y, true),
+
+        OpCode::new(0x29, Mnemonic::AND, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0x25, Mnemonic::AND, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x35, Mnemonic::AND, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x2d, Mnemonic::AND, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0x3d, Mnemonic::AND, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::new(0x39, Mnemonic::AND, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::new(0x21, Mnemonic::AND, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::new(0x31, Mnemonic::AND, 2, 5, AddressingMode::Indirect_Y, true),
+
+        OpCode::new(0x49, Mnemonic::EOR, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0x45, Mnemonic::EOR, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x55, Mnemonic::EOR, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x4d, Mnemonic::EOR, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0x5d, Mnemonic::EOR, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::new(0x59, Mnemonic::EOR, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::new(0x41, Mnemonic::EOR, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::new(0x51, Mnemonic::EOR, 2, 5, AddressingMode::Indirect_Y, true),
+
+        OpCode::new(0x09, Mnemonic::ORA, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0x05, Mnemonic::ORA, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x15, Mnemonic::ORA, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x0d, Mnemonic::ORA, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0x1d, Mnemonic::ORA, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::new(0x19, Mnemonic::ORA, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::new(0x01, Mnemonic::ORA, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::new(0x11, Mnemonic::ORA, 2, 5, AddressingMode::Indirect_Y, true),
+
+        OpCode::new(0x0a, Mnemonic::ASL, 1, 2, AddressingMode::Accumulator, false),
+        OpCode::new(0x06, Mnemonic::ASL, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x16, Mnemonic::ASL, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x0e, Mnemonic::ASL, 3, 6, AddressingMode::Absolute, false),
+        OpCode::new(0x1e, Mnemonic::ASL, 3, 7, AddressingMode::Absolute_X, false),
+
+        OpCode::new(0x4a, Mnemonic::LSR, 1, 2, AddressingMode::Accumulator, false),
+        OpCode::new(0x46, Mnemonic::LSR, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x56, Mnemonic::LSR, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x4e, Mnemonic::LSR, 3, 6, AddressingMode::Absolute, false),
+        OpCode::new(0x5e, Mnemonic::LSR, 3, 7, AddressingMode::Absolute_X, false),
+
+        OpCode::new(0x2a, Mnemonic::ROL, 1, 2, AddressingMode::Accumulator, false),
+        OpCode::new(0x26, Mnemonic::ROL, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x36, Mnemonic::ROL, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x2e, Mnemonic::ROL, 3, 6, AddressingMode::Absolute, false),
+        OpCode::new(0x3e, Mnemonic::ROL, 3, 7, AddressingMode::Absolute_X, false),
+
+        OpCode::new(0x6a, Mnemonic::ROR, 1, 2, AddressingMode::Accumulator, false),
+        OpCode::new(0x66, Mnemonic::ROR, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x76, Mnemonic::ROR, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x6e, Mnemonic::ROR, 3, 6, AddressingMode::Absolute, false),
+        OpCode::new(0x7e, Mnemonic::ROR, 3, 7, AddressingMode::Absolute_X, false),
+
+        OpCode::new(0xe6, Mnemonic::INC, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0xf6, Mnemonic::INC, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0xee, Mnemonic::INC, 3, 6, AddressingMode::Absolute, false),
+        OpCode::new(0xfe, Mnemonic::INC, 3, 7, AddressingMode::Absolute_X, false),
+
+        OpCode::new(0xe8, Mnemonic::INX, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xc8, Mnemonic::INY, 1, 2, AddressingMode::NoneAddressing, false),
+
+        OpCode::new(0xc6, Mnemonic::DEC, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0xd6, Mnemonic::DEC, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0xce, Mnemonic::DEC, 3, 6, AddressingMode::Absolute, false),
+        OpCode::new(0xde, Mnemonic::DEC, 3, 7, AddressingMode::Absolute_X, false),
+
+        OpCode::new(0xca, Mnemonic::DEX, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x88, Mnemonic::DEY, 1, 2, AddressingMode::NoneAddressing, false),
+
+        OpCode::new(0xc9, Mnemonic::CMP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0xc5, Mnemonic::CMP, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0xd5, Mnemonic::CMP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0xcd, Mnemonic::CMP, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0xdd, Mnemonic::CMP, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::new(0xd9, Mnemonic::CMP, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::new(0xc1, Mnemonic::CMP, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::new(0xd1, Mnemonic::CMP, 2, 5, AddressingMode::Indirect_Y, true),
+
+        OpCode::new(0xe0, Mnemonic::CPX, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0xe4, Mnemonic::CPX, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0xec, Mnemonic::CPX, 3, 4, AddressingMode::Absolute, false),
+
+        OpCode::new(0xc0, Mnemonic::CPY, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0xc4, Mnemonic::CPY, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0xcc, Mnemonic::CPY, 3, 4, AddressingMode::Absolute, false),
+
+        OpCode::new(0x4c, Mnemonic::JMP, 3, 3, AddressingMode::Absolute, false),
+        OpCode::new(0x6c, Mnemonic::JMP, 3, 5, AddressingMode::Indirect, false),
+
+        OpCode::new(0x20, Mnemonic::JSR, 3, 6, AddressingMode::Absolute, false),
+        OpCode::new(0x60, Mnemonic::RTS, 1, 6, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x40, Mnemonic::RTI, 1, 6, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xd0, Mnemonic::BNE, 2, 2, AddressingMode::Relative, false),
+        OpCode::new(0x70, Mnemonic::BVS, 2, 2, AddressingMode::Relative, false),
+        OpCode::new(0x50, Mnemonic::BVC, 2, 2, AddressingMode::Relative, false),
+        OpCode::new(0x30, Mnemonic::BMI, 2, 2, AddressingMode::Relative, false),
+        OpCode::new(0xf0, Mnemonic::BEQ, 2, 2, AddressingMode::Relative, false),
+        OpCode::new(0xb0, Mnemonic::BCS, 2, 2, AddressingMode::Relative, false),
+        OpCode::new(0x90, Mnemonic::BCC, 2, 2, AddressingMode::Relative, false),
+        OpCode::new(0x10, Mnemonic::BPL, 2, 2, AddressingMode::Relative, false),
+
+        OpCode::new(0x24, Mnemonic::BIT, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x2c, Mnemonic::BIT, 3, 4, AddressingMode::Absolute, false),
+
+        OpCode::new(0xa9, Mnemonic::LDA, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0xa5, Mnemonic::LDA, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0xb5, Mnemonic::LDA, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0xad, Mnemonic::LDA, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0xbd, Mnemonic::LDA, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::new(0xb9, Mnemonic::LDA, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::new(0xa1, Mnemonic::LDA, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::new(0xb1, Mnemonic::LDA, 2, 5, AddressingMode::Indirect_Y, true),
+
+        OpCode::new(0xa2, Mnemonic::LDX, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0xa6, Mnemonic::LDX, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0xb6, Mnemonic::LDX, 2, 4, AddressingMode::ZeroPage_Y, false),
+        OpCode::new(0xae, Mnemonic::LDX, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0xbe, Mnemonic::LDX, 3, 4, AddressingMode::Absolute_Y, true),
+
+        OpCode::new(0xa0, Mnemonic::LDY, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0xa4, Mnemonic::LDY, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0xb4, Mnemonic::LDY, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0xac, Mnemonic::LDY, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0xbc, Mnemonic::LDY, 3, 4, AddressingMode::Absolute_X, true),
+
+        OpCode::new(0x85, Mnemonic::STA, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x95, Mnemonic::STA, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x8d, Mnemonic::STA, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0x9d, Mnemonic::STA, 3, 5, AddressingMode::Absolute_X, false),
+        OpCode::new(0x99, Mnemonic::STA, 3, 5, AddressingMode::Absolute_Y, false),
+        OpCode::new(0x81, Mnemonic::STA, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::new(0x91, Mnemonic::STA, 2, 6, AddressingMode::Indirect_Y, false),
+
+        OpCode::new(0x86, Mnemonic::STX, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x96, Mnemonic::STX, 2, 4, AddressingMode::ZeroPage_Y, false),
+        OpCode::new(0x8e, Mnemonic::STX, 3, 4, AddressingMode::Absolute, false),
+
+        OpCode::new(0x84, Mnemonic::STY, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x94, Mnemonic::STY, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x8c, Mnemonic::STY, 3, 4, AddressingMode::Absolute, false),
+
+        OpCode::new(0xd8, Mnemonic::CLD, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x58, Mnemonic::CLI, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xb8, Mnemonic::CLV, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x18, Mnemonic::CLC, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x38, Mnemonic::SEC, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x78, Mnemonic::SEI, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xf8, Mnemonic::SED, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xaa, Mnemonic::TAX, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xa8, Mnemonic::TAY, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xba, Mnemonic::TSX, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x8a, Mnemonic::TXA, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x9a, Mnemonic::TXS, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x98, Mnemonic::TYA, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x48, Mnemonic::PHA, 1, 3, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x68, Mnemonic::PLA, 1, 4, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x08, Mnemonic::PHP, 1, 3, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x28, Mnemonic::PLP, 1, 4, AddressingMode::NoneAddressing, false),
+
+        /* Unofficial opcodes, with their NMOS behaviour */
+        OpCode::unofficial(0x1a, Mnemonic::NOP, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x3a, Mnemonic::NOP, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x5a, Mnemonic::NOP, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x7a, Mnemonic::NOP, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xda, Mnemonic::NOP, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xfa, Mnemonic::NOP, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x80, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x82, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x89, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xc2, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xe2, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x04, Mnemonic::NOP, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x44, Mnemonic::NOP, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x64, Mnemonic::NOP, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x14, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x34, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x54, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x74, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0xd4, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0xf4, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x0c, Mnemonic::NOP, 3, 4, AddressingMode::Absolute, false),
+        OpCode::unofficial(0x1c, Mnemonic::NOP, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::unofficial(0x3c, Mnemonic::NOP, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::unofficial(0x5c, Mnemonic::NOP, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::unofficial(0x7c, Mnemonic::NOP, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::unofficial(0xdc, Mnemonic::NOP, 3, 4, AddressingMode::Absolute_X, true),
+        OpCode::unofficial(0xfc, Mnemonic::NOP, 3, 4, AddressingMode::Absolute_X, true),
+
+        OpCode::unofficial(0x02, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x12, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x22, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x32, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x42, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x52, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x62, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x72, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x92, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xb2, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xd2, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xf2, Mnemonic::JAM, 1, 2, AddressingMode::NoneAddressing, false),
+
+        OpCode::unofficial(0xa7, Mnemonic::LAX, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0xb7, Mnemonic::LAX, 2, 4, AddressingMode::ZeroPage_Y, false),
+        OpCode::unofficial(0xaf, Mnemonic::LAX, 3, 4, AddressingMode::Absolute, false),
+        OpCode::unofficial(0xbf, Mnemonic::LAX, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::unofficial(0xa3, Mnemonic::LAX, 2, 6, AddressingMode::Indirect_X, false),
+        OpCode::unofficial(0xb3, Mnemonic::LAX, 2, 5, AddressingMode::Indirect_Y, true),
+
+        OpCode::unofficial(0x87, Mnemonic::SAX, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x97, Mnemonic::SAX, 2, 4, AddressingMode::ZeroPage_Y, false),
+        OpCode::unofficial(0x8f, Mnemonic::SAX, 3, 4, AddressingMode::Absolute, false),
+        OpCode::unofficial(0x83, Mnemonic::SAX, 2, 6, AddressingMode::Indirect_X, false),
+
+        OpCode::unofficial(0xc7, Mnemonic::DCP, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0xd7, Mnemonic::DCP, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0xcf, Mnemonic::DCP, 3, 6, AddressingMode::Absolute, false),
+        OpCode::unofficial(0xdf, Mnemonic::DCP, 3, 7, AddressingMode::Absolute_X, false),
+        OpCode::unofficial(0xdb, Mnemonic::DCP, 3, 7, AddressingMode::Absolute_Y, false),
+        OpCode::unofficial(0xc3, Mnemonic::DCP, 2, 8, AddressingMode::Indirect_X, false),
+        OpCode::unofficial(0xd3, Mnemonic::DCP, 2, 8, AddressingMode::Indirect_Y, false),
+
+        OpCode::unofficial(0xe7, Mnemonic::ISB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0xf7, Mnemonic::ISB, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0xef, Mnemonic::ISB, 3, 6, AddressingMode::Absolute, false),
+        OpCode::unofficial(0xff, Mnemonic::ISB, 3, 7, AddressingMode::Absolute_X, false),
+        OpCode::unofficial(0xfb, Mnemonic::ISB, 3, 7, AddressingMode::Absolute_Y, false),
+        OpCode::unofficial(0xe3, Mnemonic::ISB, 2, 8, AddressingMode::Indirect_X, false),
+        OpCode::unofficial(0xf3, Mnemonic::ISB, 2, 8, AddressingMode::Indirect_Y, false),
+
+        OpCode::unofficial(0x07, Mnemonic::SLO, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x17, Mnemonic::SLO, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x0f, Mnemonic::SLO, 3, 6, AddressingMode::Absolute, false),
+        OpCode::unofficial(0x1f, Mnemonic::SLO, 3, 7, AddressingMode::Absolute_X, false),
+        OpCode::unofficial(0x1b, Mnemonic::SLO, 3, 7, AddressingMode::Absolute_Y, false),
+        OpCode::unofficial(0x03, Mnemonic::SLO, 2, 8, AddressingMode::Indirect_X, false),
+        OpCode::unofficial(0x13, Mnemonic::SLO, 2, 8, AddressingMode::Indirect_Y, false),
+
+        OpCode::unofficial(0x27, Mnemonic::RLA, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x37, Mnemonic::RLA, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x2f, Mnemonic::RLA, 3, 6, AddressingMode::Absolute, false),
+        OpCode::unofficial(0x3f, Mnemonic::RLA, 3, 7, AddressingMode::Absolute_X, false),
+        OpCode::unofficial(0x3b, Mnemonic::RLA, 3, 7, AddressingMode::Absolute_Y, false),
+        OpCode::unofficial(0x23, Mnemonic::RLA, 2, 8, AddressingMode::Indirect_X, false),
+        OpCode::unofficial(0x33, Mnemonic::RLA, 2, 8, AddressingMode::Indirect_Y, false),
+
+        OpCode::unofficial(0x47, Mnemonic::SRE, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x57, Mnemonic::SRE, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x4f, Mnemonic::SRE, 3, 6, AddressingMode::Absolute, false),
+        OpCode::unofficial(0x5f, Mnemonic::SRE, 3, 7, AddressingMode::Absolute_X, false),
+        OpCode::unofficial(0x5b, Mnemonic::SRE, 3, 7, AddressingMode::Absolute_Y, false),
+        OpCode::unofficial(0x43, Mnemonic::SRE, 2, 8, AddressingMode::Indirect_X, false),
+        OpCode::unofficial(0x53, Mnemonic::SRE, 2, 8, AddressingMode::Indirect_Y, false),
+
+        OpCode::unofficial(0x67, Mnemonic::RRA, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x77, Mnemonic::RRA, 2, 6, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x6f, Mnemonic::RRA, 3, 6, AddressingMode::Absolute, false),
+        OpCode::unofficial(0x7f, Mnemonic::RRA, 3, 7, AddressingMode::Absolute_X, false),
+        OpCode::unofficial(0x7b, Mnemonic::RRA, 3, 7, AddressingMode::Absolute_Y, false),
+        OpCode::unofficial(0x63, Mnemonic::RRA, 2, 8, AddressingMode::Indirect_X, false),
+        OpCode::unofficial(0x73, Mnemonic::RRA, 2, 8, AddressingMode::Indirect_Y, false),
+
+        OpCode::unofficial(0x0b, Mnemonic::ANC, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x2b, Mnemonic::ANC, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x4b, Mnemonic::ALR, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x6b, Mnemonic::ARR, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xcb, Mnemonic::AXS, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xeb, Mnemonic::SBC, 2, 2, AddressingMode::Immediate, false),
+
+        OpCode::unofficial(0x8b, Mnemonic::XAA, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xab, Mnemonic::LXA, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xbb, Mnemonic::LAS, 3, 4, AddressingMode::Absolute_Y, true),
+        OpCode::unofficial(0x9b, Mnemonic::TAS, 3, 5, AddressingMode::Absolute_Y, false),
+        OpCode::unofficial(0x9f, Mnemonic::AHX, 3, 5, AddressingMode::Absolute_Y, false),
+        OpCode::unofficial(0x93, Mnemonic::AHX, 2, 6, AddressingMode::Indirect_Y, false),
+        OpCode::unofficial(0x9c, Mnemonic::SHY, 3, 5, AddressingMode::Absolute_X, false),
+        OpCode::unofficial(0x9e, Mnemonic::SHX, 3, 5, AddressingMode::Absolute_Y, false),
+    ];
+
+    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
+        let mut map = HashMap::new();
+        for cpuop in &*CPU_OPS_CODES {
+            map.insert(cpuop.code, cpuop);
+        }
+        map
+    };
+
+    /* WDC 65C02 differences from the official NMOS set above */
+    pub static ref CMOS_OPS_CODES: Vec<OpCode> = vec![
+        /* official opcodes whose timing or addressing changed */
+        OpCode::new(0x6c, Mnemonic::JMP, 3, 6, AddressingMode::Indirect, false),
+        OpCode::new(0x1e, Mnemonic::ASL, 3, 6, AddressingMode::Absolute_X, true),
+        OpCode::new(0x5e, Mnemonic::LSR, 3, 6, AddressingMode::Absolute_X, true),
+        OpCode::new(0x3e, Mnemonic::ROL, 3, 6, AddressingMode::Absolute_X, true),
+        OpCode::new(0x7e, Mnemonic::ROR, 3, 6, AddressingMode::Absolute_X, true),
+
+        /* new instructions */
+        OpCode::new(0x80, Mnemonic::BRA, 2, 2, AddressingMode::Relative, false),
+
+        OpCode::new(0xda, Mnemonic::PHX, 1, 3, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xfa, Mnemonic::PLX, 1, 4, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x5a, Mnemonic::PHY, 1, 3, AddressingMode::NoneAddressing, false),
+        OpCode::new(0x7a, Mnemonic::PLY, 1, 4, AddressingMode::NoneAddressing, false),
+
+        OpCode::new(0x64, Mnemonic::STZ, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::new(0x74, Mnemonic::STZ, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x9c, Mnemonic::STZ, 3, 4, AddressingMode::Absolute, false),
+        OpCode::new(0x9e, Mnemonic::STZ, 3, 5, AddressingMode::Absolute_X, false),
+
+        OpCode::new(0x14, Mnemonic::TRB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x1c, Mnemonic::TRB, 3, 6, AddressingMode::Absolute, false),
+        OpCode::new(0x04, Mnemonic::TSB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x0c, Mnemonic::TSB, 3, 6, AddressingMode::Absolute, false),
+
+        OpCode::new(0x1a, Mnemonic::INC, 1, 2, AddressingMode::Accumulator, false),
+        OpCode::new(0x3a, Mnemonic::DEC, 1, 2, AddressingMode::Accumulator, false),
+
+        OpCode::new(0x89, Mnemonic::BIT, 2, 2, AddressingMode::Immediate, false),
+        OpCode::new(0x34, Mnemonic::BIT, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::new(0x3c, Mnemonic::BIT, 3, 4, AddressingMode::Absolute_X, true),
+
+        OpCode::new(0x7c, Mnemonic::JMP, 3, 6, AddressingMode::Absolute_Indirect_X, false),
+
+        OpCode::new(0x12, Mnemonic::ORA, 2, 5, AddressingMode::ZeroPage_Indirect, false),
+        OpCode::new(0x32, Mnemonic::AND, 2, 5, AddressingMode::ZeroPage_Indirect, false),
+        OpCode::new(0x52, Mnemonic::EOR, 2, 5, AddressingMode::ZeroPage_Indirect, false),
+        OpCode::new(0x72, Mnemonic::ADC, 2, 5, AddressingMode::ZeroPage_Indirect, false),
+        OpCode::new(0x92, Mnemonic::STA, 2, 5, AddressingMode::ZeroPage_Indirect, false),
+        OpCode::new(0xb2, Mnemonic::LDA, 2, 5, AddressingMode::ZeroPage_Indirect, false),
+        OpCode::new(0xd2, Mnemonic::CMP, 2, 5, AddressingMode::ZeroPage_Indirect, false),
+        OpCode::new(0xf2, Mnemonic::SBC, 2, 5, AddressingMode::ZeroPage_Indirect, false),
+
+        OpCode::new(0xcb, Mnemonic::WAI, 1, 3, AddressingMode::NoneAddressing, false),
+        OpCode::new(0xdb, Mnemonic::STP, 1, 3, AddressingMode::NoneAddressing, false),
+
+        OpCode::new(0x07, Mnemonic::RMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x17, Mnemonic::RMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x27, Mnemonic::RMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x37, Mnemonic::RMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x47, Mnemonic::RMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x57, Mnemonic::RMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x67, Mnemonic::RMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x77, Mnemonic::RMB, 2, 5, AddressingMode::ZeroPage, false),
+
+        OpCode::new(0x87, Mnemonic::SMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0x97, Mnemonic::SMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0xa7, Mnemonic::SMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0xb7, Mnemonic::SMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0xc7, Mnemonic::SMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0xd7, Mnemonic::SMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0xe7, Mnemonic::SMB, 2, 5, AddressingMode::ZeroPage, false),
+        OpCode::new(0xf7, Mnemonic::SMB, 2, 5, AddressingMode::ZeroPage, false),
+
+        OpCode::new(0x0f, Mnemonic::BBR, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0x1f, Mnemonic::BBR, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0x2f, Mnemonic::BBR, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0x3f, Mnemonic::BBR, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0x4f, Mnemonic::BBR, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0x5f, Mnemonic::BBR, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0x6f, Mnemonic::BBR, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0x7f, Mnemonic::BBR, 3, 5, AddressingMode::ZeroPage_Relative, false),
+
+        OpCode::new(0x8f, Mnemonic::BBS, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0x9f, Mnemonic::BBS, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0xaf, Mnemonic::BBS, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0xbf, Mnemonic::BBS, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0xcf, Mnemonic::BBS, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0xdf, Mnemonic::BBS, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0xef, Mnemonic::BBS, 3, 5, AddressingMode::ZeroPage_Relative, false),
+        OpCode::new(0xff, Mnemonic::BBS, 3, 5, AddressingMode::ZeroPage_Relative, false),
+
+        /* reserved opcodes are documented NOPs of various lengths and timings */
+        OpCode::unofficial(0x02, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x22, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x42, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x44, Mnemonic::NOP, 2, 3, AddressingMode::ZeroPage, false),
+        OpCode::unofficial(0x54, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0x5c, Mnemonic::NOP, 3, 8, AddressingMode::Absolute, false),
+        OpCode::unofficial(0x62, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0x82, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xc2, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xd4, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0xdc, Mnemonic::NOP, 3, 4, AddressingMode::Absolute, false),
+        OpCode::unofficial(0xe2, Mnemonic::NOP, 2, 2, AddressingMode::Immediate, false),
+        OpCode::unofficial(0xf4, Mnemonic::NOP, 2, 4, AddressingMode::ZeroPage_X, false),
+        OpCode::unofficial(0xfc, Mnemonic::NOP, 3, 4, AddressingMode::Absolute, false),
+
+        OpCode::unofficial(0x03, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x0b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x13, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x1b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x23, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x2b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x33, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x3b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x43, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x4b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x53, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x5b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x63, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x6b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x73, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x7b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x83, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x8b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x93, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0x9b, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xa3, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xab, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xb3, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xbb, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xc3, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xd3, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xe3, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xeb, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xf3, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+        OpCode::unofficial(0xfb, Mnemonic::NOP, 1, 1, AddressingMode::NoneAddressing, false),
+    ];
+
+    pub static ref CMOS_OPCODES_MAP: HashMap<u8, &'static OpCode> = {
+        let mut map = HashMap::new();
+        for cpuop in CPU_OPS_CODES.iter().filter(|op| !op.unofficial) {
+            map.insert(cpuop.code, cpuop);
+        }
+        for cpuop in &*CMOS_OPS_CODES {
+            map.insert(cpuop.code, cpuop);
+        }
+        map
+    };
+}
+
+/* The decode table for the given processor */
+pub fn opcode_table(variant: CpuVariant) -> &'static HashMap<u8, &'static OpCode> {
+    match variant {
+        CpuVariant::Nes2A03 | CpuVariant::Nmos6502 => &OPCODES_MAP,
+        CpuVariant::Wdc65C02 => &CMOS_OPCODES_MAP,
+    }
+}
@@ -0,0 +1,658 @@
+use crate::mapper::Mapper;
+
+bitflags!{
+    #[derive (Copy, Clone)]
+    pub struct PpuCtrl: u8{
+       const NAMETABLE_X = 0b00000001;
+       const NAMETABLE_Y = 0b00000010;
+       const VRAM_INCREMENT_32 = 0b00000100;
+       const SPRITE_PATTERN_HIGH = 0b00001000;
+       const BACKGROUND_PATTERN_HIGH = 0b00010000;
+       const TALL_SPRITES = 0b00100000;
+       const MASTER_SLAVE = 0b01000000;
+       const GENERATE_NMI = 0b10000000;
+    }
+}
+
+bitflags!{
+    #[derive (Copy, Clone)]
+    pub struct PpuMask: u8{
+       const GRAYSCALE = 0b00000001;
+       const SHOW_BACKGROUND_LEFT = 0b00000010;
+       const SHOW_SPRITES_LEFT = 0b00000100;
+       const SHOW_BACKGROUND = 0b00001000;
+       const SHOW_SPRITES = 0b00010000;
+       const EMPHASIZE_RED = 0b00100000;
+       const EMPHASIZE_GREEN = 0b01000000;
+       const EMPHASIZE_BLUE = 0b10000000;
+    }
+}
+
+bitflags!{
+    #[derive (Copy, Clone)]
+    pub struct PpuStatus: u8{
+       const SPRITE_OVERFLOW = 0b00100000;
+       const SPRITE_ZERO_HIT = 0b01000000;
+       const VBLANK = 0b10000000;
+    }
+}
+
+pub const FRAME_WIDTH: usize = 256;
+pub const FRAME_HEIGHT: usize = 240;
+
+const DOTS_PER_SCANLINE: u16 = 341;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+//  _______________ $4000
+// | Mirrors       |
+// | $3F00-$3F1F   |
+// |_______________| $3F20
+// | Palette RAM   |
+// |_______________| $3F00
+// | Mirrors       |
+// | $2000-$2EFF   |
+// |_______________| $3000
+// | Nametables    |
+// |_______________| $2000
+// | Pattern       |
+// | tables (CHR)  |
+// |_______________| $0000
+const NAMETABLES: u16 = 0x2000;
+const NAMETABLE_MIRRORS_END: u16 = 0x3EFF;
+const PALETTE: u16 = 0x3F00;
+
+/* The 2C02 picture processor as the CPU sees it through $2000-$2007.
+ *
+ *  $2000  PPUCTRL    write  nametable select, increment, pattern tables, NMI enable
+ *  $2001  PPUMASK    write  rendering enables, left column clipping, emphasis
+ *  $2002  PPUSTATUS  read   vblank, sprite 0 hit, overflow; clears vblank and w
+ *  $2003  OAMADDR    write
+ *  $2004  OAMDATA    read/write, writes advance OAMADDR
+ *  $2005  PPUSCROLL  write x2  coarse/fine X, then coarse/fine Y into t
+ *  $2006  PPUADDR    write x2  high then low byte of t, copied to v on the second
+ *  $2007  PPUDATA    read/write at v, which then advances by 1 or 32
+ *
+ * Scrolling and addressing share the internal registers v (current VRAM address),
+ * t (the address the next frame or $2006 write starts from), x (fine X scroll) and
+ * the w toggle that picks the first or second write of $2005/$2006. Reads of PPUDATA
+ * below the palette come from a buffer filled by the previous read.
+ *
+ * Pattern tables and nametables go through the mapper, which also decides how the
+ * nametables are mirrored onto the console's 2 KiB of CIRAM.
+ *
+ * While rendering is enabled the background is fetched the way the real PPU does it,
+ * one 8-dot nametable/attribute/pattern cycle per tile, into shift registers that feed
+ * the picture a pixel per dot. v walks across the screen as it goes: coarse X after
+ * every tile, Y at dot 256, and the horizontal and vertical bits are reloaded from t
+ * at dot 257 and on the pre-render line.
+ *
+ * Sprites for the next line are picked at dot 257 of each visible line, up to eight
+ * in OAM order, and their patterns fetched over dots 257-320. OAM entries are Y (the
+ * line above the sprite's top), tile, attributes and X:
+ *
+ *  attributes  76543210
+ *              ||||||++- palette 4-7
+ *              ||+------ behind the background
+ *              |+------- horizontal flip
+ *              +-------- vertical flip */
+pub struct Ppu {
+    pub ctrl: PpuCtrl,
+    pub mask: PpuMask,
+    pub status: PpuStatus,
+    pub oam_addr: u8,
+    pub oam: [u8; 256],
+    /* Current VRAM address: yyy NN YYYYY XXXXX (fine Y, nametable, coarse Y, coarse X) */
+    pub v: u16,
+    /* Temporary VRAM address, same layout; written by $2000, $2005 and $2006 */
+    pub t: u16,
+    /* Fine X scroll, 0-7 */
+    pub x: u8,
+    /* Write toggle for $2005/$2006: false before the first write, true before the second */
+    pub w: bool,
+    /* The console's 2 KiB of nametable RAM, followed by 2 KiB that stands in for the
+     * extra RAM four-screen boards carry */
+    pub vram: [u8; 0x1000],
+    pub palette: [u8; 32],
+    read_buffer: u8,
+    // The last value driven onto the CPU data bus; write-only registers read back as it
+    io_latch: u8,
+    pub scanline: u16,
+    pub dot: u16,
+    /* Frames started since power on */
+    pub frame: u64,
+    /* The picture, FRAME_WIDTH x FRAME_HEIGHT colour numbers (0-63) as picked from
+     * palette RAM; SYSTEM_PALETTE turns them into RGB */
+    pub frame_buffer: Vec<u8>,
+    // The background tile fetched for the next 8 pixels: nametable byte, the 2-bit
+    // palette from the attribute table, then the two pattern planes
+    next_tile: u8,
+    next_attribute: u8,
+    next_pattern: [u8; 2],
+    // Background shift registers, low plane first. The high byte is the tile being
+    // drawn, the low byte the next one.
+    pattern_shifters: [u16; 2],
+    attribute_shifters: [u16; 2],
+    // The sprites evaluation found in range of the next line, 4 OAM bytes each
+    secondary_oam: [u8; 32],
+    sprite_count: usize,
+    // Whether the first of them is OAM sprite 0, which is the one that can hit
+    sprite_zero_on_line: bool,
+    // What the sprite fetches loaded for the line being drawn
+    sprite_patterns: [[u8; 2]; 8],
+    sprite_attributes: [u8; 8],
+    sprite_x: [u8; 8],
+    // Total dots since power on, the clock that mapper address snooping is timed by
+    dots: u64,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            ctrl: PpuCtrl::empty(),
+            mask: PpuMask::empty(),
+            status: PpuStatus::empty(),
+            oam_addr: 0,
+            oam: [0; 256],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            vram: [0; 0x1000],
+            palette: [0; 32],
+            read_buffer: 0,
+            io_latch: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            frame_buffer: vec![0; FRAME_WIDTH * FRAME_HEIGHT],
+            next_tile: 0,
+            next_attribute: 0,
+            next_pattern: [0; 2],
+            pattern_shifters: [0; 2],
+            attribute_shifters: [0; 2],
+            secondary_oam: [0xFF; 32],
+            sprite_count: 0,
+            sprite_zero_on_line: false,
+            sprite_patterns: [[0; 2]; 8],
+            sprite_attributes: [0; 8],
+            sprite_x: [0; 8],
+            dots: 0,
+        }
+    }
+
+    // The /NMI output: held while in vblank with NMIs enabled
+    pub fn nmi(&self) -> bool {
+        self.status.contains(PpuStatus::VBLANK) && self.ctrl.contains(PpuCtrl::GENERATE_NMI)
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask.intersects(PpuMask::SHOW_BACKGROUND | PpuMask::SHOW_SPRITES)
+    }
+
+    /* CPU read of $2000-$2007 (`addr` may be any mirror) */
+    pub fn read_register(&mut self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        let data = match addr & 0b0111 {
+            2 => {
+                let data = self.peek_register(addr);
+                self.status.remove(PpuStatus::VBLANK);
+                self.w = false;
+                data
+            }
+            4 => self.peek_register(addr),
+            7 => {
+                let data = self.peek_register(addr);
+                let addr = self.v & 0x3FFF;
+                // The palette is read directly, but the buffer still picks up the
+                // nametable byte "underneath" it
+                let buffer_addr = if addr >= PALETTE { addr - 0x1000 } else { addr };
+                self.read_buffer = self.read(buffer_addr, mapper);
+                self.increment_v(mapper);
+                data
+            }
+            _ => self.io_latch,
+        };
+        self.io_latch = data;
+        data
+    }
+
+    // Same value read_register() would return, without side effects
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        match addr & 0b0111 {
+            2 => self.status.bits() | (self.io_latch & 0x1F),
+            4 => {
+                // The unused attribute bits don't exist and read back as 0
+                let data = self.oam[self.oam_addr as usize];
+                if self.oam_addr & 0b11 == 2 { data & 0xE3 } else { data }
+            }
+            7 if self.v & 0x3FFF >= PALETTE => {
+                self.read_palette(self.v) | (self.io_latch & 0xC0)
+            }
+            7 => self.read_buffer,
+            _ => self.io_latch,
+        }
+    }
+
+    /* CPU write to $2000-$2007 (`addr` may be any mirror) */
+    pub fn write_register(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        self.io_latch = data;
+        match addr & 0b0111 {
+            0 => {
+                self.ctrl = PpuCtrl::from_bits_truncate(data);
+                self.t = (self.t & !0x0C00) | ((data & 0b11) as u16) << 10;
+            }
+            1 => self.mask = PpuMask::from_bits_truncate(data),
+            3 => self.oam_addr = data,
+            4 => self.write_oam(data),
+            5 if !self.w => {
+                self.t = (self.t & !0x001F) | (data >> 3) as u16;
+                self.x = data & 0b111;
+                self.w = true;
+            }
+            5 => {
+                let fine_y = ((data & 0b111) as u16) << 12;
+                self.t = (self.t & !0x73E0) | fine_y | ((data >> 3) as u16) << 5;
+                self.w = false;
+            }
+            6 if !self.w => {
+                // Bit 14 of t is cleared too; v is only 14 bits wide on the bus
+                self.t = (self.t & 0x00FF) | ((data & 0x3F) as u16) << 8;
+                self.w = true;
+            }
+            6 => {
+                self.t = (self.t & 0xFF00) | data as u16;
+                self.v = self.t;
+                self.w = false;
+                mapper.ppu_address(self.v & 0x3FFF, self.dots);
+            }
+            7 => {
+                self.write(self.v & 0x3FFF, data, mapper);
+                self.increment_v(mapper);
+            }
+            _ => {}
+        }
+    }
+
+    // OAMDATA write, also what OAM DMA does 256 times
+    pub fn write_oam(&mut self, data: u8) {
+        self.oam[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    /* Advances the PPU by `dots` dots (three per CPU cycle on NTSC): scanlines 0-239
+     * are drawn, vblank starts at dot 1 of scanline 241 and ends at dot 1 of the
+     * pre-render line, 261. With rendering on, the pre-render line of every other frame
+     * is a dot short. */
+    pub fn tick(&mut self, dots: u16, mapper: &mut dyn Mapper) {
+        for _ in 0..dots {
+            self.dots += 1;
+            self.dot += 1;
+            let skip_dot = self.scanline == PRE_RENDER_SCANLINE
+                && self.dot == DOTS_PER_SCANLINE - 1
+                && self.frame & 1 == 1
+                && self.rendering_enabled();
+            if self.dot == DOTS_PER_SCANLINE || skip_dot {
+                mapper.scanline(self.scanline, self.rendering_enabled());
+                self.dot = 0;
+                if self.scanline == PRE_RENDER_SCANLINE {
+                    self.scanline = 0;
+                    self.frame += 1;
+                } else {
+                    self.scanline += 1;
+                }
+            }
+
+            match (self.scanline, self.dot) {
+                (VBLANK_SCANLINE, 1) => self.status.insert(PpuStatus::VBLANK),
+                (PRE_RENDER_SCANLINE, 1) => self.status.remove(
+                    PpuStatus::VBLANK | PpuStatus::SPRITE_ZERO_HIT | PpuStatus::SPRITE_OVERFLOW,
+                ),
+                _ => {}
+            }
+
+            if self.rendering_enabled() {
+                self.fetch_background(mapper);
+                self.fetch_sprites(mapper);
+            }
+            if self.scanline < FRAME_HEIGHT as u16 && (1..=FRAME_WIDTH as u16).contains(&self.dot) {
+                self.draw_pixel();
+            }
+        }
+    }
+
+    /* The background half of a rendering dot: shift, fetch the next tile in its 8-dot
+     * slot and move v along. Lines 0-239 fetch for the line they draw; dots 321-336 and
+     * the pre-render line fetch the first two tiles of the line after. */
+    fn fetch_background(&mut self, mapper: &mut dyn Mapper) {
+        if self.scanline >= FRAME_HEIGHT as u16 && self.scanline != PRE_RENDER_SCANLINE {
+            return;
+        }
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+        }
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            match dot & 0b111 {
+                1 => {
+                    self.load_background_shifters();
+                    self.next_tile = self.read(0x2000 | (self.v & 0x0FFF), mapper);
+                }
+                3 => {
+                    let v = self.v;
+                    let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    // Each attribute byte covers 4x4 tiles, 2 bits per 2x2 quadrant
+                    let shift = ((v >> 4) & 0b100) | (v & 0b10);
+                    self.next_attribute = (self.read(addr, mapper) >> shift) & 0b11;
+                }
+                5 => self.next_pattern[0] = self.read(self.background_pattern_addr(), mapper),
+                7 => {
+                    let addr = self.background_pattern_addr() + 8;
+                    self.next_pattern[1] = self.read(addr, mapper);
+                }
+                0 => self.increment_coarse_x(),
+                _ => {}
+            }
+        }
+        match dot {
+            256 => self.increment_y(),
+            257 => {
+                self.load_background_shifters();
+                self.v = (self.v & !0x041F) | (self.t & 0x041F);
+            }
+            280..=304 if self.scanline == PRE_RENDER_SCANLINE => {
+                self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+            }
+            // Two more nametable fetches that go unused, which MMC5 counts on
+            337 | 339 => {
+                self.read(0x2000 | (self.v & 0x0FFF), mapper);
+            }
+            _ => {}
+        }
+    }
+
+    /* The sprite half of a rendering dot. Evaluation runs all at once at dot 257 rather
+     * than spread over dots 65-256, which nothing but OAMDATA reads during rendering
+     * could tell apart. The fetches go out over dots 257-320, with tile $FF standing in
+     * for the empty slots, so boards watching A12 see them like on hardware. */
+    fn fetch_sprites(&mut self, mapper: &mut dyn Mapper) {
+        if self.scanline >= FRAME_HEIGHT as u16 && self.scanline != PRE_RENDER_SCANLINE {
+            return;
+        }
+        match self.dot {
+            257 => {
+                if self.scanline == PRE_RENDER_SCANLINE {
+                    // Nothing is evaluated for line 0, so sprites can't show there
+                    self.secondary_oam = [0xFF; 32];
+                    self.sprite_count = 0;
+                    self.sprite_zero_on_line = false;
+                } else {
+                    self.evaluate_sprites();
+                }
+                mapper.sprite_fetches(true);
+            }
+            321 => mapper.sprite_fetches(false),
+            _ => {}
+        }
+        if !(257..=320).contains(&self.dot) {
+            return;
+        }
+
+        self.oam_addr = 0;
+        let slot = (self.dot - 257) as usize / 8;
+        let plane = match (self.dot - 257) % 8 {
+            4 => 0,
+            6 => 1,
+            _ => return,
+        };
+        let entry = &self.secondary_oam[slot * 4..slot * 4 + 4];
+        let (y, tile, attributes, x) = (entry[0], entry[1], entry[2], entry[3]);
+        let row = self.scanline.wrapping_sub(y as u16) & (self.sprite_height() - 1);
+        let addr = self.sprite_pattern_addr(tile, attributes, row) + plane as u16 * 8;
+        let data = self.read(addr, mapper);
+        if slot < self.sprite_count {
+            self.sprite_patterns[slot][plane] = data;
+            self.sprite_attributes[slot] = attributes;
+            self.sprite_x[slot] = x;
+        }
+    }
+
+    /* Copies the first eight sprites in range of the next line to secondary OAM. After
+     * the eighth the PPU keeps looking for a ninth to set the overflow flag, but a bug
+     * makes it step through the bytes of each entry as well as the entries, so it
+     * compares tile numbers, attributes and X against the line too. */
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        let in_range = |y: u8| self.scanline.wrapping_sub(y as u16) < height;
+
+        self.secondary_oam = [0xFF; 32];
+        self.sprite_count = 0;
+        self.sprite_zero_on_line = false;
+        let mut n = 0;
+        while n < 64 && self.sprite_count < 8 {
+            if in_range(self.oam[n * 4]) {
+                let slot = self.sprite_count * 4;
+                self.secondary_oam[slot..slot + 4].copy_from_slice(&self.oam[n * 4..n * 4 + 4]);
+                self.sprite_zero_on_line |= n == 0;
+                self.sprite_count += 1;
+            }
+            n += 1;
+        }
+
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.status.insert(PpuStatus::SPRITE_OVERFLOW);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0b11;
+        }
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl.contains(PpuCtrl::TALL_SPRITES) { 16 } else { 8 }
+    }
+
+    /* Pattern address of `row` of a sprite. 8x16 sprites take their pattern table from
+     * bit 0 of the tile number and use the even/odd tile pair for the top/bottom half;
+     * vertical flip swaps the halves as well as the rows. */
+    fn sprite_pattern_addr(&self, tile: u8, attributes: u8, row: u16) -> u16 {
+        let height = self.sprite_height();
+        let row = if attributes & 0x80 != 0 { height - 1 - row } else { row };
+        if height == 16 {
+            let table = (tile as u16 & 1) << 12;
+            table | ((tile & 0xFE) as u16 + (row >> 3)) << 4 | (row & 0b111)
+        } else {
+            let table = if self.ctrl.contains(PpuCtrl::SPRITE_PATTERN_HIGH) { 0x1000 } else { 0 };
+            table | (tile as u16) << 4 | row
+        }
+    }
+
+    fn background_pattern_addr(&self) -> u16 {
+        let table = if self.ctrl.contains(PpuCtrl::BACKGROUND_PATTERN_HIGH) { 0x1000 } else { 0 };
+        table | (self.next_tile as u16) << 4 | (self.v >> 12)
+    }
+
+    fn shift_background(&mut self) {
+        for shifter in self.pattern_shifters.iter_mut().chain(self.attribute_shifters.iter_mut()) {
+            *shifter <<= 1;
+        }
+    }
+
+    // Moves the fetched tile into the low byte of the shifters; the attribute bits
+    // are spread across all 8 pixels
+    fn load_background_shifters(&mut self) {
+        for plane in 0..2 {
+            let pattern = self.next_pattern[plane] as u16;
+            self.pattern_shifters[plane] = (self.pattern_shifters[plane] & 0xFF00) | pattern;
+            let attribute = if self.next_attribute >> plane & 1 != 0 { 0xFF } else { 0x00 };
+            self.attribute_shifters[plane] = (self.attribute_shifters[plane] & 0xFF00) | attribute;
+        }
+    }
+
+    // Next tile to the right, into the horizontally adjacent nametable after column 31
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v = (self.v & !0x001F) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /* Next pixel row: fine Y first, then coarse Y. Row 29 is the last of a nametable, so
+     * it wraps into the vertically adjacent one; rows 30 and 31 are the attribute table,
+     * reachable only through $2005/$2006, and wrap around the same nametable. */
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let coarse_y = match (self.v & 0x03E0) >> 5 {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            row => row + 1,
+        };
+        self.v = (self.v & !0x03E0) | coarse_y << 5;
+    }
+
+    /* The first opaque sprite pixel at `x` in OAM order, as a palette RAM index, with
+     * whether it goes behind the background and whether it's sprite 0's */
+    fn sprite_pixel(&self, x: usize) -> Option<(u16, bool, bool)> {
+        let show_left = self.mask.contains(PpuMask::SHOW_SPRITES_LEFT);
+        if !self.mask.contains(PpuMask::SHOW_SPRITES) || (x < 8 && !show_left) {
+            return None;
+        }
+        (0..self.sprite_count).find_map(|slot| {
+            let offset = x.wrapping_sub(self.sprite_x[slot] as usize);
+            if offset >= 8 {
+                return None;
+            }
+            let attributes = self.sprite_attributes[slot];
+            let bit = if attributes & 0x40 != 0 { offset } else { 7 - offset };
+            let [low, high] = self.sprite_patterns[slot];
+            let colour = (low >> bit & 1) as u16 | ((high >> bit & 1) as u16) << 1;
+            (colour != 0).then(|| {
+                let palette = 0x10 | ((attributes & 0b11) as u16) << 2 | colour;
+                (palette, attributes & 0x20 != 0, slot == 0 && self.sprite_zero_on_line)
+            })
+        })
+    }
+
+    fn draw_pixel(&mut self) {
+        let x = (self.dot - 1) as usize;
+        let mut background = 0;
+        let show_left = self.mask.contains(PpuMask::SHOW_BACKGROUND_LEFT);
+        if self.mask.contains(PpuMask::SHOW_BACKGROUND) && (x >= 8 || show_left) {
+            let bit = 0x8000 >> self.x;
+            let plane = |shifters: [u16; 2]| {
+                (shifters[0] & bit != 0) as u16 | ((shifters[1] & bit != 0) as u16) << 1
+            };
+            let colour = plane(self.pattern_shifters);
+            if colour != 0 {
+                background = plane(self.attribute_shifters) << 2 | colour;
+            }
+        }
+
+        // A sprite behind the background still hides the sprites after it in OAM,
+        // and sprite 0 hits wherever it overlaps the background, priority or not,
+        // except in the last column
+        let pixel = match self.sprite_pixel(x) {
+            Some((sprite, behind, zero)) => {
+                if zero && background != 0 && x != FRAME_WIDTH - 1 {
+                    self.status.insert(PpuStatus::SPRITE_ZERO_HIT);
+                }
+                if behind && background != 0 { background } else { sprite }
+            }
+            None => background,
+        };
+
+        // With rendering off the backdrop is normally colour 0, but if v points into
+        // the palette the PPU shows whichever entry it's on
+        let addr = if !self.rendering_enabled() && self.v & 0x3F00 == PALETTE {
+            self.v
+        } else {
+            PALETTE | pixel
+        };
+        self.frame_buffer[self.scanline as usize * FRAME_WIDTH + x] = self.read_palette(addr);
+    }
+
+    /* PPU bus read of $0000-$3FFF */
+    pub fn read(&mut self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        let addr = addr & 0x3FFF;
+        mapper.ppu_address(addr, self.dots);
+        match addr {
+            0..NAMETABLES => mapper.pattern_read(addr, &self.vram),
+            NAMETABLES..=NAMETABLE_MIRRORS_END => mapper.nametable_read(addr & 0x2FFF, &self.vram),
+            _ => self.read_palette(addr),
+        }
+    }
+
+    /* PPU bus write of $0000-$3FFF */
+    pub fn write(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        let addr = addr & 0x3FFF;
+        mapper.ppu_address(addr, self.dots);
+        match addr {
+            0..NAMETABLES => mapper.pattern_write(addr, data, &mut self.vram),
+            NAMETABLES..=NAMETABLE_MIRRORS_END => {
+                mapper.nametable_write(addr & 0x2FFF, data, &mut self.vram)
+            }
+            _ => self.palette[palette_index(addr)] = data & 0x3F,
+        }
+    }
+
+    fn read_palette(&self, addr: u16) -> u8 {
+        let data = self.palette[palette_index(addr)];
+        if self.mask.contains(PpuMask::GRAYSCALE) { data & 0x30 } else { data }
+    }
+
+    /* After a PPUDATA access. In the middle of rendering the PPU is busy with v, so
+     * the access bumps coarse X and Y at once instead of adding 1 or 32. */
+    fn increment_v(&mut self, mapper: &mut dyn Mapper) {
+        let drawing = self.scanline < FRAME_HEIGHT as u16 || self.scanline == PRE_RENDER_SCANLINE;
+        if drawing && self.rendering_enabled() {
+            self.increment_coarse_x();
+            self.increment_y();
+            return;
+        }
+        let step = if self.ctrl.contains(PpuCtrl::VRAM_INCREMENT_32) { 32 } else { 1 };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+        mapper.ppu_address(self.v & 0x3FFF, self.dots);
+    }
+}
+
+/* $3F10, $3F14, $3F18 and $3F1C are the same bytes as $3F00, $3F04, $3F08 and $3F0C:
+ * sprite palettes share their backdrop entry with the background ones. */
+fn palette_index(addr: u16) -> usize {
+    let index = (addr & 0x1F) as usize;
+    if index & 0x13 == 0x10 { index & 0x0F } else { index }
+}
+
+/* RGB for each of the 64 colour numbers the 2C02 can put out */
+pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
+    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96),
+    (0xA1, 0x00, 0x5E), (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00),
+    (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00), (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E),
+    (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05), (0x05, 0x05, 0x05),
+    (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00),
+    (0xC4, 0x62, 0x00), (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55),
+    (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21), (0x09, 0x09, 0x09), (0x09, 0x09, 0x09),
+    (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF), (0xD4, 0x80, 0xFF),
+    (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4),
+    (0x05, 0xFB, 0xFF), (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D),
+    (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF), (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB),
+    (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0), (0xFF, 0xEF, 0xA6),
+    (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+];
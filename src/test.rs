@@ -1,13 +1,32 @@
-use crate::cpu::CPU;
+use crate::cartridge::Rom;
+use crate::cpu::{CpuError, CpuFlags, CpuVariant, IllegalOpcodePolicy, CPU};
+use crate::opcodes;
+
+// Raw iNES image with the given flag bytes 6 and 7 and PRG/CHR contents
+#[cfg(test)]
+fn ines(flags_6: u8, flags_7: u8, prg_rom: &[u8], chr_rom: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0x4E, 0x45, 0x53, 0x1A];
+    raw.push((prg_rom.len() / 0x4000) as u8);
+    raw.push((chr_rom.len() / 0x2000) as u8);
+    raw.extend_from_slice(&[flags_6, flags_7, 0, 0, 0, 0, 0, 0, 0, 0]);
+    raw.extend_from_slice(prg_rom);
+    raw.extend_from_slice(chr_rom);
+    raw
+}
+
+#[cfg(test)]
+fn test_rom(prg_rom: Vec<u8>) -> Rom {
+    Rom::new(&ines(0, 0, &prg_rom, &[])).unwrap()
+}
 
 
 #[cfg(test)]
-mod test{
+mod cpu{
     use super::*;
 
     #[test]
     fn test_0xa9_lda(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.mem_write(0x10, 0x55);
         println!("THIS IS THE MEM READ");
         println!("{}",cpu.mem_read(0x10));
@@ -17,7 +36,7 @@ mod test{
 
     #[test]
     fn test_0xa9_lda_zero_flag(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xa9,0x00, 0x00]);
         assert_eq!(cpu.status.bits() & 0b0000_0010, 0b10);
         
@@ -25,7 +44,7 @@ mod test{
 
     #[test]
     fn test_moving_register_a_to_reg_x(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xA9, 0x04, 0xAA,0x00]);
         assert_eq!(cpu.register_x, cpu.register_a);
         assert_eq!(cpu.register_x, 0x04);
@@ -33,14 +52,14 @@ mod test{
 
     #[test]
     fn test_increment_reg_x(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xa9, 0x03,0xAA,0xE8, 0x00]);
         assert_eq!(cpu.register_x, 4);
     }
 
     #[test]
    fn test_5_operations_working_together() {
-       let mut cpu = CPU::new();
+       let mut cpu = CPU::new(CpuVariant::Nes2A03);
        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
  
        assert_eq!(cpu.register_x, 0xc1);
@@ -49,14 +68,14 @@ mod test{
 
    #[test]
    fn test_decrement_reg_x(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xa9, 0x05, 0xAA, 0xCA, 0x00]);
         assert_eq!(cpu.register_x, 4);
    }
 
     #[test]
    fn test_add_with_carry(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xA9, 0x10, 0x69, 0x01,0x00]);
         assert_eq!(cpu.register_a, 0x11);
 
@@ -64,32 +83,3000 @@ mod test{
     
    #[test]
    fn test_bitwise_and(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xA9, 0x10, 0x29, 0x11, 0x00]);
         assert_eq!(cpu.register_a, 0x10);
    }
     
    #[test]
    fn test_asl(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xA9, 0x10, 0x0A, 0x00]);
         assert_eq!(cpu.register_a, 0x20);
    }
 
    #[test]
    fn test_rol_accumulator(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xA9, 0x10, 0x2A, 0x00]);
         assert_eq!(cpu.register_a, 0x20);
    }
 
    #[test]
    fn test_rol_accumulator_multiple_times(){
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
         cpu.load_and_run(vec![0xA9, 0x10, 0x2A, 0x2A, 0x2A, 0x2A, 0x00]);
         assert_eq!(cpu.register_a, 0x00);
 
    }
 
+   #[test]
+   fn test_sbc_with_borrow(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // SEC; LDA #$10; SBC #$01
+        cpu.load_and_run(vec![0x38, 0xA9, 0x10, 0xE9, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x0F);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        // CLC; LDA #$00; SBC #$00 borrows
+        cpu.load_and_run(vec![0x18, 0xA9, 0x00, 0xE9, 0x00, 0x00]);
+        assert_eq!(cpu.register_a, 0xFF);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+   }
+
+   #[test]
+   fn test_stx_sty_absolute(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.load_and_run(vec![0xA2, 0x12, 0xA0, 0x34, 0x8E, 0x00, 0x02, 0x8C, 0x01, 0x02, 0x00]);
+        assert_eq!(cpu.mem_read(0x0200), 0x12);
+        assert_eq!(cpu.mem_read(0x0201), 0x34);
+   }
+
+   #[test]
+   fn test_compare_flags(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // LDX #$05; CPX #$05
+        cpu.load_and_run(vec![0xA2, 0x05, 0xE0, 0x05, 0x00]);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        // LDY #$05; CPY #$06
+        cpu.load_and_run(vec![0xA0, 0x05, 0xC0, 0x06, 0x00]);
+        assert!(!cpu.status.contains(CpuFlags::ZERO));
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+   }
+
+   #[test]
+   fn test_pha_pla_round_trip(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // LDA #$80; PHA; LDA #$00; PLA
+        cpu.load_and_run(vec![0xA9, 0x80, 0x48, 0xA9, 0x00, 0x68, 0x00]);
+        assert_eq!(cpu.register_a, 0x80);
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+        assert_eq!(cpu.stack_pointer, 0xfd);
+   }
+
+   #[test]
+   fn test_jmp_indirect_page_wrap(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.mem_write(0x02FF, 0x00);
+        cpu.mem_write(0x0200, 0x07);
+        cpu.mem_write(0x0300, 0x08);
+        // JMP ($02FF) must fetch the high byte from $0200, landing on $0700
+        cpu.mem_write(0x0700, 0xA9);
+        cpu.mem_write(0x0701, 0x42);
+        cpu.load_and_run(vec![0x6C, 0xFF, 0x02]);
+        assert_eq!(cpu.register_a, 0x42);
+   }
+
+   #[test]
+   fn test_lsr_ror_memory(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.mem_write(0x10, 0x03);
+        // LSR $10; ROR $10
+        cpu.load_and_run(vec![0x46, 0x10, 0x66, 0x10, 0x00]);
+        assert_eq!(cpu.mem_read(0x10), 0x80);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+   }
+
+   #[test]
+   fn test_jsr_rts(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // JSR $0606; LDX #$01; BRK; LDY #$02; RTS
+        cpu.load_and_run(vec![0x20, 0x06, 0x06, 0xA2, 0x01, 0x00, 0xA0, 0x02, 0x60]);
+        assert_eq!(cpu.register_x, 0x01);
+        assert_eq!(cpu.register_y, 0x02);
+        assert_eq!(cpu.stack_pointer, 0xfd);
+   }
+
+   #[test]
+   fn test_stack_transfers(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // LDX #$80; TXS; LDX #$00; TSX
+        cpu.load_and_run(vec![0xA2, 0x80, 0x9A, 0xA2, 0x00, 0xBA, 0x00]);
+        assert_eq!(cpu.register_x, 0x80);
+        assert_eq!(cpu.stack_pointer, 0x80);
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+   }
+
+   #[test]
+   fn test_lda_absolute_and_zero_page_x(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.mem_write(0x0234, 0x11);
+        cpu.mem_write(0x15, 0x22);
+        // LDA $0234; TAY; LDX #$05; LDA $10,X
+        cpu.load_and_run(vec![0xAD, 0x34, 0x02, 0xA8, 0xA2, 0x05, 0xB5, 0x10, 0x00]);
+        assert_eq!(cpu.register_y, 0x11);
+        assert_eq!(cpu.register_a, 0x22);
+   }
+
+   #[test]
+   fn test_opcode_table_covers_every_byte(){
+        let official = opcodes::CPU_OPS_CODES.iter().filter(|op| !op.unofficial).count();
+        assert_eq!(official, 151);
+        assert_eq!(opcodes::OPCODES_MAP.len(), 256);
+   }
+
+   #[test]
+   fn test_step_cycles_with_page_cross(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // LDX #$01; LDA $02FF,X; LDA $0200,X; STA $02FF,X
+        cpu.load(vec![0xA2, 0x01, 0xBD, 0xFF, 0x02, 0xBD, 0x00, 0x02, 0x9D, 0xFF, 0x02]);
+        cpu.reset();
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.step(), 4);
+        // stores always take the worst case, so no penalty on top
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.cycles, 7 + 2 + 5 + 4 + 5);
+   }
+
+   #[test]
+   fn test_branch_cycles(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // BEQ +2 (not taken); BNE +0 (taken); BNE -128 (taken, crosses into $05xx)
+        cpu.load(vec![0xF0, 0x02, 0xD0, 0x00, 0xD0, 0x80]);
+        cpu.reset();
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.step(), 3);
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.program_counter, 0x0586);
+   }
+
+   #[test]
+   fn test_program_counter_wraps(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // NOP at $FFFF runs on into $0000
+        cpu.mem_write(0xFFFF, 0xEA);
+        cpu.mem_write(0x0000, 0xE8);
+        cpu.program_counter = 0xFFFF;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0000);
+        cpu.step();
+        assert_eq!(cpu.register_x, 1);
+
+        // LDA #$42 with its operand at $0000
+        cpu.mem_write(0xFFFF, 0xA9);
+        cpu.mem_write(0x0000, 0x42);
+        cpu.program_counter = 0xFFFF;
+        cpu.step();
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.program_counter, 0x0001);
+
+        // JSR ending at $FFFF pushes $FFFF, and RTS returns to $0000
+        cpu.mem_write(0xFFFD, 0x20);
+        cpu.mem_write(0xFFFE, 0x00);
+        cpu.mem_write(0xFFFF, 0x07);
+        cpu.mem_write(0x0700, 0x60);
+        cpu.program_counter = 0xFFFD;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0700);
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0000);
+   }
+
+   #[test]
+   fn test_branch_to_next_byte(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // BNE $FF lands on its own operand, which then runs as ISB $xxxx,X
+        cpu.load(vec![0xD0, 0xFF, 0xEA, 0xEA]);
+        cpu.reset();
+        assert_eq!(cpu.step(), 3);
+        assert_eq!(cpu.program_counter, 0x0601);
+
+        // JMP to the byte after its opcode is taken too
+        cpu.load(vec![0x4C, 0x01, 0x06]);
+        cpu.reset();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0601);
+   }
+
+   #[test]
+   fn test_brk_and_rti(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // BRK; (padding); LDA #$01
+        cpu.load(vec![0x00, 0xFF, 0xA9, 0x01]);
+        // handler at $0700: LDX #$42; RTI
+        cpu.mem_write(0x0700, 0xA2);
+        cpu.mem_write(0x0701, 0x42);
+        cpu.mem_write(0x0702, 0x40);
+        cpu.mem_write(0xFFFE, 0x00);
+        cpu.mem_write(0xFFFF, 0x07);
+        cpu.reset();
+        cpu.status.remove(CpuFlags::INTERRUPT_DISABLE);
+
+        assert_eq!(cpu.step(), 7);
+        assert_eq!(cpu.program_counter, 0x0700);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        // the pushed status carries the B flag, the return address skips the padding byte
+        assert_eq!(cpu.mem_read(0x01fb), 0b0011_0000);
+        assert_eq!(cpu.mem_read(0x01fc), 0x02);
+        assert_eq!(cpu.mem_read(0x01fd), 0x06);
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0602);
+        assert!(!cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        cpu.step();
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.register_x, 0x42);
+   }
+
+   #[test]
+   fn test_nmi_is_edge_triggered(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // NOP; NOP; NOP
+        cpu.load(vec![0xEA, 0xEA, 0xEA]);
+        cpu.mem_write(0xFFFA, 0x00);
+        cpu.mem_write(0xFFFB, 0x07);
+        cpu.mem_write(0x0700, 0x40);
+        cpu.reset();
+
+        cpu.set_nmi(true);
+        assert_eq!(cpu.step(), 7);
+        assert_eq!(cpu.program_counter, 0x0700);
+        // hardware interrupts push the status with B clear
+        assert_eq!(cpu.mem_read(0x01fb) & 0b0001_0000, 0);
+
+        // RTI; holding the line must not fire a second NMI
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0600);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.program_counter, 0x0601);
+   }
+
+   #[test]
+   fn test_irq_is_masked_by_interrupt_disable(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // NOP; CLI; NOP
+        cpu.load(vec![0xEA, 0x58, 0xEA]);
+        cpu.mem_write(0xFFFE, 0x00);
+        cpu.mem_write(0xFFFF, 0x07);
+        cpu.reset();
+
+        cpu.set_irq(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0602);
+        assert_eq!(cpu.step(), 7);
+        assert_eq!(cpu.program_counter, 0x0700);
+   }
+
+   #[test]
+   fn test_unofficial_read_modify_write(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.mem_write(0x10, 0x41);
+        cpu.mem_write(0x11, 0x80);
+        // LAX $10; DCP $10; SEC; ISB $11
+        cpu.load_and_run(vec![0xA7, 0x10, 0xC7, 0x10, 0x38, 0xE7, 0x11, 0x00]);
+        assert_eq!(cpu.register_x, 0x41);
+        assert_eq!(cpu.mem_read(0x10), 0x40);
+        assert_eq!(cpu.mem_read(0x11), 0x81);
+        assert_eq!(cpu.register_a, 0x41u8.wrapping_sub(0x81));
+   }
+
+   #[test]
+   fn test_unofficial_immediate_ops(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // LDA #$FF; LDX #$0F; AXS #$02 -> X = $0D, carry set
+        cpu.load_and_run(vec![0xA9, 0xFF, 0xA2, 0x0F, 0xCB, 0x02, 0x00]);
+        assert_eq!(cpu.register_x, 0x0D);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        // LDA #$FF; ALR #$81 -> A = $40, carry from bit 0
+        cpu.load_and_run(vec![0xA9, 0xFF, 0x4B, 0x81, 0x00]);
+        assert_eq!(cpu.register_a, 0x40);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        // SEC; LDA #$FF; ARR #$C0 -> A = $E0, C = 1, V = 0
+        cpu.load_and_run(vec![0x38, 0xA9, 0xFF, 0x6B, 0xC0, 0x00]);
+        assert_eq!(cpu.register_a, 0xE0);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(!cpu.status.contains(CpuFlags::OVERFLOW));
+   }
+
+   #[test]
+   fn test_multi_byte_nop_lengths(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // NOP $12; NOP $1234,X; NOP #$12; LDA #$01
+        cpu.load_and_run(vec![0x04, 0x12, 0x1C, 0x34, 0x12, 0x80, 0x12, 0xA9, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x01);
+   }
+
+   #[test]
+   fn test_jam_halts_the_cpu(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.load(vec![0xEA, 0x02, 0xEA]);
+        cpu.reset();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.halted, Some(CpuError::Jammed { code: 0x02, addr: 0x0601 }));
+        assert_eq!(cpu.step(), 0);
+        assert_eq!(cpu.program_counter, 0x0601);
+   }
+
+   #[test]
+   fn test_illegal_opcode_policy_halt(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.illegal_opcode_policy = IllegalOpcodePolicy::Halt;
+        // LDA #$01; LAX $10
+        cpu.load_and_run(vec![0xA9, 0x01, 0xA7, 0x10, 0x00]);
+        assert_eq!(cpu.halted, Some(CpuError::IllegalOpcode { code: 0xA7, addr: 0x0602 }));
+        assert_eq!(cpu.register_x, 0x00);
+   }
+
+   #[test]
+   fn test_2a03_ignores_decimal_mode(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // SED; CLC; LDA #$09; ADC #$01
+        cpu.load_and_run(vec![0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x0A);
+   }
+
+   #[test]
+   fn test_nmos_decimal_adc_sbc(){
+        let mut cpu = CPU::new(CpuVariant::Nmos6502);
+        // SED; CLC; LDA #$09; ADC #$01
+        cpu.load_and_run(vec![0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x10);
+
+        // SED; CLC; LDA #$99; ADC #$01 wraps to $00 with carry, Z comes from the binary sum
+        cpu.load_and_run(vec![0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(!cpu.status.contains(CpuFlags::ZERO));
+
+        // SED; SEC; LDA #$10; SBC #$01
+        cpu.load_and_run(vec![0xF8, 0x38, 0xA9, 0x10, 0xE9, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x09);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+   }
+
+   #[test]
+   fn test_65c02_decimal_flags_and_cycles(){
+        let mut cpu = CPU::new(CpuVariant::Wdc65C02);
+        // SED; CLC; LDA #$99; ADC #$01
+        cpu.load(vec![0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01]);
+        cpu.reset();
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.step(), 3);
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+   }
+
+   #[test]
+   fn test_65c02_new_instructions(){
+        let mut cpu = CPU::new(CpuVariant::Wdc65C02);
+        cpu.mem_write(0x10, 0xFF);
+        cpu.mem_write(0x11, 0x0F);
+        cpu.mem_write(0x12, 0x00);
+        cpu.load_and_run(vec![
+            0x64, 0x10,       // STZ $10
+            0xA9, 0x03,       // LDA #$03
+            0x14, 0x11,       // TRB $11
+            0x04, 0x12,       // TSB $12
+            0xA2, 0x42,       // LDX #$42
+            0xDA,             // PHX
+            0x7A,             // PLY
+            0xD7, 0x12,       // SMB5 $12
+            0x80, 0x02,       // BRA +2
+            0xA9, 0xEE,       // (skipped)
+            0xDF, 0x12, 0x02, // BBS5 $12,+2
+            0xA9, 0xEE,       // (skipped)
+            0x1A,             // INC A
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.mem_read(0x11), 0x0C);
+        assert_eq!(cpu.mem_read(0x12), 0x23);
+        assert_eq!(cpu.register_y, 0x42);
+        assert_eq!(cpu.register_a, 0x04);
+   }
+
+   #[test]
+   fn test_65c02_jmp_indirect_without_page_wrap(){
+        let mut cpu = CPU::new(CpuVariant::Wdc65C02);
+        cpu.mem_write(0x02FF, 0x00);
+        cpu.mem_write(0x0200, 0x08);
+        cpu.mem_write(0x0300, 0x07);
+        cpu.mem_write(0x0700, 0xA9);
+        cpu.mem_write(0x0701, 0x42);
+        cpu.load_and_run(vec![0x6C, 0xFF, 0x02]);
+        assert_eq!(cpu.register_a, 0x42);
+   }
+
+   #[test]
+   fn test_65c02_interrupts_clear_decimal(){
+        let mut cpu = CPU::new(CpuVariant::Wdc65C02);
+        cpu.load(vec![0xF8, 0x00]);
+        cpu.mem_write(0xFFFE, 0x00);
+        cpu.mem_write(0xFFFF, 0x07);
+        cpu.reset();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0700);
+        assert!(!cpu.status.contains(CpuFlags::DECIMAL_MODE));
+   }
+
+}
+
+#[cfg(test)]
+mod bus{
+    use super::{ines, test_rom};
+    use crate::bus::{Bus, NesBus};
+    use crate::cartridge::Rom;
+    use crate::cpu::{CpuVariant, CPU};
+    use crate::mixer::CPU_CLOCK_HZ;
+    use crate::trace::trace;
+
+    // RAM with a status register at $2002 that clears itself when read
+    struct StatusBus {
+        ram: [u8; 0x10000],
+        status: u8,
+        ticks: u64,
+    }
+
+    impl Bus for StatusBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            if addr == 0x2002 {
+                let value = self.status;
+                self.status = 0;
+                return value;
+            }
+            self.ram[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            self.ram[addr as usize] = data;
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            if addr == 0x2002 {
+                return self.status;
+            }
+            self.ram[addr as usize]
+        }
+
+        fn tick(&mut self, cycles: u8) {
+            self.ticks += cycles as u64;
+        }
+    }
+
+    #[test]
+    fn test_flat_memory_covers_ffff(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.mem_write(0xFFFF, 0x12);
+        assert_eq!(cpu.mem_read(0xFFFF), 0x12);
+    }
+
+    #[test]
+    fn test_custom_bus_reads_and_ticks(){
+        let bus = StatusBus { ram: [0; 0x10000], status: 0x80, ticks: 0 };
+        let mut cpu = CPU::with_bus(bus, CpuVariant::Nes2A03);
+        // LDA $2002 ; LDX $2002
+        cpu.load(vec![0xAD, 0x02, 0x20, 0xAE, 0x02, 0x20]);
+        cpu.reset();
+
+        assert!(trace(&cpu).contains("LDA $2002 = 80"));
+        assert_eq!(cpu.bus.status, 0x80);
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.register_a, 0x80);
+        assert_eq!(cpu.register_x, 0x00);
+        assert_eq!(cpu.bus.ticks, 8);
+    }
+
+    #[test]
+    fn test_nes_bus_mirroring(){
+        let mut bus = NesBus::new(test_rom(vec![0; 0x4000])).unwrap();
+        bus.write(0x0012, 0x34);
+        assert_eq!(bus.read(0x0812), 0x34);
+        assert_eq!(bus.read(0x1812), 0x34);
+        bus.write(0x3FF9, 0x56);
+        assert_eq!(bus.read(0x2001), 0x56);
+        bus.write(0x4015, 0x0F);
+        assert_eq!(bus.read(0x4015), 0x0F);
+    }
+
+    #[test]
+    fn test_nes_bus_runs_16k_prg_rom(){
+        let mut prg_rom = vec![0; 0x4000];
+        // LDA #$42 ; STA $0800 (mirror of $0000)
+        prg_rom[..5].copy_from_slice(&[0xA9, 0x42, 0x8D, 0x00, 0x08]);
+        prg_rom[0x3FFC] = 0x00;
+        prg_rom[0x3FFD] = 0xC0;
+        let mut cpu = CPU::with_bus(NesBus::new(test_rom(prg_rom)).unwrap(), CpuVariant::Nes2A03);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0xC000);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.mem_read(0x0000), 0x42);
+        assert_eq!(cpu.mem_read(0x8000), 0xA9);
+    }
+
+    #[test]
+    fn test_nes_bus_save_data(){
+        // NROM with a battery: the PRG-RAM is the save
+        let raw = ines(0x02, 0x00, &[0; 0x4000], &[0; 0x2000]);
+        let mut bus = NesBus::new(Rom::new(&raw).unwrap()).unwrap();
+        bus.write(0x6000, 0x12);
+        bus.write(0x7FFF, 0x34);
+        let data = bus.save_data().unwrap();
+        assert_eq!(data.len(), 0x2000);
+
+        let mut restored = NesBus::new(Rom::new(&raw).unwrap()).unwrap();
+        restored.load_save_data(&data);
+        assert_eq!(restored.read(0x6000), 0x12);
+        assert_eq!(restored.read(0x7FFF), 0x34);
+        // A save of the wrong size is left alone
+        restored.load_save_data(&[0xFF; 16]);
+        assert_eq!(restored.read(0x6000), 0x12);
+
+        // Without the battery bit nothing is saved
+        let bus = NesBus::new(test_rom(vec![0; 0x4000])).unwrap();
+        assert!(bus.save_data().is_none());
+
+        // Boards that save elsewhere take precedence over their PRG-RAM
+        let raw = ines(0x02, 0x10, &[0; 0x8000], &[0; 0x2000]);
+        let bus = NesBus::new(Rom::new(&raw).unwrap()).unwrap();
+        assert_eq!(bus.save_data().unwrap().len(), 256);
+    }
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    // A board with `prg_rom` and 8 KiB of CHR-ROM, on a bus with audio turned on
+    fn audio_bus(mapper: u8, prg_rom: &[u8]) -> NesBus {
+        let raw = ines((mapper & 0x0F) << 4, mapper & 0xF0, prg_rom, &[0; 0x2000]);
+        let mut bus = NesBus::new(Rom::new(&raw).unwrap()).unwrap();
+        bus.enable_audio(SAMPLE_RATE);
+        bus
+    }
+
+    // What the speaker gets over `cycles` CPU cycles, clocked one at a time
+    fn record_audio(bus: &mut NesBus, cycles: u32) -> Vec<f32> {
+        for _ in 0..cycles {
+            bus.tick(1);
+        }
+        bus.take_audio()
+    }
+
+    // Pitch of a steady tone from its rising zero crossings, placed between samples by
+    // linear interpolation. The first 50 ms are skipped while the high-pass settles.
+    fn tone_frequency(samples: &[f32]) -> f64 {
+        let settled = &samples[SAMPLE_RATE as usize / 20..];
+        let crossings: Vec<f64> = settled
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(i, pair)| i as f64 + (pair[0] / (pair[0] - pair[1])) as f64)
+            .collect();
+        assert!(crossings.len() > 10);
+        let span = crossings[crossings.len() - 1] - crossings[0];
+        (crossings.len() - 1) as f64 * SAMPLE_RATE as f64 / span
+    }
+
+    #[test]
+    fn test_nes_bus_audio_is_off_until_enabled(){
+        let raw = ines(0x50, 0, &[0; 0x8000], &[0; 0x2000]);
+        let mut bus = NesBus::new(Rom::new(&raw).unwrap()).unwrap();
+        for _ in 0..1000 {
+            bus.tick(7);
+        }
+        assert!(bus.take_audio().is_empty());
+    }
+
+    #[test]
+    fn test_nes_bus_mixes_mmc5_audio(){
+        // 50% duty, constant volume, timer period 111: CPU clock / (16 * 112), about 999 Hz
+        let mut bus = audio_bus(5, &[0; 0x8000]);
+        bus.write(0x5015, 0b01);
+        bus.write(0x5000, 0b1011_1111);
+        bus.write(0x5002, 111);
+        bus.write(0x5003, 0x08);
+        let samples = record_audio(&mut bus, CPU_CLOCK_HZ as u32 / 2);
+        assert!((samples.len() as i64 - SAMPLE_RATE as i64 / 2).abs() <= 1);
+        let expected = CPU_CLOCK_HZ / (16.0 * 112.0);
+        assert!((tone_frequency(&samples) / expected - 1.0).abs() < 5e-5);
+        assert!(samples.iter().any(|&sample| sample > 0.05));
+
+        // A write to the PCM channel is a step of up to a quarter of full scale
+        bus.write(0x5015, 0);
+        record_audio(&mut bus, CPU_CLOCK_HZ as u32 / 4);
+        bus.write(0x5011, 0xFF);
+        let samples = record_audio(&mut bus, 1000);
+        assert!(samples.iter().any(|&sample| sample > 0.2));
+    }
+
+    #[test]
+    fn test_nes_bus_mixes_vrc6_audio(){
+        // Pulse 1 at 50% duty and timer period 111: CPU clock / (16 * 112)
+        let mut bus = audio_bus(24, &[0; 0x8000]);
+        bus.write(0x9000, 0x7F);
+        bus.write(0x9001, 111);
+        bus.write(0x9002, 0x80);
+        let samples = record_audio(&mut bus, CPU_CLOCK_HZ as u32 / 4);
+        let expected = CPU_CLOCK_HZ / (16.0 * 112.0);
+        assert!((tone_frequency(&samples) / expected - 1.0).abs() < 1e-4);
+
+        // The sawtooth alone, period 127: CPU clock / (14 * 128)
+        bus.write(0x9002, 0x00);
+        bus.write(0xB000, 0x2A);
+        bus.write(0xB001, 127);
+        bus.write(0xB002, 0x80);
+        let samples = record_audio(&mut bus, CPU_CLOCK_HZ as u32 / 4);
+        let expected = CPU_CLOCK_HZ / (14.0 * 128.0);
+        assert!((tone_frequency(&samples) / expected - 1.0).abs() < 1e-4);
+
+        // Halting the channels leaves a constant level, which fades out
+        bus.write(0x9003, 0x01);
+        let samples = record_audio(&mut bus, CPU_CLOCK_HZ as u32 / 4);
+        assert!(samples[samples.len() - 1].abs() < 0.01);
+    }
+
+    #[test]
+    fn test_nes_bus_mixes_sunsoft_5b_audio(){
+        // Tone A alone at period 56, which flips every 16 * 56 CPU cycles
+        let mut bus = audio_bus(69, &[0; 0x8000]);
+        for (register, data) in [(0x07, 0b11_1110), (0x00, 56), (0x01, 0), (0x08, 0x0F)] {
+            bus.write(0xC000, register);
+            bus.write(0xE000, data);
+        }
+        let samples = record_audio(&mut bus, CPU_CLOCK_HZ as u32 / 4);
+        let expected = CPU_CLOCK_HZ / (32.0 * 56.0);
+        assert!((tone_frequency(&samples) / expected - 1.0).abs() < 1e-4);
+        assert!(samples.iter().any(|&sample| sample > 0.05));
+    }
+
+    #[test]
+    fn test_nes_bus_mixes_namco163_audio(){
+        let mut bus = audio_bus(19, &[0; 0x8000]);
+        // A 16-sample square wave at the start of sound RAM
+        bus.write(0xF800, 0x80);
+        for data in [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00] {
+            bus.write(0x4800, data);
+        }
+        // Channel 7 alone, frequency $2000: a step every 15 * 8 CPU cycles
+        bus.write(0xF800, 0xF8);
+        for data in [0x00, 0x00, 0x20, 0x00, 0xF0, 0x00, 0x00, 0x0F] {
+            bus.write(0x4800, data);
+        }
+        let samples = record_audio(&mut bus, CPU_CLOCK_HZ as u32 / 4);
+        let expected = CPU_CLOCK_HZ / (15.0 * 8.0 * 16.0);
+        assert!((tone_frequency(&samples) / expected - 1.0).abs() < 1e-4);
+        assert!(samples.iter().any(|&sample| sample > 0.05));
+
+        // $E000 bit 6 turns the sound off
+        bus.write(0xE000, 0x40);
+        let samples = record_audio(&mut bus, CPU_CLOCK_HZ as u32 / 4);
+        assert!(samples[samples.len() - 1].abs() < 0.01);
+    }
+}
+
+#[cfg(test)]
+mod cartridge{
+    use super::ines;
+    use crate::cartridge::{ConsoleType, Mirroring, Rom, RomError, RomFormat, Timing};
+
+    #[test]
+    fn test_parse_header(){
+        let prg_rom = vec![0x11; 2 * 0x4000];
+        let chr_rom = vec![0x22; 0x2000];
+        let rom = Rom::new(&ines(0x31 | 0b10, 0x40, &prg_rom, &chr_rom)).unwrap();
+        assert_eq!(rom.mapper, 0x43);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert!(rom.trainer.is_none());
+        assert_eq!(rom.prg_rom, prg_rom);
+        assert_eq!(rom.chr_rom, chr_rom);
+    }
+
+    #[test]
+    fn test_trainer_and_four_screen(){
+        let mut raw = ines(0b1100, 0, &[], &[]);
+        raw[4] = 1;
+        raw.extend(vec![0xAA; 512]);
+        raw.extend(vec![0xBB; 0x4000]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.screen_mirroring, Mirroring::FourScreen);
+        assert_eq!(rom.trainer, Some(vec![0xAA; 512]));
+        assert_eq!(rom.prg_rom, vec![0xBB; 0x4000]);
+        assert!(rom.chr_rom.is_empty());
+    }
+
+    #[test]
+    fn test_diskdude_header_ignores_byte_7(){
+        let mut raw = ines(0x10, 0x44, &[0; 0x4000], &[]);
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(Rom::new(&raw).unwrap().mapper, 1);
+    }
+
+    #[test]
+    fn test_ines_defaults(){
+        let rom = Rom::new(&ines(0b10, 0, &[0; 0x4000], &[])).unwrap();
+        assert_eq!(rom.format, RomFormat::INes);
+        assert_eq!(rom.submapper, 0);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(rom.timing, Timing::Ntsc);
+        assert_eq!(rom.console_type, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn test_parse_nes2_header(){
+        let mut raw = ines(0x40, 0x08 | 0x10 | 0b01, &[0; 0x4000], &[0; 0x2000]);
+        raw[8] = 0x21;
+        raw[10] = 0x70;
+        raw[11] = 0x07;
+        raw[12] = 0x01;
+        raw[13] = 0x34;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.format, RomFormat::Nes2);
+        assert_eq!(rom.mapper, 0x114);
+        assert_eq!(rom.submapper, 2);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(rom.chr_nvram_size, 0);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(rom.console_type, ConsoleType::VsSystem { ppu_type: 4, hardware_type: 3 });
+    }
+
+    #[test]
+    fn test_nes2_rom_sizes(){
+        // PRG-ROM with a high byte: $101 banks of 16 KiB
+        let mut raw = ines(0, 0x08, &[], &[]);
+        raw[4] = 0x01;
+        raw[9] = 0x01;
+        raw.extend(vec![0; 0x101 * 0x4000]);
+        assert_eq!(Rom::new(&raw).unwrap().prg_rom.len(), 0x101 * 0x4000);
+
+        // Exponent-multiplier notation: 2^13 * 3 = 24 KiB
+        let mut raw = ines(0, 0x08, &[], &[]);
+        raw[4] = (13 << 2) | 0b01;
+        raw[9] = 0x0F;
+        raw.extend(vec![0; 24 * 1024]);
+        assert_eq!(Rom::new(&raw).unwrap().prg_rom.len(), 24 * 1024);
+    }
+
+    #[test]
+    fn test_invalid_files(){
+        assert_eq!(Rom::new(b"NES").err(), Some(RomError::NotINes));
+        assert_eq!(Rom::new(&[0; 32]).err(), Some(RomError::NotINes));
+        assert_eq!(Rom::new(&ines(0, 0, &[], &[])).err(), Some(RomError::NoPrgRom));
+
+        let mut raw = ines(0, 0, &[0; 0x4000], &[]);
+        raw[5] = 1;
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::Truncated { expected: 16 + 0x4000 + 0x2000, actual: 16 + 0x4000 })
+        );
+    }
 }
 
+#[cfg(test)]
+mod mapper{
+    use super::ines;
+    use crate::cartridge::{Mirroring, Rom, RomError};
+    use crate::mapper::{self, Mapper, Mmc3, Mmc3Revision};
+    use crate::savestate::{StateError, StateReader, StateWriter};
+
+    // PRG-ROM whose every 16 KiB bank is filled with its own bank number
+    fn numbered_prg(banks: usize) -> Vec<u8> {
+        (0..banks).flat_map(|bank| vec![bank as u8; 0x4000]).collect()
+    }
+
+    // Same for CHR, in 1 KiB units so any CHR bank size can be told apart
+    fn numbered_chr(kib: usize) -> Vec<u8> {
+        (0..kib).flat_map(|bank| vec![bank as u8; 0x400]).collect()
+    }
+
+    fn board(number: u8, prg_rom: &[u8], chr_rom: &[u8]) -> Box<dyn Mapper> {
+        let raw = ines((number & 0x0F) << 4, number & 0xF0, prg_rom, chr_rom);
+        mapper::for_rom(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_unsupported_mapper(){
+        let raw = ines(0xF0, 0xF0, &numbered_prg(1), &[]);
+        assert_eq!(
+            mapper::for_rom(Rom::new(&raw).unwrap()).err(),
+            Some(RomError::UnsupportedMapper(255))
+        );
+    }
+
+    #[test]
+    fn test_nrom_128_mirrors_prg(){
+        let mut nrom = board(0, &numbered_prg(1), &numbered_chr(8));
+        nrom.cpu_write(0x8000, 0x55);
+        assert_eq!(nrom.cpu_read(0x8000), 0);
+        assert_eq!(nrom.cpu_read(0xC000), 0);
+
+        let nrom = board(0, &numbered_prg(2), &numbered_chr(8));
+        assert_eq!(nrom.cpu_peek(0x8000), 0);
+        assert_eq!(nrom.cpu_peek(0xFFFF), 1);
+        assert_eq!(nrom.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_nrom_chr_rom_and_chr_ram(){
+        let mut nrom = board(0, &numbered_prg(1), &numbered_chr(8));
+        nrom.ppu_write(0x1C00, 0xFF);
+        assert_eq!(nrom.ppu_read(0x1C00), 7);
+
+        let mut nrom = board(0, &numbered_prg(1), &[]);
+        nrom.ppu_write(0x1C00, 0xFF);
+        assert_eq!(nrom.ppu_read(0x1C00), 0xFF);
+    }
+
+    #[test]
+    fn test_nrom_save_state(){
+        let mut nrom = board(0, &numbered_prg(1), &[]);
+        nrom.cpu_write(0x6123, 0x42);
+        nrom.ppu_write(0x0456, 0x24);
+        let mut state = StateWriter::new();
+        nrom.save_state(&mut state);
+        let state = state.into_bytes();
+
+        let mut restored = board(0, &numbered_prg(1), &[]);
+        restored.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(restored.cpu_read(0x6123), 0x42);
+        assert_eq!(restored.ppu_read(0x0456), 0x24);
+
+        assert_eq!(
+            restored.load_state(&mut StateReader::new(&state[..100])),
+            Err(StateError::UnexpectedEnd)
+        );
+    }
+
+    // Loads an MMC1 register through the serial port, one instruction per bit
+    fn mmc1_write(mmc1: &mut Box<dyn Mapper>, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(addr, value >> bit);
+            mmc1.cpu_clock(2);
+        }
+    }
+
+    #[test]
+    fn test_mmc1_prg_modes(){
+        let mut mmc1 = board(1, &numbered_prg(8), &numbered_chr(8));
+        // Powers up with the last bank fixed at $C000
+        assert_eq!(mmc1.cpu_peek(0xC000), 7);
+        mmc1_write(&mut mmc1, 0xE000, 3);
+        assert_eq!(mmc1.cpu_peek(0x8000), 3);
+        assert_eq!(mmc1.cpu_peek(0xFFFF), 7);
+
+        // First bank fixed at $8000
+        mmc1_write(&mut mmc1, 0x8000, 0b01000);
+        assert_eq!(mmc1.cpu_peek(0x8000), 0);
+        assert_eq!(mmc1.cpu_peek(0xC000), 3);
+
+        // 32 KiB mode ignores bit 0 of the bank
+        mmc1_write(&mut mmc1, 0x8000, 0b00000);
+        assert_eq!(mmc1.cpu_peek(0x8000), 2);
+        assert_eq!(mmc1.cpu_peek(0xC000), 3);
+
+        // Bit 7 resets the shift register and goes back to mode 3
+        mmc1.cpu_write(0x8000, 1);
+        mmc1.cpu_clock(2);
+        mmc1.cpu_write(0x8000, 0x80);
+        mmc1.cpu_clock(2);
+        assert_eq!(mmc1.cpu_peek(0xC000), 7);
+        mmc1_write(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.cpu_peek(0x8000), 5);
+    }
+
+    #[test]
+    fn test_mmc1_chr_banks_and_mirroring(){
+        let mut mmc1 = board(1, &numbered_prg(2), &numbered_chr(32));
+        mmc1_write(&mut mmc1, 0xA000, 3);
+        // 8 KiB mode uses banks 2 and 3
+        assert_eq!(mmc1.ppu_read(0x0000), 8);
+        assert_eq!(mmc1.ppu_read(0x1000), 12);
+
+        mmc1_write(&mut mmc1, 0x8000, 0b10010);
+        mmc1_write(&mut mmc1, 0xC000, 6);
+        assert_eq!(mmc1.ppu_read(0x0000), 12);
+        assert_eq!(mmc1.ppu_read(0x1000), 24);
+        assert_eq!(mmc1.mirroring(), Mirroring::Vertical);
+
+        for (mode, mirroring) in [
+            (0, Mirroring::SingleScreenLower),
+            (1, Mirroring::SingleScreenUpper),
+            (3, Mirroring::Horizontal),
+        ] {
+            mmc1_write(&mut mmc1, 0x8000, mode);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_mmc1_ignores_consecutive_writes(){
+        let mut mmc1 = board(1, &numbered_prg(8), &numbered_chr(8));
+        // Both writes of one read-modify-write instruction: only the first counts
+        for _ in 0..5 {
+            mmc1.cpu_write(0xE000, 1);
+            mmc1.cpu_write(0xE000, 0);
+            mmc1.cpu_clock(6);
+        }
+        // Five 1 bits: bank 15, which wraps to the last of the 8 banks
+        assert_eq!(mmc1.cpu_peek(0x8000), 7);
+    }
+
+    #[test]
+    fn test_mmc1_prg_ram(){
+        let mut mmc1 = board(1, &numbered_prg(2), &numbered_chr(8));
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+        mmc1_write(&mut mmc1, 0xE000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        mmc1_write(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_mmc1_surom_and_sorom(){
+        // SUROM: 512 KiB PRG-ROM, CHR-RAM, bit 4 of CHR bank 0 picks the 256 KiB half
+        let mut surom = board(1, &numbered_prg(32), &[]);
+        assert_eq!(surom.cpu_peek(0xC000), 15);
+        mmc1_write(&mut surom, 0xA000, 0x10);
+        assert_eq!(surom.cpu_peek(0xC000), 31);
+        mmc1_write(&mut surom, 0xE000, 2);
+        assert_eq!(surom.cpu_peek(0x8000), 18);
+
+        // SOROM: 16 KiB PRG-RAM (8 KiB work RAM and 8 KiB battery-backed), bit 3 picks
+        let mut raw = ines(0x12, 0x08, &numbered_prg(2), &[]);
+        raw[10] = 0x77;
+        raw[11] = 0x07;
+        let mut sorom = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        sorom.cpu_write(0x6000, 0x11);
+        mmc1_write(&mut sorom, 0xA000, 0x08);
+        assert_eq!(sorom.cpu_read(0x6000), 0);
+        sorom.cpu_write(0x6000, 0x22);
+        mmc1_write(&mut sorom, 0xA000, 0x00);
+        assert_eq!(sorom.cpu_read(0x6000), 0x11);
+    }
+
+    #[test]
+    fn test_snrom_prg_ram_disable(){
+        let mut snrom = board(1, &numbered_prg(16), &[]);
+        snrom.cpu_write(0x6000, 0x42);
+        mmc1_write(&mut snrom, 0xA000, 0x10);
+        assert_eq!(snrom.cpu_read(0x6000), 0);
+        mmc1_write(&mut snrom, 0xA000, 0x00);
+        assert_eq!(snrom.cpu_read(0x6000), 0x42);
+    }
+
+
+    // PRG-ROM that reads back $FF everywhere except bank-numbered marker bytes at the
+    // start of each 16 KiB bank, so bank-switch writes elsewhere don't hit conflicts
+    fn conflict_free_prg(banks: usize) -> Vec<u8> {
+        let mut prg = vec![0xFF; banks * 0x4000];
+        for bank in 0..banks {
+            prg[bank * 0x4000] = bank as u8;
+        }
+        prg
+    }
+
+    #[test]
+    fn test_uxrom_bank_switch(){
+        let mut uxrom = board(2, &conflict_free_prg(8), &[]);
+        assert_eq!(uxrom.cpu_peek(0xC000), 7);
+        uxrom.cpu_write(0x8001, 5);
+        assert_eq!(uxrom.cpu_peek(0x8000), 5);
+        assert_eq!(uxrom.cpu_peek(0xC000), 7);
+
+        // Bus conflict: the ROM holds 0 at $8000 of bank 0
+        uxrom.cpu_write(0x8001, 0);
+        uxrom.cpu_write(0x8000, 3);
+        assert_eq!(uxrom.cpu_peek(0x8000), 0);
+
+        uxrom.ppu_write(0x1234, 0x56);
+        assert_eq!(uxrom.ppu_read(0x1234), 0x56);
+    }
+
+    #[test]
+    fn test_cnrom_bank_switch(){
+        let mut cnrom = board(3, &conflict_free_prg(2), &numbered_chr(32));
+        cnrom.cpu_write(0x8001, 2);
+        assert_eq!(cnrom.ppu_read(0x0000), 16);
+        assert_eq!(cnrom.ppu_read(0x1FFF), 23);
+        cnrom.cpu_write(0x8000, 3);
+        assert_eq!(cnrom.ppu_read(0x0000), 0);
+    }
+
+    #[test]
+    fn test_axrom_bank_switch_and_mirroring(){
+        let mut axrom = board(7, &numbered_prg(8), &[]);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+        axrom.cpu_write(0x8000, 0x12);
+        assert_eq!(axrom.cpu_peek(0x8000), 4);
+        assert_eq!(axrom.cpu_peek(0xC000), 5);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_gxrom_bank_switch(){
+        let mut gxrom = board(66, &conflict_free_prg(8), &numbered_chr(32));
+        gxrom.cpu_write(0x8001, 0x13);
+        assert_eq!(gxrom.cpu_peek(0x8000), 2);
+        assert_eq!(gxrom.ppu_read(0x0000), 24);
+    }
+
+    #[test]
+    fn test_color_dreams_bank_switch(){
+        let mut color_dreams = board(11, &conflict_free_prg(8), &numbered_chr(32));
+        color_dreams.cpu_write(0x8001, 0x21);
+        assert_eq!(color_dreams.cpu_peek(0x8000), 2);
+        assert_eq!(color_dreams.ppu_read(0x0000), 16);
+    }
+
+    #[test]
+    fn test_bnrom_and_nina_001(){
+        let mut bnrom = board(34, &conflict_free_prg(8), &[]);
+        bnrom.cpu_write(0x8001, 3);
+        assert_eq!(bnrom.cpu_peek(0x8000), 6);
+
+        let mut nina = board(34, &numbered_prg(4), &numbered_chr(64));
+        nina.cpu_write(0x7FFD, 1);
+        nina.cpu_write(0x7FFE, 5);
+        nina.cpu_write(0x7FFF, 9);
+        assert_eq!(nina.cpu_peek(0x8000), 2);
+        assert_eq!(nina.ppu_read(0x0000), 20);
+        assert_eq!(nina.ppu_read(0x1000), 36);
+        assert_eq!(nina.cpu_peek(0x7FFF), 9);
+    }
+
+
+    // One scanline's worth of A12 activity: background fetches from $0000, then a
+    // sprite fetch from $1000
+    fn mmc3_scanline(mmc3: &mut dyn Mapper, line: u64) {
+        let dot = line * 341;
+        mmc3.ppu_address(0x0000, dot);
+        mmc3.ppu_address(0x1000, dot + 260);
+        mmc3.ppu_address(0x2000, dot + 262);
+        mmc3.ppu_address(0x1000, dot + 264);
+    }
+
+    #[test]
+    fn test_mmc3_banking(){
+        let mut mmc3 = board(4, &numbered_prg(8), &numbered_chr(64));
+        // 16 8 KiB banks: second-to-last and last are fixed
+        mmc3.cpu_write(0x8000, 6);
+        mmc3.cpu_write(0x8001, 3);
+        mmc3.cpu_write(0x8000, 7);
+        mmc3.cpu_write(0x8001, 4);
+        assert_eq!(mmc3.cpu_peek(0x8000), 1);
+        assert_eq!(mmc3.cpu_peek(0xA000), 2);
+        assert_eq!(mmc3.cpu_peek(0xC000), 7);
+        assert_eq!(mmc3.cpu_peek(0xE000), 7);
+        mmc3.cpu_write(0x8000, 0x40);
+        assert_eq!(mmc3.cpu_peek(0x8000), 7);
+        assert_eq!(mmc3.cpu_peek(0xC000), 1);
+
+        mmc3.cpu_write(0x8000, 0);
+        mmc3.cpu_write(0x8001, 9);
+        mmc3.cpu_write(0x8000, 5);
+        mmc3.cpu_write(0x8001, 33);
+        assert_eq!(mmc3.ppu_read(0x0000), 8);
+        assert_eq!(mmc3.ppu_read(0x0400), 9);
+        assert_eq!(mmc3.ppu_read(0x1C00), 33);
+        mmc3.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc3.ppu_read(0x1000), 8);
+        assert_eq!(mmc3.ppu_read(0x0C00), 33);
+
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+        mmc3.cpu_write(0xA000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_mmc3_prg_ram_protect(){
+        let mut mmc3 = board(4, &numbered_prg(8), &numbered_chr(64));
+        mmc3.cpu_write(0x6000, 0x42);
+        mmc3.cpu_write(0xA001, 0xC0);
+        mmc3.cpu_write(0x6000, 0x24);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x42);
+        mmc3.cpu_write(0xA001, 0x00);
+        assert_eq!(mmc3.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq(){
+        let mut mmc3 = board(4, &numbered_prg(8), &numbered_chr(64));
+        mmc3.cpu_write(0xC000, 2);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+        // Reload to 2, then 1, then 0 fires
+        mmc3_scanline(mmc3.as_mut(), 0);
+        mmc3_scanline(mmc3.as_mut(), 1);
+        assert!(!mmc3.irq());
+        mmc3_scanline(mmc3.as_mut(), 2);
+        assert!(mmc3.irq());
+
+        // $E000 acknowledges and disables
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq());
+        mmc3_scanline(mmc3.as_mut(), 3);
+        mmc3_scanline(mmc3.as_mut(), 4);
+        mmc3_scanline(mmc3.as_mut(), 5);
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn test_mmc3_zero_latch_revisions(){
+        let rom = || Rom::new(&ines(0x40, 0, &numbered_prg(8), &numbered_chr(64))).unwrap();
+        for (revision, fires) in [(Mmc3Revision::A, false), (Mmc3Revision::B, true)] {
+            let mut mmc3 = Mmc3::with_revision(rom(), revision);
+            mmc3.cpu_write(0xC000, 0);
+            mmc3.cpu_write(0xE001, 0);
+            // The counter starts at 0, so every clock reloads it with 0
+            mmc3_scanline(&mut mmc3, 0);
+            assert_eq!(mmc3.irq(), fires);
+            mmc3.cpu_write(0xC001, 0);
+            mmc3_scanline(&mut mmc3, 1);
+            assert!(mmc3.irq());
+        }
+    }
+
+    #[test]
+    fn test_mmc3_irq_reaches_cpu(){
+        use crate::bus::NesBus;
+        use crate::cpu::{CpuVariant, CPU};
+
+        let mut prg = numbered_prg(2);
+        // CLI ; JMP $8001 at $8000, with the IRQ vector at $8123
+        prg[..4].copy_from_slice(&[0x58, 0x4C, 0x01, 0x80]);
+        prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+        prg[0x7FFE..].copy_from_slice(&[0x23, 0x81]);
+        let rom = Rom::new(&ines(0x40, 0, &prg, &numbered_chr(8))).unwrap();
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap(), CpuVariant::Nes2A03);
+        cpu.reset();
+        cpu.mem_write(0xC000, 0);
+        cpu.mem_write(0xE001, 0);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8001);
+
+        mmc3_scanline(cpu.bus.mapper.as_mut(), 0);
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8123);
+    }
+
+
+    // PRG-ROM with every 8 KiB bank filled with its own bank number
+    fn numbered_prg_8k(banks: usize) -> Vec<u8> {
+        (0..banks).flat_map(|bank| vec![bank as u8; 0x2000]).collect()
+    }
+
+    #[test]
+    fn test_mmc5_prg_modes(){
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        // Powers up in 8 KiB mode with the last bank at $E000
+        assert_eq!(mmc5.cpu_peek(0xE000), 15);
+        mmc5.cpu_write(0x5114, 0x83);
+        mmc5.cpu_write(0x5115, 0x85);
+        mmc5.cpu_write(0x5116, 0x87);
+        assert_eq!(mmc5.cpu_peek(0x8000), 3);
+        assert_eq!(mmc5.cpu_peek(0xA000), 5);
+        assert_eq!(mmc5.cpu_peek(0xC000), 7);
+
+        // 16+8+8: $5115 in 16 KiB units with its low bit ignored
+        mmc5.cpu_write(0x5100, 2);
+        assert_eq!(mmc5.cpu_peek(0x8000), 4);
+        assert_eq!(mmc5.cpu_peek(0xA000), 5);
+        assert_eq!(mmc5.cpu_peek(0xC000), 7);
+
+        mmc5.cpu_write(0x5100, 1);
+        assert_eq!(mmc5.cpu_peek(0xC000), 14);
+        assert_eq!(mmc5.cpu_peek(0xE000), 15);
+
+        mmc5.cpu_write(0x5100, 0);
+        mmc5.cpu_write(0x5117, 0x85);
+        assert_eq!(mmc5.cpu_peek(0x8000), 4);
+        assert_eq!(mmc5.cpu_peek(0xE000), 7);
+    }
+
+    #[test]
+    fn test_mmc5_prg_ram(){
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+        mmc5.cpu_write(0x5102, 0b10);
+        mmc5.cpu_write(0x5103, 0b01);
+        mmc5.cpu_write(0x5113, 2);
+        mmc5.cpu_write(0x6000, 0x42);
+        // The same RAM bank mapped into the ROM area with bit 7 clear
+        mmc5.cpu_write(0x5114, 0x02);
+        assert_eq!(mmc5.cpu_read(0x8000), 0x42);
+        mmc5.cpu_write(0x8001, 0x24);
+        assert_eq!(mmc5.cpu_read(0x6001), 0x24);
+    }
+
+    #[test]
+    fn test_mmc5_chr_sets(){
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        mmc5.cpu_write(0x5101, 3);
+        for register in 0..8 {
+            mmc5.cpu_write(0x5120 + register, 10 + register as u8);
+        }
+        assert_eq!(mmc5.ppu_read(0x1C00), 17);
+        for register in 0..4 {
+            mmc5.cpu_write(0x5128 + register, 40 + register as u8);
+        }
+        // With 8x8 sprites the last set written is used for everything
+        assert_eq!(mmc5.ppu_read(0x0400), 41);
+        assert_eq!(mmc5.ppu_read(0x1400), 41);
+
+        // 8x16 sprites: set A for sprites, set B for the background
+        mmc5.ppu_register_write(0x2000, 0x20);
+        mmc5.ppu_register_write(0x2001, 0x18);
+        mmc5.scanline(261, true);
+        assert_eq!(mmc5.ppu_read(0x1400), 41);
+        mmc5.sprite_fetches(true);
+        assert_eq!(mmc5.ppu_read(0x1400), 15);
+
+        // $5130 supplies the upper bits of the bank written after it
+        let mut chr = vec![0; 512 * 1024];
+        chr[256 * 1024] = 0xAB;
+        let mut big = board(5, &numbered_prg_8k(16), &chr);
+        big.cpu_write(0x5130, 1);
+        big.cpu_write(0x5120, 0);
+        assert_eq!(big.ppu_read(0x0000), 0xAB);
+    }
+
+    #[test]
+    fn test_mmc5_nametables(){
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        let mut vram = [0u8; 0x800];
+        // NT0 -> CIRAM 1, NT1 -> ExRAM, NT2 -> fill, NT3 -> CIRAM 0
+        mmc5.cpu_write(0x5105, 0b00_11_10_01);
+        mmc5.cpu_write(0x5106, 0x77);
+        mmc5.cpu_write(0x5107, 2);
+        mmc5.nametable_write(0x2005, 0x11, &mut vram);
+        assert_eq!(vram[0x405], 0x11);
+        mmc5.nametable_write(0x2405, 0x22, &mut vram);
+        assert_eq!(mmc5.nametable_read(0x2405, &vram), 0x22);
+        assert_eq!(mmc5.nametable_read(0x2805, &vram), 0x77);
+        assert_eq!(mmc5.nametable_read(0x2BC5, &vram), 0xAA);
+        assert_eq!(mmc5.nametable_read(0x2C05, &vram), 0);
+
+        // As CPU RAM the ExRAM stops being a nametable
+        mmc5.cpu_write(0x5104, 2);
+        mmc5.cpu_write(0x5C05, 0x33);
+        assert_eq!(mmc5.cpu_read(0x5C05), 0x33);
+        assert_eq!(mmc5.nametable_read(0x2405, &vram), 0);
+    }
+
+    #[test]
+    fn test_mmc5_extended_attributes(){
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        let vram = [0u8; 0x800];
+        mmc5.cpu_write(0x5104, 2);
+        mmc5.cpu_write(0x5C21, 0b11_000101);
+        mmc5.cpu_write(0x5104, 1);
+        mmc5.ppu_register_write(0x2001, 0x18);
+        mmc5.scanline(261, true);
+        mmc5.nametable_read(0x2021, &vram);
+        assert_eq!(mmc5.nametable_read(0x23C0, &vram), 0xFF);
+        // 4 KiB bank 5 = 1 KiB units 20-23
+        assert_eq!(mmc5.ppu_read(0x0010), 20);
+        assert_eq!(mmc5.ppu_read(0x1C10), 23);
+    }
+
+    #[test]
+    fn test_mmc5_vertical_split(){
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        let vram = [0u8; 0x800];
+        mmc5.cpu_write(0x5104, 2);
+        // Row 1 of the split nametable, column 3
+        mmc5.cpu_write(0x5C23, 0x99);
+        mmc5.cpu_write(0x5104, 0);
+        // Left split 4 tiles wide, scrolled down 8 lines, CHR from 4 KiB bank 2
+        mmc5.cpu_write(0x5200, 0x84);
+        mmc5.cpu_write(0x5201, 8);
+        mmc5.cpu_write(0x5202, 2);
+        mmc5.ppu_register_write(0x2001, 0x18);
+        mmc5.scanline(261, true);
+        mmc5.scanline(0, true);
+        mmc5.scanline(1, true);
+
+        // Fetch 0 is tile 2, fetch 1 is tile 3: inside the split
+        mmc5.nametable_read(0x2000, &vram);
+        assert_eq!(mmc5.nametable_read(0x2001, &vram), 0x99);
+        // Scanline 2 + scroll 8 = line 10, fine Y 2
+        assert_eq!(mmc5.ppu_read(0x0998), 10);
+        // Tile 4 is outside and falls back to the nametable
+        assert_eq!(mmc5.nametable_read(0x2002, &vram), 0);
+    }
+
+    #[test]
+    fn test_mmc5_irq_and_multiplier(){
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 150);
+        assert_eq!(mmc5.cpu_read(0x5205), (30000 & 0xFF) as u8);
+        assert_eq!(mmc5.cpu_read(0x5206), (30000 >> 8) as u8);
+
+        mmc5.cpu_write(0x5203, 3);
+        mmc5.cpu_write(0x5204, 0x80);
+        mmc5.scanline(261, true);
+        assert_eq!(mmc5.cpu_peek(0x5204), 0x40);
+        for line in 0..2 {
+            mmc5.scanline(line, true);
+        }
+        assert!(!mmc5.irq());
+        mmc5.scanline(2, true);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(0x5204), 0xC0);
+        assert!(!mmc5.irq());
+
+        // Leaving the visible lines ends the frame
+        mmc5.scanline(239, true);
+        assert_eq!(mmc5.cpu_peek(0x5204), 0x00);
+    }
+
+    #[test]
+    fn test_mmc5_audio(){
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        mmc5.cpu_write(0x5015, 0b01);
+        mmc5.cpu_write(0x5000, 0b1011_1111);
+        mmc5.cpu_write(0x5002, 0x20);
+        mmc5.cpu_write(0x5003, 0x08);
+        assert_eq!(mmc5.cpu_peek(0x5015), 0b01);
+        let mut heard = false;
+        for _ in 0..200 {
+            mmc5.cpu_clock(1);
+            heard |= mmc5.audio_output() > 0.0;
+        }
+        assert!(heard);
+
+        mmc5.cpu_write(0x5015, 0);
+        assert_eq!(mmc5.cpu_peek(0x5015), 0);
+
+        mmc5.cpu_write(0x5011, 0x80);
+        assert!(mmc5.audio_output() > 0.0);
+        mmc5.cpu_write(0x5010, 0x80);
+        mmc5.cpu_write(0x5011, 0x00);
+        assert!(mmc5.irq());
+        mmc5.cpu_read(0x5010);
+        assert!(!mmc5.irq());
+    }
+
+    #[test]
+    fn test_mmc5_pulse_timer_keeps_time(){
+        // 12.5% duty at timer period 7: one rising edge every 16 * 8 CPU cycles, across
+        // as many frame sequencer steps as it takes
+        let mut mmc5 = board(5, &numbered_prg_8k(16), &numbered_chr(64));
+        mmc5.cpu_write(0x5015, 0b01);
+        mmc5.cpu_write(0x5000, 0b0011_1111);
+        mmc5.cpu_write(0x5002, 0x07);
+        mmc5.cpu_write(0x5003, 0x08);
+        let mut edges = Vec::new();
+        let mut high = false;
+        for cycle in 0..40_000 {
+            mmc5.cpu_clock(1);
+            let now = mmc5.audio_output() > 0.0;
+            if now && !high {
+                edges.push(cycle);
+            }
+            high = now;
+        }
+        assert!(edges.len() > 300);
+        assert!(edges.windows(2).all(|pair| pair[1] - pair[0] == 128));
+    }
+
+    fn nes2_board(number: u8, submapper: u8, prg_rom: &[u8], chr_rom: &[u8]) -> Box<dyn Mapper> {
+        let mut raw = ines((number & 0x0F) << 4, (number & 0xF0) | 0x08, prg_rom, chr_rom);
+        raw[8] = submapper << 4;
+        mapper::for_rom(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_vrc4_address_wiring(){
+        // VRC4e decodes A0/A1 from CPU A2/A3, VRC4f from A0/A1
+        let mut vrc4e = nes2_board(23, 2, &numbered_prg_8k(16), &numbered_chr(256));
+        vrc4e.cpu_write(0xB008, 0x05);
+        vrc4e.cpu_write(0xB00C, 0x01);
+        assert_eq!(vrc4e.ppu_read(0x0400), 0x15);
+        // CPU A0 isn't connected, so $B001 is just another $B000
+        vrc4e.cpu_write(0xB001, 0x07);
+        assert_eq!(vrc4e.ppu_read(0x0000), 0x07);
+
+        let mut vrc4f = nes2_board(23, 1, &numbered_prg_8k(16), &numbered_chr(256));
+        vrc4f.cpu_write(0xB002, 0x05);
+        vrc4f.cpu_write(0xB003, 0x01);
+        assert_eq!(vrc4f.ppu_read(0x0400), 0x15);
+
+        // Without a submapper both wirings are decoded
+        let mut either = board(23, &numbered_prg_8k(16), &numbered_chr(256));
+        either.cpu_write(0xC000, 0x02);
+        either.cpu_write(0xC004, 0x03);
+        assert_eq!(either.ppu_read(0x0800), 0x32);
+        either.cpu_write(0xC00C, 0x01);
+        either.cpu_write(0xC003, 0x02);
+        assert_eq!(either.ppu_read(0x0C00), 0x20);
+
+        // VRC4c sits on A6/A7
+        let mut vrc4c = nes2_board(21, 2, &numbered_prg_8k(16), &numbered_chr(256));
+        vrc4c.cpu_write(0xE080, 0x09);
+        assert_eq!(vrc4c.ppu_read(0x1C00), 0x09);
+    }
+
+    #[test]
+    fn test_vrc4_prg_banking(){
+        let mut vrc4 = nes2_board(25, 1, &numbered_prg_8k(16), &numbered_chr(8));
+        vrc4.cpu_write(0x8000, 3);
+        vrc4.cpu_write(0xA000, 5);
+        assert_eq!(vrc4.cpu_peek(0x8000), 3);
+        assert_eq!(vrc4.cpu_peek(0xA000), 5);
+        assert_eq!(vrc4.cpu_peek(0xC000), 14);
+        assert_eq!(vrc4.cpu_peek(0xE000), 15);
+
+        // VRC4b has A0 on CPU A1, so $9002 is written at $9001
+        vrc4.cpu_write(0x9001, 0b10);
+        assert_eq!(vrc4.cpu_peek(0x8000), 14);
+        assert_eq!(vrc4.cpu_peek(0xC000), 3);
+
+        vrc4.cpu_write(0x9000, 3);
+        assert_eq!(vrc4.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_vrc2_variants(){
+        // VRC2a drops the low bit of each CHR bank number
+        let mut vrc2a = board(22, &numbered_prg_8k(16), &numbered_chr(256));
+        vrc2a.cpu_write(0xB000, 0x06);
+        assert_eq!(vrc2a.ppu_read(0x0000), 0x03);
+        // All of $9000-$9003 set the mirroring bit; VRC2a wires A1 to CPU A0
+        vrc2a.cpu_write(0x9001, 1);
+        assert_eq!(vrc2a.mirroring(), Mirroring::Horizontal);
+        vrc2a.cpu_write(0x9003, 0);
+        assert_eq!(vrc2a.mirroring(), Mirroring::Vertical);
+        vrc2a.cpu_write(0x9002, 1);
+        assert_eq!(vrc2a.mirroring(), Mirroring::Horizontal);
+
+        // VRC2 has no swap mode, one mirroring bit and no IRQ
+        let mut vrc2b = nes2_board(23, 3, &numbered_prg_8k(16), &numbered_chr(8));
+        vrc2b.cpu_write(0x8000, 3);
+        vrc2b.cpu_write(0x9002, 0b10);
+        assert_eq!(vrc2b.cpu_peek(0x8000), 3);
+        vrc2b.cpu_write(0x9000, 3);
+        assert_eq!(vrc2b.mirroring(), Mirroring::Horizontal);
+        vrc2b.cpu_write(0xF000, 0x0F);
+        vrc2b.cpu_write(0xF001, 0x0F);
+        vrc2b.cpu_write(0xF002, 0b110);
+        vrc2b.cpu_clock(1);
+        assert!(!vrc2b.irq());
+
+        // Without a submapper, mapper 23 is VRC2b only when the board has no PRG-RAM
+        let mut vrc2b = nes2_board(23, 0, &numbered_prg_8k(16), &numbered_chr(8));
+        vrc2b.cpu_write(0x6000, 0xFF);
+        assert_eq!(vrc2b.cpu_peek(0x6000), 1);
+        vrc2b.cpu_write(0x9000, 3);
+        assert_eq!(vrc2b.mirroring(), Mirroring::Horizontal);
+        let mut vrc4 = board(23, &numbered_prg_8k(16), &numbered_chr(8));
+        vrc4.cpu_write(0x6000, 0xFF);
+        assert_eq!(vrc4.cpu_peek(0x6000), 0xFF);
+        vrc4.cpu_write(0x9000, 3);
+        assert_eq!(vrc4.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_vrc4_irq_modes(){
+        let mut vrc4 = nes2_board(23, 1, &numbered_prg_8k(16), &numbered_chr(8));
+        // Cycle mode: a latch of $FE overflows on the second CPU cycle
+        vrc4.cpu_write(0xF000, 0x0E);
+        vrc4.cpu_write(0xF001, 0x0F);
+        vrc4.cpu_write(0xF002, 0b111);
+        vrc4.cpu_clock(1);
+        assert!(!vrc4.irq());
+        vrc4.cpu_clock(1);
+        assert!(vrc4.irq());
+        // Acknowledging keeps it running because of the A bit
+        vrc4.cpu_write(0xF003, 0);
+        assert!(!vrc4.irq());
+        vrc4.cpu_clock(2);
+        assert!(vrc4.irq());
+
+        // Scanline mode: the prescaler clocks the counter every 113-114 CPU cycles
+        vrc4.cpu_write(0xF002, 0b010);
+        assert!(!vrc4.irq());
+        vrc4.cpu_clock(200);
+        assert!(!vrc4.irq());
+        vrc4.cpu_clock(30);
+        assert!(vrc4.irq());
+        // Without the A bit the counter stops after acknowledging
+        vrc4.cpu_write(0xF003, 0);
+        vrc4.cpu_clock(250);
+        assert!(!vrc4.irq());
+    }
+
+    #[test]
+    fn test_vrc6_banking(){
+        let mut vrc6 = board(24, &numbered_prg_8k(16), &numbered_chr(256));
+        vrc6.cpu_write(0x8000, 2);
+        vrc6.cpu_write(0xC000, 9);
+        assert_eq!(vrc6.cpu_peek(0x8000), 4);
+        assert_eq!(vrc6.cpu_peek(0xA000), 5);
+        assert_eq!(vrc6.cpu_peek(0xC000), 9);
+        assert_eq!(vrc6.cpu_peek(0xE000), 15);
+        vrc6.cpu_write(0xE003, 0x42);
+        assert_eq!(vrc6.ppu_read(0x1C00), 0x42);
+
+        // PRG-RAM and mirroring through $B003
+        vrc6.cpu_write(0x6000, 0x55);
+        assert_eq!(vrc6.cpu_peek(0x6000), 0);
+        vrc6.cpu_write(0xB003, 0xA4);
+        vrc6.cpu_write(0x6000, 0x55);
+        assert_eq!(vrc6.cpu_peek(0x6000), 0x55);
+        assert_eq!(vrc6.mirroring(), Mirroring::Horizontal);
+
+        // VRC6b swaps A0 and A1
+        let mut vrc6b = board(26, &numbered_prg_8k(16), &numbered_chr(256));
+        vrc6b.cpu_write(0xD001, 0x33);
+        assert_eq!(vrc6b.ppu_read(0x0800), 0x33);
+    }
+
+    #[test]
+    fn test_vrc6_irq(){
+        let mut vrc6 = board(24, &numbered_prg_8k(16), &numbered_chr(8));
+        vrc6.cpu_write(0xF000, 0xFD);
+        vrc6.cpu_write(0xF001, 0b110);
+        vrc6.cpu_clock(2);
+        assert!(!vrc6.irq());
+        vrc6.cpu_clock(1);
+        assert!(vrc6.irq());
+        vrc6.cpu_write(0xF002, 0);
+        assert!(!vrc6.irq());
+    }
+
+    #[test]
+    fn test_vrc6_audio(){
+        let mut vrc6 = board(24, &numbered_prg_8k(16), &numbered_chr(8));
+        assert_eq!(vrc6.audio_output(), 0.0);
+
+        // A constant-volume pulse is heard right away
+        vrc6.cpu_write(0x9000, 0x8F);
+        vrc6.cpu_write(0x9002, 0x80);
+        assert!(vrc6.audio_output() > 0.0);
+        vrc6.cpu_write(0x9002, 0x00);
+        assert_eq!(vrc6.audio_output(), 0.0);
+
+        // A 50% duty pulse alternates between volume and silence
+        vrc6.cpu_write(0xA000, 0x7F);
+        vrc6.cpu_write(0xA001, 0x00);
+        vrc6.cpu_write(0xA002, 0x80);
+        let mut levels = std::collections::HashSet::new();
+        for _ in 0..64 {
+            vrc6.cpu_clock(1);
+            levels.insert(vrc6.audio_output().to_bits());
+        }
+        assert_eq!(levels.len(), 2);
+        vrc6.cpu_write(0xA002, 0x00);
+
+        // The sawtooth ramps up and resets
+        vrc6.cpu_write(0xB000, 0x2A);
+        vrc6.cpu_write(0xB002, 0x80);
+        let mut peak = 0.0f32;
+        for _ in 0..14 {
+            vrc6.cpu_clock(1);
+            peak = peak.max(vrc6.audio_output());
+        }
+        assert!(peak > 0.0);
+        assert_eq!(vrc6.audio_output(), 0.0);
+
+        // Halt freezes every channel
+        vrc6.cpu_write(0x9003, 0x01);
+        vrc6.cpu_clock(2);
+        let frozen = vrc6.audio_output();
+        vrc6.cpu_clock(10);
+        assert_eq!(vrc6.audio_output(), frozen);
+    }
+
+    #[test]
+    fn test_vrc_save_state(){
+        let mut vrc4 = nes2_board(21, 1, &numbered_prg_8k(16), &numbered_chr(256));
+        vrc4.cpu_write(0x8000, 7);
+        vrc4.cpu_write(0xB000, 0x0A);
+        vrc4.cpu_write(0xF000, 0x0F);
+        vrc4.cpu_write(0xF002, 0x0F);
+        vrc4.cpu_write(0xF004, 0b110);
+        let mut state = StateWriter::new();
+        vrc4.save_state(&mut state);
+        let bytes = state.into_bytes();
+
+        let mut restored = nes2_board(21, 1, &numbered_prg_8k(16), &numbered_chr(256));
+        restored.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!(restored.cpu_peek(0x8000), 7);
+        assert_eq!(restored.ppu_read(0x0000), 0x0A);
+        restored.cpu_clock(1);
+        assert!(restored.irq());
+
+        let mut vrc6 = board(24, &numbered_prg_8k(16), &numbered_chr(8));
+        assert_eq!(
+            vrc6.load_state(&mut StateReader::new(&bytes[..4])).err(),
+            Some(StateError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn test_mmc2_chr_latches(){
+        let mut mmc2 = board(9, &numbered_prg_8k(16), &numbered_chr(128));
+        assert_eq!(mmc2.cpu_peek(0x8000), 0);
+        assert_eq!(mmc2.cpu_peek(0xA000), 13);
+        assert_eq!(mmc2.cpu_peek(0xE000), 15);
+        mmc2.cpu_write(0xA000, 5);
+        assert_eq!(mmc2.cpu_peek(0x8000), 5);
+
+        // 4 KiB banks, so 1 KiB labels 4, 8, 12 and 16
+        mmc2.cpu_write(0xB000, 1);
+        mmc2.cpu_write(0xC000, 2);
+        mmc2.cpu_write(0xD000, 3);
+        mmc2.cpu_write(0xE000, 4);
+        // Both latches power up on $FE
+        assert_eq!(mmc2.ppu_read(0x0000), 8);
+        assert_eq!(mmc2.ppu_read(0x1000), 16);
+
+        // The tile that sets the latch still comes from the old bank
+        assert_eq!(mmc2.ppu_read(0x0FD8), 11);
+        assert_eq!(mmc2.ppu_read(0x0000), 4);
+        // The left half only reacts to $0FE8 itself
+        mmc2.ppu_read(0x0FE9);
+        assert_eq!(mmc2.ppu_read(0x0000), 4);
+        mmc2.ppu_read(0x0FE8);
+        assert_eq!(mmc2.ppu_read(0x0000), 8);
+
+        // The right half reacts to the whole row range
+        mmc2.ppu_read(0x1FDB);
+        assert_eq!(mmc2.ppu_read(0x1000), 12);
+        mmc2.ppu_read(0x1FEF);
+        assert_eq!(mmc2.ppu_read(0x1000), 16);
+
+        mmc2.cpu_write(0xF000, 1);
+        assert_eq!(mmc2.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_mmc4_chr_latches(){
+        let mut mmc4 = board(10, &numbered_prg(8), &numbered_chr(128));
+        mmc4.cpu_write(0xA000, 3);
+        assert_eq!(mmc4.cpu_peek(0x8000), 3);
+        assert_eq!(mmc4.cpu_peek(0xC000), 7);
+        mmc4.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc4.cpu_peek(0x6000), 0x42);
+
+        mmc4.cpu_write(0xB000, 1);
+        mmc4.cpu_write(0xC000, 2);
+        mmc4.ppu_read(0x0FDD);
+        assert_eq!(mmc4.ppu_read(0x0000), 4);
+        mmc4.ppu_read(0x0FEC);
+        assert_eq!(mmc4.ppu_read(0x0000), 8);
+
+        let mut state = StateWriter::new();
+        mmc4.save_state(&mut state);
+        let bytes = state.into_bytes();
+        let mut restored = board(10, &numbered_prg(8), &numbered_chr(128));
+        restored.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!(restored.ppu_read(0x0000), 8);
+        assert_eq!(restored.cpu_peek(0x6000), 0x42);
+    }
+
+    fn fme7_command(fme7: &mut Box<dyn Mapper>, command: u8, parameter: u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xA000, parameter);
+    }
+
+    #[test]
+    fn test_fme7_banking(){
+        let mut fme7 = board(69, &numbered_prg_8k(32), &numbered_chr(256));
+        assert_eq!(fme7.cpu_peek(0xE000), 31);
+        fme7_command(&mut fme7, 0x9, 4);
+        fme7_command(&mut fme7, 0xA, 5);
+        fme7_command(&mut fme7, 0xB, 6);
+        assert_eq!(fme7.cpu_peek(0x8000), 4);
+        assert_eq!(fme7.cpu_peek(0xA000), 5);
+        assert_eq!(fme7.cpu_peek(0xC000), 6);
+        for bank in 0..8 {
+            fme7_command(&mut fme7, bank, 0x80 + bank);
+        }
+        assert_eq!(fme7.ppu_read(0x1C00), 0x87);
+
+        // $6000 holds ROM, then open bus while RAM is selected but disabled, then RAM
+        fme7_command(&mut fme7, 0x8, 0x03);
+        assert_eq!(fme7.cpu_peek(0x6000), 3);
+        fme7_command(&mut fme7, 0x8, 0x40);
+        fme7.cpu_write(0x6000, 0x55);
+        assert_eq!(fme7.cpu_peek(0x6000), 0);
+        fme7_command(&mut fme7, 0x8, 0xC0);
+        fme7.cpu_write(0x6000, 0x55);
+        assert_eq!(fme7.cpu_peek(0x6000), 0x55);
+
+        fme7_command(&mut fme7, 0xC, 3);
+        assert_eq!(fme7.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_fme7_irq(){
+        let mut fme7 = board(69, &numbered_prg_8k(32), &numbered_chr(8));
+        fme7_command(&mut fme7, 0xE, 0x02);
+        fme7_command(&mut fme7, 0xF, 0x00);
+        // Counting without the IRQ enabled wraps silently
+        fme7_command(&mut fme7, 0xD, 0x80);
+        fme7.cpu_clock(3);
+        assert!(!fme7.irq());
+
+        fme7_command(&mut fme7, 0xE, 0x02);
+        fme7_command(&mut fme7, 0xF, 0x00);
+        fme7_command(&mut fme7, 0xD, 0x81);
+        fme7.cpu_clock(2);
+        assert!(!fme7.irq());
+        fme7.cpu_clock(1);
+        assert!(fme7.irq());
+        fme7_command(&mut fme7, 0xD, 0x00);
+        assert!(!fme7.irq());
+        fme7.cpu_clock(100);
+        assert!(!fme7.irq());
+    }
+
+    #[test]
+    fn test_sunsoft_5b_audio(){
+        let mut fme7 = board(69, &numbered_prg_8k(32), &numbered_chr(8));
+        let write = |fme7: &mut Box<dyn Mapper>, register: u8, data: u8| {
+            fme7.cpu_write(0xC000, register);
+            fme7.cpu_write(0xE000, data);
+        };
+        assert_eq!(fme7.audio_output(), 0.0);
+
+        // Tone A only, full volume, period 1: toggles every 16 CPU cycles
+        write(&mut fme7, 0x07, 0b11_1110);
+        write(&mut fme7, 0x00, 0x01);
+        write(&mut fme7, 0x08, 0x0F);
+        let mut levels = std::collections::HashSet::new();
+        for _ in 0..64 {
+            fme7.cpu_clock(1);
+            levels.insert(fme7.audio_output().to_bits());
+        }
+        assert_eq!(levels.len(), 2);
+
+        // Each volume step is 3 dB
+        write(&mut fme7, 0x07, 0b11_1111);
+        let full = fme7.audio_output();
+        assert!(full > 0.0);
+        write(&mut fme7, 0x08, 0x0D);
+        let ratio = fme7.audio_output() / full;
+        assert!((ratio - 10f32.powf(-6.0 / 20.0)).abs() < 0.001);
+
+        // A decaying envelope (shape 0) ends silent and stays there
+        write(&mut fme7, 0x08, 0x10);
+        write(&mut fme7, 0x0B, 0x01);
+        write(&mut fme7, 0x0D, 0x00);
+        let start = fme7.audio_output();
+        fme7.cpu_clock(8 * 16);
+        assert!(fme7.audio_output() < start);
+        for _ in 0..4 {
+            fme7.cpu_clock(100);
+        }
+        assert_eq!(fme7.audio_output(), 0.0);
+
+        // A held attack (shape 13) ends at full volume
+        write(&mut fme7, 0x0D, 0x0D);
+        for _ in 0..4 {
+            fme7.cpu_clock(100);
+        }
+        assert_eq!(fme7.audio_output(), full);
+
+        // Noise alone comes and goes
+        write(&mut fme7, 0x07, 0b11_0111);
+        write(&mut fme7, 0x08, 0x0F);
+        let mut levels = std::collections::HashSet::new();
+        for _ in 0..100 {
+            fme7.cpu_clock(16);
+            levels.insert(fme7.audio_output().to_bits());
+        }
+        assert_eq!(levels.len(), 2);
+
+        let mut state = StateWriter::new();
+        fme7.save_state(&mut state);
+        let bytes = state.into_bytes();
+        let mut restored = board(69, &numbered_prg_8k(32), &numbered_chr(8));
+        restored.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!(restored.audio_output(), fme7.audio_output());
+    }
+
+    #[test]
+    fn test_namco163_banking(){
+        let mut n163 = board(19, &numbered_prg_8k(16), &numbered_chr(256));
+        assert_eq!(n163.cpu_peek(0xE000), 15);
+        n163.cpu_write(0xE000, 3);
+        n163.cpu_write(0xE800, 4);
+        n163.cpu_write(0xF000, 5);
+        assert_eq!(n163.cpu_peek(0x8000), 3);
+        assert_eq!(n163.cpu_peek(0xA000), 4);
+        assert_eq!(n163.cpu_peek(0xC000), 5);
+        n163.cpu_write(0xB800, 0x42);
+        assert_eq!(n163.ppu_read(0x1C00), 0x42);
+
+        // PRG-RAM only takes writes once $F800 unlocks it, quarter by quarter
+        n163.cpu_write(0x6000, 0x11);
+        assert_eq!(n163.cpu_peek(0x6000), 0);
+        n163.cpu_write(0xF800, 0x41);
+        n163.cpu_write(0x6000, 0x11);
+        n163.cpu_write(0x6800, 0x22);
+        assert_eq!(n163.cpu_peek(0x6000), 0);
+        assert_eq!(n163.cpu_peek(0x6800), 0x22);
+    }
+
+    #[test]
+    fn test_namco163_ciram_mapping(){
+        let mut n163 = board(19, &numbered_prg_8k(16), &numbered_chr(256));
+        let mut vram = vec![0; 0x800];
+        vram[0x400] = 0x99;
+
+        // Nametables in CIRAM page 1 and in CHR-ROM
+        n163.cpu_write(0xC000, 0xE1);
+        n163.cpu_write(0xD000, 0x20);
+        assert_eq!(n163.nametable_read(0x2000, &vram), 0x99);
+        assert_eq!(n163.nametable_read(0x2400, &vram), 0x99);
+        assert_eq!(n163.nametable_read(0x2800, &vram), 0x20);
+        n163.nametable_write(0x2000, 0x55, &mut vram);
+        assert_eq!(vram[0x400], 0x55);
+
+        // CIRAM in the pattern tables, unless $E800 turns it off for that half
+        n163.cpu_write(0x8000, 0xE0);
+        n163.cpu_write(0xA000, 0xE0);
+        n163.pattern_write(0x0010, 0x77, &mut vram);
+        assert_eq!(vram[0x10], 0x77);
+        assert_eq!(n163.pattern_read(0x1010, &vram), 0x77);
+        n163.cpu_write(0xE800, 0x80);
+        assert_eq!(n163.pattern_read(0x0010, &vram), 0x77);
+        assert_eq!(n163.pattern_read(0x1010, &vram), 0xE0);
+    }
+
+    #[test]
+    fn test_namco163_irq(){
+        let mut n163 = board(19, &numbered_prg_8k(16), &numbered_chr(8));
+        n163.cpu_write(0x5000, 0xFD);
+        n163.cpu_write(0x5800, 0xFF);
+        assert_eq!(n163.cpu_peek(0x5800), 0xFF);
+        n163.cpu_clock(1);
+        assert!(!n163.irq());
+        n163.cpu_clock(5);
+        assert!(n163.irq());
+        // The counter stops at $7FFF
+        assert_eq!(n163.cpu_peek(0x5000), 0xFF);
+        n163.cpu_write(0x5000, 0);
+        assert!(!n163.irq());
+    }
+
+    #[test]
+    fn test_namco163_sound(){
+        let mut n163 = board(19, &numbered_prg_8k(16), &numbered_chr(8));
+        // Fill the sound RAM through the auto-incrementing port
+        n163.cpu_write(0xF800, 0x80);
+        for value in 0..0x80u8 {
+            n163.cpu_write(0x4800, value);
+        }
+        n163.cpu_write(0xF800, 0x85);
+        assert_eq!(n163.cpu_read(0x4800), 5);
+        assert_eq!(n163.cpu_read(0x4800), 6);
+        assert_eq!(n163.cpu_peek(0x4800), 7);
+
+        // One channel: a 4-sample wave of $F at full volume
+        let mut registers = [0u8; 0x80];
+        registers[0] = 0xFF;
+        registers[1] = 0xFF;
+        registers[0x78] = 0x00;
+        registers[0x7A] = 0x00;
+        registers[0x7C] = 0xFC;
+        registers[0x7E] = 0x00;
+        registers[0x7F] = 0x0F;
+        n163.cpu_write(0xF800, 0x80);
+        for value in registers {
+            n163.cpu_write(0x4800, value);
+        }
+        assert_eq!(n163.audio_output(), 0.0);
+        n163.cpu_clock(15);
+        let one_channel = n163.audio_output();
+        assert!(one_channel > 0.0);
+
+        // A second, silent channel halves how much of the first is heard
+        n163.cpu_write(0xF800, 0x7F);
+        n163.cpu_write(0x4800, 0x1F);
+        n163.cpu_clock(30);
+        assert_eq!(n163.audio_output(), one_channel / 2.0);
+
+        // $E000 bit 6 mutes the chip
+        n163.cpu_write(0xE000, 0x40);
+        assert_eq!(n163.audio_output(), 0.0);
+    }
+
+    #[test]
+    fn test_namco163_battery(){
+        let raw = ines(0x32, 0x10, &numbered_prg_8k(16), &numbered_chr(8));
+        let mut n163 = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        n163.cpu_write(0xF800, 0x40);
+        n163.cpu_write(0x6000, 0x12);
+        n163.cpu_write(0x4800, 0x34);
+        let data = n163.battery_data().unwrap();
+        assert_eq!(data.len(), 0x2000 + 0x80);
+
+        let mut restored = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        restored.load_battery_data(&data);
+        assert_eq!(restored.cpu_peek(0x6000), 0x12);
+        restored.cpu_write(0xF800, 0x40);
+        assert_eq!(restored.cpu_peek(0x4800), 0x34);
+
+        assert!(board(19, &numbered_prg_8k(16), &numbered_chr(8)).battery_data().is_none());
+    }
+
+    // Drives a Bandai EEPROM through $800D: bit 5 is SCL, bit 6 SDA, bit 7 read enable
+    struct I2cMaster<'a> {
+        board: &'a mut Box<dyn Mapper>,
+        lsb_first: bool,
+    }
+
+    impl I2cMaster<'_> {
+        fn lines(&mut self, scl: bool, sda: bool) {
+            self.board.cpu_write(0x800D, 0x80 | (sda as u8) << 6 | (scl as u8) << 5);
+        }
+
+        fn start(&mut self) {
+            self.lines(false, true);
+            self.lines(true, true);
+            self.lines(true, false);
+            self.lines(false, false);
+        }
+
+        fn stop(&mut self) {
+            self.lines(false, false);
+            self.lines(true, false);
+            self.lines(true, true);
+        }
+
+        fn sda_in(&self) -> bool {
+            self.board.cpu_peek(0x6000) & 0x10 != 0
+        }
+
+        // Sends a byte and returns whether the chip acknowledged it
+        fn send(&mut self, byte: u8) -> bool {
+            for bit in 0..8 {
+                let position = if self.lsb_first { bit } else { 7 - bit };
+                let sda = (byte >> position) & 1 != 0;
+                self.lines(false, sda);
+                self.lines(true, sda);
+                self.lines(false, sda);
+            }
+            self.lines(false, true);
+            self.lines(true, true);
+            let ack = !self.sda_in();
+            self.lines(false, true);
+            ack
+        }
+
+        fn receive(&mut self, ack: bool) -> u8 {
+            let mut byte = 0;
+            for bit in 0..8 {
+                self.lines(false, true);
+                self.lines(true, true);
+                let position = if self.lsb_first { bit } else { 7 - bit };
+                byte |= (self.sda_in() as u8) << position;
+                self.lines(false, true);
+            }
+            self.lines(false, !ack);
+            self.lines(true, !ack);
+            self.lines(false, !ack);
+            byte
+        }
+    }
+
+    #[test]
+    fn test_bandai_banking_and_irq(){
+        let mut fcg = nes2_board(16, 4, &numbered_prg(8), &numbered_chr(256));
+        assert_eq!(fcg.cpu_peek(0xC000), 7);
+        fcg.cpu_write(0x6008, 3);
+        fcg.cpu_write(0x6007, 0x42);
+        fcg.cpu_write(0x6009, 1);
+        assert_eq!(fcg.cpu_peek(0x8000), 3);
+        assert_eq!(fcg.ppu_read(0x1C00), 0x42);
+        assert_eq!(fcg.mirroring(), Mirroring::Horizontal);
+        // The FCG ignores $8000 writes and loads its counter directly
+        fcg.cpu_write(0x8008, 5);
+        assert_eq!(fcg.cpu_peek(0x8000), 3);
+        fcg.cpu_write(0x600B, 3);
+        fcg.cpu_write(0x600C, 0);
+        fcg.cpu_write(0x600A, 1);
+        fcg.cpu_clock(2);
+        assert!(!fcg.irq());
+        fcg.cpu_clock(1);
+        assert!(fcg.irq());
+        fcg.cpu_write(0x600A, 0);
+        assert!(!fcg.irq());
+
+        // The LZ93D50 latches the count until $800A
+        let mut lz93d50 = nes2_board(16, 5, &numbered_prg(8), &numbered_chr(256));
+        lz93d50.cpu_write(0x800B, 3);
+        lz93d50.cpu_write(0x800C, 0);
+        lz93d50.cpu_clock(10);
+        assert!(!lz93d50.irq());
+        lz93d50.cpu_write(0x800A, 1);
+        lz93d50.cpu_clock(3);
+        assert!(lz93d50.irq());
+        lz93d50.cpu_write(0x6008, 5);
+        assert_eq!(lz93d50.cpu_peek(0x8000), 0);
+    }
+
+    #[test]
+    fn test_bandai_24c02_eeprom(){
+        let mut cart = nes2_board(16, 5, &numbered_prg(8), &numbered_chr(8));
+        let mut eeprom = I2cMaster { board: &mut cart, lsb_first: false };
+        // Write two bytes from address $10
+        eeprom.start();
+        assert!(eeprom.send(0xA0));
+        assert!(eeprom.send(0x10));
+        assert!(eeprom.send(0x12));
+        assert!(eeprom.send(0x34));
+        eeprom.stop();
+
+        // Another device address goes unanswered
+        eeprom.start();
+        assert!(!eeprom.send(0xB0));
+        eeprom.stop();
+
+        // Set the address, then read back with a repeated start
+        eeprom.start();
+        assert!(eeprom.send(0xA0));
+        assert!(eeprom.send(0x10));
+        eeprom.start();
+        assert!(eeprom.send(0xA1));
+        assert_eq!(eeprom.receive(true), 0x12);
+        assert_eq!(eeprom.receive(false), 0x34);
+        eeprom.stop();
+
+        let data = cart.battery_data().unwrap();
+        assert_eq!(data.len(), 256);
+        assert_eq!(&data[0x10..0x12], &[0x12, 0x34]);
+
+        let mut restored = nes2_board(16, 5, &numbered_prg(8), &numbered_chr(8));
+        restored.load_battery_data(&data);
+        let mut eeprom = I2cMaster { board: &mut restored, lsb_first: false };
+        eeprom.start();
+        assert!(eeprom.send(0xA0));
+        assert!(eeprom.send(0x11));
+        eeprom.start();
+        assert!(eeprom.send(0xA1));
+        assert_eq!(eeprom.receive(false), 0x34);
+        eeprom.stop();
+    }
+
+    #[test]
+    fn test_bandai_x24c01_eeprom(){
+        let mut cart = board(159, &numbered_prg(8), &numbered_chr(8));
+        let mut eeprom = I2cMaster { board: &mut cart, lsb_first: true };
+        // No device address: 7 address bits then the read/write bit, LSB first
+        eeprom.start();
+        assert!(eeprom.send(0x05));
+        assert!(eeprom.send(0xAB));
+        eeprom.stop();
+        eeprom.start();
+        assert!(eeprom.send(0x80 | 0x05));
+        assert_eq!(eeprom.receive(false), 0xAB);
+        eeprom.stop();
+        assert_eq!(cart.battery_data().unwrap().len(), 128);
+
+        // Save states carry the EEPROM along
+        let mut state = StateWriter::new();
+        cart.save_state(&mut state);
+        let bytes = state.into_bytes();
+        let mut restored = board(159, &numbered_prg(8), &numbered_chr(8));
+        restored.load_state(&mut StateReader::new(&bytes)).unwrap();
+        assert_eq!(restored.battery_data().unwrap()[5], 0xAB);
+    }
+
+    #[test]
+    fn test_bandai_153_prg_ram_and_outer_bank(){
+        let raw = ines(0x92, 0x90, &numbered_prg(32), &[]);
+        let mut cart = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        assert_eq!(cart.cpu_peek(0xC000), 15);
+        cart.cpu_write(0x8002, 1);
+        assert_eq!(cart.cpu_peek(0xC000), 31);
+        cart.cpu_write(0x8008, 2);
+        assert_eq!(cart.cpu_peek(0x8000), 18);
+
+        cart.cpu_write(0x6000, 0x55);
+        assert_eq!(cart.cpu_peek(0x6000), 0);
+        cart.cpu_write(0x800D, 0x20);
+        cart.cpu_write(0x6000, 0x55);
+        assert_eq!(cart.cpu_peek(0x6000), 0x55);
+        assert_eq!(cart.battery_data().unwrap()[0], 0x55);
+    }
+
+    #[test]
+    fn test_unrom512_banking_and_mirroring(){
+        let mut unrom512 = board(30, &numbered_prg(32), &[]);
+        assert_eq!(unrom512.cpu_peek(0xC000), 31);
+        unrom512.cpu_write(0x8000, 0b0100_0101);
+        assert_eq!(unrom512.cpu_peek(0x8000), 5);
+        // 32 KiB of CHR-RAM in four 8 KiB banks
+        unrom512.ppu_write(0x0000, 0x22);
+        unrom512.cpu_write(0x8000, 0);
+        assert_eq!(unrom512.ppu_read(0x0000), 0);
+        unrom512.cpu_write(0x8000, 0b0100_0000);
+        assert_eq!(unrom512.ppu_read(0x0000), 0x22);
+        assert_eq!(unrom512.mirroring(), Mirroring::Horizontal);
+
+        // %10: one screen picked by bit 7
+        let raw = ines(0xE8, 0x10, &numbered_prg(32), &[]);
+        let mut one_screen = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        assert_eq!(one_screen.mirroring(), Mirroring::SingleScreenLower);
+        one_screen.cpu_write(0x8000, 0x80);
+        assert_eq!(one_screen.mirroring(), Mirroring::SingleScreenUpper);
+
+        // %11: four screens kept in the last CHR-RAM bank
+        let raw = ines(0xE9, 0x10, &numbered_prg(32), &[]);
+        let mut four_screen = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        let mut vram = vec![0; 0x800];
+        four_screen.nametable_write(0x2C00, 0x44, &mut vram);
+        assert!(vram.iter().all(|&byte| byte == 0));
+        assert_eq!(four_screen.nametable_read(0x2C00, &vram), 0x44);
+        four_screen.cpu_write(0x8000, 0x60);
+        assert_eq!(four_screen.ppu_read(0x0C00), 0x44);
+    }
+
+    fn flash_command(cart: &mut Box<dyn Mapper>, bank: u8, addr: u16, data: u8) {
+        cart.cpu_write(0xC000, bank);
+        cart.cpu_write(addr, data);
+    }
+
+    fn flash_unlock(cart: &mut Box<dyn Mapper>) {
+        flash_command(cart, 1, 0x9555, 0xAA);
+        flash_command(cart, 0, 0xAAAA, 0x55);
+    }
+
+    #[test]
+    fn test_unrom512_flash_saves(){
+        let raw = ines(0xE2, 0x10, &numbered_prg(32), &[]);
+        let mut cart = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        assert_eq!(cart.battery_data().unwrap(), Vec::<u8>::new());
+
+        // Without the unlock sequence the register just changes
+        cart.cpu_write(0xC000, 3);
+        cart.cpu_write(0x8000, 0x00);
+        assert_eq!(cart.cpu_peek(0x8000), 3);
+
+        // Program a byte in bank 3: bits can only be cleared
+        flash_unlock(&mut cart);
+        flash_command(&mut cart, 1, 0x9555, 0xA0);
+        flash_command(&mut cart, 3, 0x8010, 0xF1);
+        assert_eq!(cart.cpu_peek(0x8010), 0x01);
+        assert_eq!(cart.cpu_peek(0x8011), 0x03);
+
+        // Software ID mode
+        flash_unlock(&mut cart);
+        flash_command(&mut cart, 1, 0x9555, 0x90);
+        assert_eq!(cart.cpu_peek(0x8000), 0xBF);
+        assert_eq!(cart.cpu_peek(0x8001), 0xB7);
+        cart.cpu_write(0x8000, 0xF0);
+        cart.cpu_write(0xC000, 3);
+        assert_eq!(cart.cpu_peek(0x8000), 3);
+
+        // Erase the second sector of bank 5
+        flash_unlock(&mut cart);
+        flash_command(&mut cart, 1, 0x9555, 0x80);
+        flash_unlock(&mut cart);
+        flash_command(&mut cart, 5, 0x9234, 0x30);
+        cart.cpu_write(0xC000, 5);
+        assert_eq!(cart.cpu_peek(0x8FFF), 5);
+        assert_eq!(cart.cpu_peek(0x9000), 0xFF);
+        assert_eq!(cart.cpu_peek(0x9FFF), 0xFF);
+        assert_eq!(cart.cpu_peek(0xA000), 5);
+
+        // Only the two changed sectors are saved, and they come back on load
+        let data = cart.battery_data().unwrap();
+        assert_eq!(data.len(), 2 * (2 + 4 + 0x1000));
+        let mut restored = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        restored.load_battery_data(&data);
+        restored.cpu_write(0xC000, 3);
+        assert_eq!(restored.cpu_peek(0x8010), 0x01);
+        restored.cpu_write(0xC000, 5);
+        assert_eq!(restored.cpu_peek(0x9000), 0xFF);
+
+        // Chip erase
+        flash_unlock(&mut cart);
+        flash_command(&mut cart, 1, 0x9555, 0x80);
+        flash_unlock(&mut cart);
+        flash_command(&mut cart, 1, 0x9555, 0x10);
+        assert_eq!(cart.cpu_peek(0xC000), 0xFF);
+
+        // Boards without the battery bit have no flash saves
+        assert!(board(30, &numbered_prg(32), &[]).battery_data().is_none());
+    }
+
+    // NES 2.0 exponent notation can give a board less PRG than its fixed windows span
+    #[test]
+    fn test_prg_smaller_than_a_bank(){
+        let prg_rom: Vec<u8> = (0..0x2000).map(|i| (i >> 8) as u8).collect();
+        let supported = [
+            0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 16, 19, 21, 22, 23, 24, 25, 26, 30, 34, 66, 69, 153, 159,
+        ];
+        for number in supported {
+            let mut raw = ines((number & 0x0F) << 4, (number & 0xF0) | 0x08, &prg_rom, &[]);
+            // 2^13 * 1 bytes
+            raw[4] = 13 << 2;
+            raw[9] = 0x0F;
+            let cart = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+            assert_eq!(cart.cpu_peek(0x8000), 0x00, "mapper {}", number);
+            assert_eq!(cart.cpu_peek(0xFFFF), 0x1F, "mapper {}", number);
+        }
+    }
+
+    #[test]
+    fn test_unrom512_small_nes2_chips(){
+        // 6 KiB of flash PRG and 4 KiB of CHR-RAM on a four-screen board
+        let prg_rom: Vec<u8> = (0..0x1800).map(|i| (i >> 12) as u8).collect();
+        let mut raw = ines(0xEB, 0x18, &prg_rom, &[]);
+        // 2^11 * 3 bytes
+        raw[4] = (11 << 2) | 1;
+        raw[9] = 0x0F;
+        // 64 << 6 bytes
+        raw[11] = 6;
+        let mut cart = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        assert_eq!(cart.mirroring(), Mirroring::FourScreen);
+        let mut vram = [0u8; 0x800];
+        cart.nametable_write(0x2C05, 0x42, &mut vram);
+        assert_eq!(cart.nametable_read(0x2C05, &vram), 0x42);
+        assert_eq!(cart.ppu_read(0x0C05), 0x42);
+
+        // Erasing the partial last sector stops at the end of the chip
+        flash_unlock(&mut cart);
+        flash_command(&mut cart, 1, 0x9555, 0x80);
+        flash_unlock(&mut cart);
+        flash_command(&mut cart, 0, 0x9000, 0x30);
+        cart.cpu_write(0xC000, 0);
+        assert_eq!(cart.cpu_peek(0x8FFF), 0);
+        assert_eq!(cart.cpu_peek(0x9000), 0xFF);
+        assert_eq!(cart.cpu_peek(0x97FF), 0xFF);
+
+        let data = cart.battery_data().unwrap();
+        assert_eq!(data.len(), 2 + 4 + 0x800);
+        let mut restored = mapper::for_rom(Rom::new(&raw).unwrap()).unwrap();
+        restored.load_battery_data(&data);
+        assert_eq!(restored.cpu_peek(0x9000), 0xFF);
+    }
+
+}
+
+#[cfg(test)]
+mod trace{
+    use crate::cpu::{CpuVariant, CPU};
+    use crate::trace::{disassemble, trace};
+
+    #[test]
+    fn test_format_trace(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.mem_write(100, 0xa2);
+        cpu.mem_write(101, 0x01);
+        cpu.mem_write(102, 0xca);
+        cpu.mem_write(103, 0x88);
+        cpu.mem_write(104, 0x00);
+        cpu.program_counter = 0x64;
+        cpu.register_a = 1;
+        cpu.register_x = 2;
+        cpu.register_y = 3;
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| {
+            result.push(trace(cpu));
+        });
+        assert_eq!(
+            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD",
+            result[0]
+        );
+        assert_eq!(
+            "0067  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD",
+            result[1]
+        );
+    }
+
+    #[test]
+    fn test_format_mem_access(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        // ORA ($33),Y
+        cpu.mem_write(100, 0x11);
+        cpu.mem_write(101, 0x33);
+
+        cpu.mem_write(0x33, 0x00);
+        cpu.mem_write(0x34, 0x04);
+        cpu.mem_write(0x400, 0xAA);
+
+        cpu.program_counter = 0x64;
+        cpu.register_y = 0;
+        assert_eq!(
+            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_format_unofficial(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.load(vec![0x04, 0xA9]);
+        cpu.reset();
+        assert_eq!(
+            "0600  04 A9    *NOP $A9 = 00                    A:00 X:00 Y:00 P:24 SP:FD",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_disassemble(){
+        let mut cpu = CPU::new(CpuVariant::Nes2A03);
+        cpu.load(vec![0x6C, 0xFF, 0x02, 0xD0, 0xFE, 0x0A, 0x02]);
+        assert_eq!(disassemble(&cpu, 0x0600), (String::from("JMP ($02ff)"), 3));
+        assert_eq!(disassemble(&cpu, 0x0603), (String::from("BNE $0603"), 2));
+        assert_eq!(disassemble(&cpu, 0x0605), (String::from("ASL A"), 1));
+        assert_eq!(disassemble(&cpu, 0x0606), (String::from("JAM"), 1));
+
+        let mut cpu = CPU::new(CpuVariant::Wdc65C02);
+        cpu.load(vec![0x8F, 0x12, 0x01, 0xB2, 0x34]);
+        assert_eq!(disassemble(&cpu, 0x0600), (String::from("BBS0 $12,$0604"), 3));
+        assert_eq!(disassemble(&cpu, 0x0603), (String::from("LDA ($34)"), 2));
+    }
+}
+
+mod battery{
+    use crate::battery::SaveFile;
+    use std::path::{Path, PathBuf};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nes_emulator_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_save_file_path(){
+        let rom = Path::new("/games/zelda.nes");
+        assert_eq!(SaveFile::for_rom(rom, None).path(), Path::new("/games/zelda.sav"));
+        assert_eq!(
+            SaveFile::for_rom(rom, Some(Path::new("/saves"))).path(),
+            Path::new("/saves/zelda.sav")
+        );
+    }
+
+    #[test]
+    fn test_save_file_round_trip(){
+        let dir = temp_dir("round_trip");
+        let rom = dir.join("game.nes");
+        let mut save = SaveFile::for_rom(&rom, Some(&dir.join("saves")));
+        assert_eq!(save.load().unwrap(), None);
+
+        // The directory is created on the first write, and no temporary file is left
+        save.flush(&[1, 2, 3]).unwrap();
+        assert_eq!(std::fs::read(dir.join("saves/game.sav")).unwrap(), vec![1, 2, 3]);
+        assert!(!dir.join("saves/game.sav.tmp").exists());
+
+        let mut reopened = SaveFile::for_rom(&rom, Some(&dir.join("saves")));
+        assert_eq!(reopened.load().unwrap(), Some(vec![1, 2, 3]));
+
+        // An unchanged save isn't written again
+        std::fs::remove_file(dir.join("saves/game.sav")).unwrap();
+        reopened.flush(&[1, 2, 3]).unwrap();
+        assert!(!dir.join("saves/game.sav").exists());
+        reopened.flush(&[4, 5]).unwrap();
+        assert_eq!(std::fs::read(dir.join("saves/game.sav")).unwrap(), vec![4, 5]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod ppu{
+    use super::{ines, test_rom};
+    use crate::bus::{Bus, NesBus};
+    use crate::cartridge::Rom;
+    use crate::cpu::{CpuVariant, CPU};
+    use crate::ppu::{PpuStatus, FRAME_WIDTH};
+
+    fn nes_bus(flags_6: u8) -> NesBus {
+        NesBus::new(Rom::new(&ines(flags_6, 0, &[0; 0x4000], &[])).unwrap()).unwrap()
+    }
+
+    fn set_address(bus: &mut NesBus, addr: u16) {
+        bus.write(0x2006, (addr >> 8) as u8);
+        bus.write(0x2006, addr as u8);
+    }
+
+    fn write_vram(bus: &mut NesBus, addr: u16, data: u8) {
+        set_address(bus, addr);
+        bus.write(0x2007, data);
+    }
+
+    // CHR-ROM where tile 1 is solid colour 1 and tile 2 solid colour 2, tile 3 has just
+    // its top-left pixel set and tile $105 is solid colour 1 again. The backdrop is $0F,
+    // colour 1 of palettes 0 and 1 $30 and $16, and colour 2 of palette 0 $27.
+    fn chr_bus(flags_6: u8) -> NesBus {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0x10..0x18].fill(0xFF);
+        chr_rom[0x28..0x30].fill(0xFF);
+        chr_rom[0x30] = 0x80;
+        chr_rom[0x1050..0x1058].fill(0xFF);
+        let raw = ines(flags_6, 0, &[0; 0x4000], &chr_rom);
+        let mut bus = NesBus::new(Rom::new(&raw).unwrap()).unwrap();
+        for (addr, colour) in [(0x3F00, 0x0F), (0x3F01, 0x30), (0x3F02, 0x27), (0x3F05, 0x16)] {
+            write_vram(&mut bus, addr, colour);
+        }
+        bus
+    }
+
+    fn scroll(bus: &mut NesBus, nametable: u8, x: u8, y: u8) {
+        bus.write(0x2000, nametable);
+        bus.write(0x2005, x);
+        bus.write(0x2005, y);
+    }
+
+    // The first frame after power on starts without a pre-render line, so the second
+    // one is the first complete picture
+    fn render_frames(bus: &mut NesBus, frames: u64) {
+        let end = bus.ppu.frame + frames;
+        while bus.ppu.frame < end {
+            bus.tick(1);
+        }
+    }
+
+    fn pixel(bus: &NesBus, x: usize, y: usize) -> u8 {
+        bus.ppu.frame_buffer[y * FRAME_WIDTH + x]
+    }
+
+    // chr_bus with sprite colours $2A ($3F11) and $21 ($3F15)
+    fn sprite_bus() -> NesBus {
+        let mut bus = chr_bus(0);
+        write_vram(&mut bus, 0x3F11, 0x2A);
+        write_vram(&mut bus, 0x3F15, 0x21);
+        scroll(&mut bus, 0, 0, 0);
+        bus
+    }
+
+    fn write_sprite(bus: &mut NesBus, index: u8, sprite: [u8; 4]) {
+        bus.write(0x2003, index * 4);
+        for byte in sprite {
+            bus.write(0x2004, byte);
+        }
+    }
+
+    // Runs to the start of `line` of the current or next frame. The status flags are
+    // cleared again at the end of each frame, so this is where to look at them.
+    fn run_to_line(bus: &mut NesBus, line: u16) {
+        while bus.ppu.scanline == line {
+            bus.tick(1);
+        }
+        while bus.ppu.scanline != line {
+            bus.tick(1);
+        }
+    }
+
+    // Moves every sprite below the picture
+    fn hide_sprites(bus: &mut NesBus) {
+        for index in 0..64 {
+            write_sprite(bus, index, [0xEF, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_scroll_and_address_registers(){
+        let mut bus = nes_bus(0);
+        // The worked example from the wiki's "PPU scrolling" page
+        bus.write(0x2000, 0x00);
+        bus.read(0x2002);
+        bus.write(0x2005, 0x7D);
+        assert_eq!(bus.ppu.t, 0x000F);
+        assert_eq!(bus.ppu.x, 0b101);
+        assert!(bus.ppu.w);
+        bus.write(0x2005, 0x5E);
+        assert_eq!(bus.ppu.t, 0x616F);
+        assert!(!bus.ppu.w);
+        bus.write(0x2006, 0x3D);
+        assert_eq!(bus.ppu.t, 0x3D6F);
+        bus.write(0x2006, 0xF0);
+        assert_eq!(bus.ppu.t, 0x3DF0);
+        assert_eq!(bus.ppu.v, 0x3DF0);
+
+        // PPUCTRL's nametable bits land in t, and reading PPUSTATUS resets w
+        bus.write(0x2000, 0x00);
+        assert_eq!(bus.ppu.t, 0x31F0);
+        bus.write(0x2005, 0x00);
+        bus.read(0x2002);
+        bus.write(0x2005, 0xFF);
+        assert!(bus.ppu.w);
+        assert_eq!(bus.ppu.t & 0x1F, 0x1F);
+    }
+
+    #[test]
+    fn test_ppudata_buffered_read(){
+        let mut bus = nes_bus(0);
+        set_address(&mut bus, 0x2000);
+        for value in [0x11, 0x22, 0x33] {
+            bus.write(0x2007, value);
+        }
+        set_address(&mut bus, 0x2000);
+        // The first read returns the stale buffer, each later one the previous byte
+        bus.read(0x2007);
+        assert_eq!(bus.peek(0x2007), 0x11);
+        assert_eq!(bus.read(0x2007), 0x11);
+        assert_eq!(bus.read(0x2007), 0x22);
+        assert_eq!(bus.ppu.v, 0x2003);
+
+        // Increment by 32 walks down a column
+        bus.write(0x2000, 0x04);
+        set_address(&mut bus, 0x2001);
+        bus.write(0x2007, 0x44);
+        bus.write(0x2007, 0x55);
+        assert_eq!(bus.ppu.v, 0x2041);
+        assert_eq!(bus.ppu.vram[0x01], 0x44);
+        assert_eq!(bus.ppu.vram[0x21], 0x55);
+
+        // Pattern tables go to the cartridge's CHR-RAM
+        bus.write(0x2000, 0x00);
+        set_address(&mut bus, 0x1234);
+        bus.write(0x2007, 0x66);
+        set_address(&mut bus, 0x1234);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_palette_reads_and_mirrors(){
+        let mut bus = nes_bus(0);
+        // The nametable byte under the palette ends up in the read buffer
+        set_address(&mut bus, 0x2F00);
+        bus.write(0x2007, 0x77);
+        set_address(&mut bus, 0x3F10);
+        bus.write(0x2007, 0x2A);
+        bus.write(0x2007, 0x16);
+        set_address(&mut bus, 0x3F00);
+        assert_eq!(bus.read(0x2007) & 0x3F, 0x2A);
+        assert_eq!(bus.read(0x2002) & 0x1F, 0x2A & 0x1F);
+
+        // $3F10/$14/$18/$1C share bytes with $3F00/$04/$08/$0C, the rest don't
+        assert_eq!(bus.ppu.palette[0x00], 0x2A);
+        assert_eq!(bus.ppu.palette[0x11], 0x16);
+        assert_eq!(bus.ppu.palette[0x01], 0x00);
+        set_address(&mut bus, 0x3F1C);
+        bus.write(0x2007, 0x30);
+        assert_eq!(bus.ppu.palette[0x0C], 0x30);
+        // $3F20-$3FFF mirror the 32 bytes, and grayscale masks the colour away
+        set_address(&mut bus, 0x3FEC);
+        assert_eq!(bus.read(0x2007) & 0x3F, 0x30);
+        bus.write(0x2001, 0x01);
+        set_address(&mut bus, 0x3F11);
+        assert_eq!(bus.read(0x2007) & 0x3F, 0x10);
+
+        set_address(&mut bus, 0x3F00);
+        bus.read(0x2007);
+        set_address(&mut bus, 0x0000);
+        assert_eq!(bus.read(0x2007), 0x77);
+    }
+
+    #[test]
+    fn test_nametable_mirroring(){
+        // Horizontal: $2000 = $2400, $2800 = $2C00
+        let mut bus = nes_bus(0);
+        set_address(&mut bus, 0x2005);
+        bus.write(0x2007, 0xAB);
+        set_address(&mut bus, 0x2405);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0xAB);
+        set_address(&mut bus, 0x2805);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0x00);
+
+        // Vertical: $2000 = $2800, and $3000-$3EFF mirrors $2000-$2EFF
+        let mut bus = nes_bus(0x01);
+        set_address(&mut bus, 0x2005);
+        bus.write(0x2007, 0xCD);
+        set_address(&mut bus, 0x3805);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0xCD);
+        set_address(&mut bus, 0x2405);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0x00);
+
+        // Four-screen: every nametable is its own
+        let mut bus = nes_bus(0x08);
+        for table in 0..4 {
+            set_address(&mut bus, 0x2000 + table * 0x400);
+            bus.write(0x2007, table as u8 + 1);
+        }
+        assert_eq!(bus.ppu.vram[0xC00], 4);
+        assert_eq!(bus.ppu.vram[0x400], 2);
+    }
+
+    #[test]
+    fn test_oam_access_and_dma(){
+        let mut bus = nes_bus(0);
+        bus.write(0x2003, 0xFE);
+        bus.write(0x2004, 0x12);
+        bus.write(0x2004, 0x34);
+        bus.write(0x2004, 0x56);
+        assert_eq!(bus.ppu.oam[0xFE], 0x12);
+        assert_eq!(bus.ppu.oam[0xFF], 0x34);
+        assert_eq!(bus.ppu.oam[0x00], 0x56);
+        // Reads don't advance OAMADDR, and attribute bytes lack bits 2-4
+        bus.write(0x2003, 0x02);
+        bus.write(0x2004, 0xFF);
+        bus.write(0x2003, 0x02);
+        assert_eq!(bus.read(0x2004), 0xE3);
+        assert_eq!(bus.read(0x2004), 0xE3);
+
+        // DMA copies a whole page, starting at OAMADDR
+        for i in 0..=0xFF {
+            bus.write(0x0300 + i, i as u8);
+        }
+        bus.write(0x2003, 0x10);
+        bus.write(0x4014, 0x03);
+        assert_eq!(bus.ppu.oam[0x10], 0x00);
+        assert_eq!(bus.ppu.oam[0x0F], 0xFF);
+        assert_eq!(bus.ppu.oam_addr, 0x10);
+    }
+
+    #[test]
+    fn test_vblank_flag_and_nmi(){
+        let mut bus = nes_bus(0);
+        // Vblank starts at dot 1 of line 241: 82182 dots in, 27394 CPU cycles
+        for _ in 0..27393 {
+            bus.tick(1);
+        }
+        assert!(!bus.ppu.status.contains(PpuStatus::VBLANK));
+        bus.tick(1);
+        assert_eq!(bus.peek(0x2002) & 0x80, 0x80);
+        // Write-only registers read back the last value on the bus
+        bus.write(0x2001, 0x1F);
+        assert_eq!(bus.read(0x2002), 0x9F);
+        assert_eq!(bus.read(0x2002), 0x1F);
+
+        let mut prg_rom = vec![0xEA; 0x4000];
+        // LDA #$80 ; STA $2000 ; JMP $C005
+        prg_rom[..8].copy_from_slice(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0xC0]);
+        prg_rom[0x3FFA..].copy_from_slice(&[0x00, 0xD0, 0x00, 0xC0, 0x00, 0xC0]);
+        let mut cpu = CPU::with_bus(NesBus::new(test_rom(prg_rom)).unwrap(), CpuVariant::Nes2A03);
+        cpu.reset();
+        while cpu.program_counter < 0xD000 {
+            assert!(cpu.cycles < 30_000, "no NMI");
+            cpu.step();
+        }
+        assert_eq!(cpu.bus.ppu.scanline, 241);
+        // The flag stays set through the handler until $2002 is read, and clears on
+        // its own at the pre-render line
+        assert_eq!(cpu.mem_read(0x2002) & 0x80, 0x80);
+        assert_eq!(cpu.mem_read(0x2002) & 0x80, 0x00);
+        cpu.bus.write(0x2000, 0x00);
+        cpu.bus.write(0x2000, 0x80);
+        assert!(!cpu.bus.nmi());
+        while cpu.bus.ppu.scanline != 0 {
+            cpu.step();
+        }
+        assert!(!cpu.bus.ppu.status.contains(PpuStatus::VBLANK));
+    }
+
+    #[test]
+    fn test_background_tiles_and_attributes(){
+        let mut bus = chr_bus(0);
+        write_vram(&mut bus, 0x2000 + 3 * 32 + 1, 1);
+        write_vram(&mut bus, 0x2000 + 5 * 32 + 6, 2);
+        // Palette 1 for the bottom-right 2x2 tiles of the first attribute byte's area
+        write_vram(&mut bus, 0x2000 + 3 * 32 + 3, 1);
+        write_vram(&mut bus, 0x23C0, 0b01_00_00_00);
+        scroll(&mut bus, 0, 0, 0);
+        bus.write(0x2001, 0x0A);
+        render_frames(&mut bus, 2);
+
+        assert_eq!(pixel(&bus, 7, 24), 0x0F);
+        assert_eq!(pixel(&bus, 8, 24), 0x30);
+        assert_eq!(pixel(&bus, 15, 31), 0x30);
+        assert_eq!(pixel(&bus, 16, 24), 0x0F);
+        assert_eq!(pixel(&bus, 24, 24), 0x16);
+        assert_eq!(pixel(&bus, 31, 31), 0x16);
+        assert_eq!(pixel(&bus, 16, 32), 0x0F);
+        assert_eq!(pixel(&bus, 48, 40), 0x27);
+        assert_eq!(pixel(&bus, 55, 47), 0x27);
+        assert_eq!(pixel(&bus, 56, 47), 0x0F);
+    }
+
+    #[test]
+    fn test_background_fine_scroll_and_clipping(){
+        let mut bus = chr_bus(0);
+        write_vram(&mut bus, 0x2000 + 32 + 1, 1);
+        // Three pixels right and five down moves tile (1, 1) to x 5-12, y 3-10
+        scroll(&mut bus, 0, 3, 5);
+        bus.write(0x2001, 0x0A);
+        render_frames(&mut bus, 2);
+        assert_eq!(pixel(&bus, 4, 3), 0x0F);
+        assert_eq!(pixel(&bus, 5, 3), 0x30);
+        assert_eq!(pixel(&bus, 12, 10), 0x30);
+        assert_eq!(pixel(&bus, 13, 10), 0x0F);
+        assert_eq!(pixel(&bus, 12, 11), 0x0F);
+
+        // Without SHOW_BACKGROUND_LEFT the first 8 pixels show the backdrop
+        bus.write(0x2001, 0x08);
+        render_frames(&mut bus, 1);
+        assert_eq!(pixel(&bus, 7, 3), 0x0F);
+        assert_eq!(pixel(&bus, 8, 3), 0x30);
+
+        // And with rendering off everything is the backdrop
+        bus.write(0x2001, 0x00);
+        render_frames(&mut bus, 1);
+        assert_eq!(pixel(&bus, 8, 3), 0x0F);
+    }
+
+    #[test]
+    fn test_background_scroll_wraps_across_nametables(){
+        // Vertical mirroring: column 31 of $2000 is followed by column 0 of $2400
+        let mut bus = chr_bus(0x01);
+        write_vram(&mut bus, 0x2000 + 31, 1);
+        write_vram(&mut bus, 0x2400, 2);
+        write_vram(&mut bus, 0x2000, 1);
+        scroll(&mut bus, 0, 248, 0);
+        bus.write(0x2001, 0x0A);
+        render_frames(&mut bus, 2);
+        assert_eq!(pixel(&bus, 0, 0), 0x30);
+        assert_eq!(pixel(&bus, 8, 0), 0x27);
+        assert_eq!(pixel(&bus, 16, 0), 0x0F);
+
+        // Horizontal mirroring: row 29 of $2000 is followed by row 0 of $2800
+        let mut bus = chr_bus(0);
+        write_vram(&mut bus, 0x2000 + 29 * 32, 1);
+        write_vram(&mut bus, 0x2800, 2);
+        scroll(&mut bus, 0, 0, 232);
+        bus.write(0x2001, 0x0A);
+        render_frames(&mut bus, 2);
+        assert_eq!(pixel(&bus, 0, 7), 0x30);
+        assert_eq!(pixel(&bus, 0, 8), 0x27);
+        assert_eq!(pixel(&bus, 0, 16), 0x0F);
+    }
+
+    #[test]
+    fn test_odd_frames_skip_a_dot_while_rendering(){
+        let mut bus = chr_bus(0);
+        let frame_length = |bus: &mut NesBus| {
+            let frame = bus.ppu.frame;
+            let mut dots = 0;
+            while bus.ppu.frame == frame {
+                bus.ppu.tick(1, bus.mapper.as_mut());
+                dots += 1;
+            }
+            dots
+        };
+        // The CPU ticks three dots at a time, so the first count only lines up the start
+        frame_length(&mut bus);
+        assert_eq!(frame_length(&mut bus), 89342);
+        assert_eq!(frame_length(&mut bus), 89342);
+        bus.write(0x2001, 0x08);
+        let lengths = [frame_length(&mut bus), frame_length(&mut bus)];
+        assert!(lengths.contains(&89341) && lengths.contains(&89342));
+    }
+
+    #[test]
+    fn test_sprites_position_and_flips(){
+        let mut bus = sprite_bus();
+        hide_sprites(&mut bus);
+        // Y is the line above the top, so this solid tile covers x 20-27, y 11-18
+        write_sprite(&mut bus, 0, [10, 1, 0x00, 20]);
+        write_sprite(&mut bus, 1, [40, 3, 0x01, 40]);
+        write_sprite(&mut bus, 2, [40, 3, 0x40, 60]);
+        write_sprite(&mut bus, 3, [40, 3, 0x80, 80]);
+        write_sprite(&mut bus, 4, [40, 3, 0xC0, 100]);
+        bus.write(0x2001, 0x1E);
+        render_frames(&mut bus, 2);
+
+        assert_eq!(pixel(&bus, 20, 10), 0x0F);
+        assert_eq!(pixel(&bus, 20, 11), 0x2A);
+        assert_eq!(pixel(&bus, 27, 18), 0x2A);
+        assert_eq!(pixel(&bus, 28, 18), 0x0F);
+        assert_eq!(pixel(&bus, 27, 19), 0x0F);
+        // The single pixel lands in each corner, in palette 5 for the first one
+        assert_eq!(pixel(&bus, 40, 41), 0x21);
+        assert_eq!(pixel(&bus, 41, 41), 0x0F);
+        assert_eq!(pixel(&bus, 67, 41), 0x2A);
+        assert_eq!(pixel(&bus, 60, 41), 0x0F);
+        assert_eq!(pixel(&bus, 80, 48), 0x2A);
+        assert_eq!(pixel(&bus, 80, 41), 0x0F);
+        assert_eq!(pixel(&bus, 107, 48), 0x2A);
+
+        // Sprites in the first 8 pixels need SHOW_SPRITES_LEFT
+        write_sprite(&mut bus, 0, [10, 1, 0x00, 4]);
+        bus.write(0x2001, 0x1A);
+        render_frames(&mut bus, 1);
+        assert_eq!(pixel(&bus, 7, 11), 0x0F);
+        assert_eq!(pixel(&bus, 8, 11), 0x2A);
+    }
+
+    #[test]
+    fn test_tall_sprites(){
+        let mut bus = sprite_bus();
+        hide_sprites(&mut bus);
+        // Tile 5: the $1000 table, tile 4 on top (empty) and tile 5 below (solid)
+        write_sprite(&mut bus, 0, [10, 5, 0x00, 20]);
+        write_sprite(&mut bus, 1, [10, 5, 0x80, 40]);
+        bus.write(0x2000, 0x20);
+        bus.write(0x2001, 0x1E);
+        render_frames(&mut bus, 2);
+        assert_eq!(pixel(&bus, 20, 18), 0x0F);
+        assert_eq!(pixel(&bus, 20, 19), 0x2A);
+        assert_eq!(pixel(&bus, 20, 26), 0x2A);
+        assert_eq!(pixel(&bus, 20, 27), 0x0F);
+        // Flipped vertically the solid half is on top
+        assert_eq!(pixel(&bus, 40, 11), 0x2A);
+        assert_eq!(pixel(&bus, 40, 18), 0x2A);
+        assert_eq!(pixel(&bus, 40, 19), 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_priority(){
+        let mut bus = sprite_bus();
+        hide_sprites(&mut bus);
+        // Background tile 1 at x 16-23, y 16-23
+        write_vram(&mut bus, 0x2000 + 2 * 32 + 2, 1);
+        scroll(&mut bus, 0, 0, 0);
+        // A sprite behind the background shows only where the background is clear
+        write_sprite(&mut bus, 0, [15, 1, 0x20, 12]);
+        // Lower OAM indexes win, even when they are behind the background: sprite 1
+        // is hidden under sprite 0 and so under the background too
+        write_sprite(&mut bus, 1, [15, 1, 0x01, 14]);
+        // And in front of the background a sprite covers it
+        write_sprite(&mut bus, 2, [30, 1, 0x00, 16]);
+        write_vram(&mut bus, 0x2000 + 4 * 32 + 2, 1);
+        scroll(&mut bus, 0, 0, 0);
+        bus.write(0x2001, 0x1E);
+        render_frames(&mut bus, 2);
+
+        assert_eq!(pixel(&bus, 12, 16), 0x2A);
+        assert_eq!(pixel(&bus, 16, 16), 0x30);
+        assert_eq!(pixel(&bus, 19, 16), 0x30);
+        assert_eq!(pixel(&bus, 20, 16), 0x21);
+        assert_eq!(pixel(&bus, 24, 16), 0x0F);
+        assert_eq!(pixel(&bus, 16, 32), 0x2A);
+        assert_eq!(pixel(&bus, 16, 30), 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_limit_and_overflow(){
+        let mut bus = sprite_bus();
+        hide_sprites(&mut bus);
+        // Nine sprites on one line: the ninth isn't drawn and the flag is set
+        for index in 0..9 {
+            write_sprite(&mut bus, index, [50, 1, 0x00, index * 10]);
+        }
+        bus.write(0x2001, 0x1E);
+        render_frames(&mut bus, 2);
+        assert_eq!(pixel(&bus, 70, 51), 0x2A);
+        assert_eq!(pixel(&bus, 80, 51), 0x0F);
+        run_to_line(&mut bus, 100);
+        assert_ne!(bus.ppu.status.bits() & 0x20, 0);
+
+        // Exactly eight, followed by one whose tile number matches the line: the
+        // buggy search reads byte 1 of entry 9 as a Y coordinate and sets the flag
+        write_sprite(&mut bus, 8, [0xEF, 0, 0, 0]);
+        write_sprite(&mut bus, 9, [0xEF, 50, 0, 0]);
+        render_frames(&mut bus, 1);
+        run_to_line(&mut bus, 100);
+        assert_ne!(bus.ppu.status.bits() & 0x20, 0);
+
+        // With the tile moved out of range too, there's no overflow
+        write_sprite(&mut bus, 9, [0xEF, 0, 0, 0]);
+        render_frames(&mut bus, 1);
+        run_to_line(&mut bus, 100);
+        assert_eq!(bus.ppu.status.bits() & 0x20, 0);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_timing(){
+        let mut bus = sprite_bus();
+        hide_sprites(&mut bus);
+        // Background tile 1 at x 8-15, y 16-23; sprite 0 overlaps it from (12, 20)
+        write_vram(&mut bus, 0x2000 + 2 * 32 + 1, 1);
+        scroll(&mut bus, 0, 0, 0);
+        write_sprite(&mut bus, 0, [19, 1, 0x20, 12]);
+        bus.write(0x2001, 0x1E);
+        render_frames(&mut bus, 2);
+        run_to_line(&mut bus, 100);
+        assert_ne!(bus.ppu.status.bits() & 0x40, 0);
+
+        // Pixel (12, 20) is drawn at dot 13 of line 20
+        let tick_to = |bus: &mut NesBus, line: u16, dot: u16| {
+            while (bus.ppu.scanline, bus.ppu.dot) != (line, dot) {
+                bus.ppu.tick(1, bus.mapper.as_mut());
+            }
+        };
+        tick_to(&mut bus, 0, 0);
+        assert_eq!(bus.ppu.status.bits() & 0x40, 0);
+        tick_to(&mut bus, 20, 12);
+        assert_eq!(bus.ppu.status.bits() & 0x40, 0);
+        tick_to(&mut bus, 20, 13);
+        assert_ne!(bus.ppu.status.bits() & 0x40, 0);
+
+        // No hit over a transparent background, or in the clipped left column
+        write_sprite(&mut bus, 0, [19, 1, 0x00, 30]);
+        render_frames(&mut bus, 2);
+        run_to_line(&mut bus, 100);
+        assert_eq!(bus.ppu.status.bits() & 0x40, 0);
+        bus.write(0x2001, 0x00);
+        write_sprite(&mut bus, 0, [19, 1, 0x00, 0]);
+        write_vram(&mut bus, 0x2000 + 2 * 32, 1);
+        scroll(&mut bus, 0, 0, 0);
+        bus.write(0x2001, 0x18);
+        render_frames(&mut bus, 2);
+        run_to_line(&mut bus, 100);
+        assert_eq!(bus.ppu.status.bits() & 0x40, 0);
+    }
+}
+
+#[cfg(test)]
+mod mixer{
+    use crate::mixer::{Mixer, CPU_CLOCK_HZ};
+
+    #[test]
+    fn test_mixer_sample_rate(){
+        let mut mixer = Mixer::new(48_000);
+        for _ in 0..CPU_CLOCK_HZ as u32 / 7 {
+            mixer.clock(0.0, 7);
+        }
+        let samples = mixer.take_samples();
+        assert!((samples.len() as i64 - 48_000).abs() <= 1);
+        assert!(samples.iter().all(|&sample| sample == 0.0));
+        assert!(mixer.take_samples().is_empty());
+    }
+
+    #[test]
+    fn test_mixer_blocks_dc(){
+        // A channel resting at a constant level jumps the output, then fades to silence
+        let mut mixer = Mixer::new(44_100);
+        for _ in 0..CPU_CLOCK_HZ as u32 {
+            mixer.clock(0.5, 1);
+        }
+        let samples = mixer.take_samples();
+        assert!(samples[0] > 0.45);
+        assert!(samples.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(samples[samples.len() - 1].abs() < 0.001);
+    }
+
+    #[test]
+    fn test_mixer_averages_within_a_sample(){
+        // A tone far above the output rate comes out as its average level, give or take
+        // the odd cycle a sample's fractional share adds, which the high-pass then
+        // removes like any other constant
+        let mut mixer = Mixer::new(44_100);
+        for cycle in 0..CPU_CLOCK_HZ as u32 {
+            mixer.clock(if cycle & 1 == 0 { 1.0 } else { 0.0 }, 1);
+        }
+        let samples = mixer.take_samples();
+        assert!(samples[0] > 0.45 && samples[0] < 0.55);
+        assert!(samples[samples.len() - 1].abs() < 0.02);
+    }
+}
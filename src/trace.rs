@@ -0,0 +1,143 @@
+use crate::bus::Bus;
+use crate::cpu::{AddressingMode, CPU};
+use crate::opcodes::{self, Mnemonic, OpCode};
+
+/* Operand text for the instruction at `addr`, e.g. `($10),Y`.
+ * With `annotate` set the resolved address and the value it holds are appended the way
+ * nestest.log prints them, e.g. `($10),Y = 0300 @ 0305 = 7F`. */
+fn format_operand<B: Bus>(cpu: &CPU<B>, ops: &OpCode, addr: u16, annotate: bool) -> String {
+    let operand = addr.wrapping_add(1);
+    let lo = cpu.peek(operand);
+    let word = cpu.peek_u16(operand);
+
+    let text = match ops.mode {
+        AddressingMode::NoneAddressing => String::new(),
+        AddressingMode::Accumulator => String::from("A"),
+        AddressingMode::Immediate => format!("#${:02x}", lo),
+        AddressingMode::ZeroPage => format!("${:02x}", lo),
+        AddressingMode::ZeroPage_X => format!("${:02x},X", lo),
+        AddressingMode::ZeroPage_Y => format!("${:02x},Y", lo),
+        AddressingMode::Absolute => format!("${:04x}", word),
+        AddressingMode::Absolute_X => format!("${:04x},X", word),
+        AddressingMode::Absolute_Y => format!("${:04x},Y", word),
+        AddressingMode::Indirect_X => format!("(${:02x},X)", lo),
+        AddressingMode::Indirect_Y => format!("(${:02x}),Y", lo),
+        AddressingMode::Indirect => format!("(${:04x})", word),
+        AddressingMode::Relative => {
+            format!("${:04x}", cpu.get_absolute_address(&ops.mode, operand))
+        }
+        AddressingMode::ZeroPage_Indirect => format!("(${:02x})", lo),
+        AddressingMode::Absolute_Indirect_X => format!("(${:04x},X)", word),
+        AddressingMode::ZeroPage_Relative => {
+            let offset = operand.wrapping_add(1);
+            let target = cpu.get_absolute_address(&AddressingMode::Relative, offset);
+            format!("${:02x},${:04x}", lo, target)
+        }
+    };
+
+    let has_target = !matches!(
+        ops.mode,
+        AddressingMode::NoneAddressing
+            | AddressingMode::Accumulator
+            | AddressingMode::Immediate
+            | AddressingMode::Relative
+            | AddressingMode::ZeroPage_Relative
+    );
+    if !annotate || !has_target {
+        return text;
+    }
+
+    let target = cpu.get_absolute_address(&ops.mode, operand);
+    match ops.mode {
+        AddressingMode::ZeroPage => format!("{} = {:02x}", text, cpu.peek(target)),
+        AddressingMode::Absolute if is_jump(ops) => text,
+        AddressingMode::Absolute => format!("{} = {:02x}", text, cpu.peek(target)),
+        AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+            format!("{} @ {:02x} = {:02x}", text, target, cpu.peek(target))
+        }
+        AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+            format!("{} @ {:04x} = {:02x}", text, target, cpu.peek(target))
+        }
+        AddressingMode::Indirect_X => format!(
+            "{} @ {:02x} = {:04x} = {:02x}",
+            text,
+            lo.wrapping_add(cpu.register_x),
+            target,
+            cpu.peek(target)
+        ),
+        AddressingMode::Indirect_Y => format!(
+            "{} = {:04x} @ {:04x} = {:02x}",
+            text,
+            target.wrapping_sub(cpu.register_y as u16),
+            target,
+            cpu.peek(target)
+        ),
+        AddressingMode::Indirect | AddressingMode::Absolute_Indirect_X => {
+            format!("{} = {:04x}", text, target)
+        }
+        AddressingMode::ZeroPage_Indirect => {
+            format!("{} = {:04x} = {:02x}", text, target, cpu.peek(target))
+        }
+        _ => text,
+    }
+}
+
+fn is_jump(ops: &OpCode) -> bool {
+    matches!(ops.mnemonic, Mnemonic::JMP | Mnemonic::JSR)
+}
+
+// The 65C02 bit instructions carry the bit number in the name, e.g. `SMB3`
+fn mnemonic_text(ops: &OpCode) -> String {
+    match ops.mnemonic {
+        Mnemonic::RMB | Mnemonic::SMB | Mnemonic::BBR | Mnemonic::BBS => {
+            format!("{:?}{}", ops.mnemonic, (ops.code >> 4) & 0x07)
+        }
+        _ => format!("{:?}", ops.mnemonic),
+    }
+}
+
+/* Disassembles the instruction at `addr`, returning its text and length in bytes.
+ * Bytes that don't decode to a known opcode are shown as a `.db` directive. */
+pub fn disassemble<B: Bus>(cpu: &CPU<B>, addr: u16) -> (String, u8) {
+    let code = cpu.peek(addr);
+    match opcodes::opcode_table(cpu.variant).get(&code) {
+        Some(ops) => {
+            let operand = format_operand(cpu, ops, addr, false);
+            (format!("{} {}", mnemonic_text(ops), operand).trim_end().to_string(), ops.len)
+        }
+        None => (format!(".db ${:02x}", code), 1),
+    }
+}
+
+/* One line in the nestest.log format for the instruction the CPU is about to execute:
+ * C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD */
+pub fn trace<B: Bus>(cpu: &CPU<B>) -> String {
+    let begin = cpu.program_counter;
+    let code = cpu.peek(begin);
+
+    let (hex_dump, asm) = match opcodes::opcode_table(cpu.variant).get(&code) {
+        Some(ops) => {
+            let bytes: Vec<String> = (0..ops.len as u16)
+                .map(|i| format!("{:02x}", cpu.peek(begin.wrapping_add(i))))
+                .collect();
+            let operand = format_operand(cpu, ops, begin, true);
+            let marker = if ops.unofficial { '*' } else { ' ' };
+            (bytes.join(" "), format!("{}{} {}", marker, mnemonic_text(ops), operand))
+        }
+        None => (format!("{:02x}", code), format!(" .db ${:02x}", code)),
+    };
+
+    // nestest.log flags unofficial opcodes with a `*` in front of the mnemonic
+    format!(
+        "{:04x}  {:8} {:33}A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
+        begin,
+        hex_dump,
+        asm.trim_end(),
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status.bits(),
+        cpu.stack_pointer,
+    )
+    .to_ascii_uppercase()
+}